@@ -0,0 +1,65 @@
+use std::time::Duration;
+
+use mongodb::bson::DateTime;
+use rocket::{get, post, http::{ContentType, Header}, response::{self, Responder}, serde::json::Json, Request, Response, State};
+use serde::Serialize;
+use crate::models::ApiError;
+use crate::api::auth::Admin;
+use crate::database::{Database, AuditContext, DeleteResult, backup::{self, Manifest}};
+
+/// How long soft deleted documents are kept before `purge_deleted` removes them
+pub struct DeletedRetention(pub Duration);
+
+#[derive(Debug, Serialize)]
+pub struct PurgeResult {
+    pub students: DeleteResult,
+    pub teachers: DeleteResult,
+    pub devices: DeleteResult,
+    pub webhooks: DeleteResult,
+}
+
+/// Removes for good the students, teachers, devices and webhooks deleted longer ago than the retention period
+#[post("/admin/purge")]
+pub async fn purge_deleted(db: &State<Database>, _admin: Admin, audit: AuditContext, retention: &State<DeletedRetention>) -> Result<Json<PurgeResult>, ApiError> {
+    let now = DateTime::now().timestamp_millis();
+    let deleted_before = DateTime::from_millis(now.saturating_sub(retention.0.as_millis() as i64));
+
+    Ok(Json(PurgeResult {
+        students: db.audited(db.student_database.as_ref(), &audit).purge(deleted_before).await?,
+        teachers: db.audited(db.teacher_database.as_ref(), &audit).purge(deleted_before).await?,
+        devices: db.audited(db.device_database.as_ref(), &audit).purge(deleted_before).await?,
+        webhooks: db.audited(db.webhook_database.as_ref(), &audit).purge(deleted_before).await?,
+    }))
+}
+
+/// A backup archive, answered as a file to save
+pub struct Archive {
+    pub file_name: String,
+    pub bytes: Vec<u8>,
+}
+
+impl<'r> Responder<'r, 'static> for Archive {
+    fn respond_to(self, request: &'r Request<'_>) -> response::Result<'static> {
+        Response::build_from(self.bytes.respond_to(request)?)
+            .header(ContentType::new("application", "gzip"))
+            .header(Header::new("Content-Disposition", format!("attachment; filename=\"{}\"", self.file_name)))
+            .ok()
+    }
+}
+
+/// Every collection as a backup archive, see `backup::backup`
+#[get("/admin/backup")]
+pub async fn get_backup(db: &State<Database>, _admin: Admin) -> Result<Archive, ApiError> {
+    let (manifest, bytes) = backup::backup(db).await?;
+    Ok(Archive {
+        file_name: manifest.file_name(),
+        bytes
+    })
+}
+
+/// Loads the backup archive sent as the body into the empty database, see `backup::restore`.
+/// With `?dry_run=true` the archive is only checked.
+#[post("/admin/restore?<dry_run>", data = "<archive>")]
+pub async fn post_restore(db: &State<Database>, _admin: Admin, dry_run: Option<bool>, archive: Vec<u8>) -> Result<Json<Manifest>, ApiError> {
+    backup::restore(db, &archive, dry_run.unwrap_or(false)).await.map(Json)
+}
@@ -0,0 +1,123 @@
+use std::{collections::VecDeque, sync::{Arc, Mutex}, time::Duration};
+
+use mongodb::bson::oid::ObjectId;
+use rocket::{get, request::{FromRequest, Outcome, Request}, response::stream::{Event, EventStream}, Shutdown, State};
+use rocket::tokio::{select, sync::broadcast::{self, error::RecvError}};
+use crate::models::{Attendance, ApiError, utils::parse_oid};
+use crate::api::auth::Staff;
+
+/// How many check-ins a lagging subscriber may fall behind before it's dropped
+const CHANNEL_CAPACITY: usize = 256;
+/// How many of the latest check-ins are kept for clients reconnecting with `Last-Event-ID`
+const RECENT_EVENTS: usize = 1000;
+/// Proxies close connections that stay silent, a comment is sent this often to keep it open
+const HEARTBEAT: Duration = Duration::from_secs(15);
+
+/// One check-in as it's pushed to the stream, numbered in the order they happened
+#[derive(Debug, Clone)]
+pub struct FeedEvent {
+    pub id: u64,
+    pub attendance: Arc<Attendance>,
+}
+
+/// Every check-in made since the server started, handed to whoever listens.
+///
+/// The numbering starts over with the server, so does what a reconnecting client
+/// can catch up on: only the latest `RECENT_EVENTS` are kept, and only in memory.
+pub struct AttendanceFeed {
+    sender: broadcast::Sender<FeedEvent>,
+    recent: Mutex<VecDeque<FeedEvent>>,
+}
+
+impl Default for AttendanceFeed {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl AttendanceFeed {
+    pub fn new() -> Self {
+        Self {
+            sender: broadcast::channel(CHANNEL_CAPACITY).0,
+            recent: Mutex::new(VecDeque::new()),
+        }
+    }
+
+    /// Pushes a stored attendance to every listener
+    pub fn publish(&self, attendance: Attendance) {
+        // Numbered and sent under the lock, so listeners get them in the order of their id
+        let mut recent = self.recent.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        let event = FeedEvent {
+            id: recent.back().map(|event| event.id + 1).unwrap_or(1),
+            attendance: Arc::new(attendance),
+        };
+        if recent.len() == RECENT_EVENTS {
+            recent.pop_front();
+        }
+        recent.push_back(event.clone());
+        // Nobody listening isn't an error
+        self.sender.send(event).ok();
+    }
+
+    /// Starts listening, answering with the kept events after `last_event_id` as well.
+    /// An id the feed never handed out is from before a restart, so all of them are.
+    pub fn subscribe(&self, last_event_id: Option<u64>) -> (Vec<FeedEvent>, broadcast::Receiver<FeedEvent>) {
+        let recent = self.recent.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        let receiver = self.sender.subscribe();
+        let newest = recent.back().map(|event| event.id).unwrap_or(0);
+
+        let missed = match last_event_id {
+            Some(last_event_id) if last_event_id <= newest => recent.iter().filter(|event| event.id > last_event_id).cloned().collect(),
+            Some(_) => recent.iter().cloned().collect(),
+            None => Vec::new()
+        };
+        (missed, receiver)
+    }
+}
+
+/// The `Last-Event-ID` header an `EventSource` sends when it reconnects, ignored when it isn't one of ours
+pub struct LastEventId(pub Option<u64>);
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for LastEventId {
+    type Error = ApiError;
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let last_event_id = request.headers().get_one("Last-Event-ID").and_then(|id| id.trim().parse::<u64>().ok());
+        Outcome::Success(LastEventId(last_event_id))
+    }
+}
+
+fn to_event(event: &FeedEvent) -> Event {
+    Event::json(event.attendance.as_ref()).id(event.id.to_string())
+}
+
+/// Pushes every check-in as it happens, of one class or of all of them, as Server-Sent Events, to staff.
+/// Each event is the stored attendance, with an `id` to send back as `Last-Event-ID`
+/// to get the check-ins missed while disconnected.
+#[get("/attendance/stream?<class_id>")]
+pub fn get_attendance_stream(feed: &State<AttendanceFeed>, _staff: Staff, last_event_id: LastEventId, class_id: Option<String>, mut shutdown: Shutdown) -> Result<EventStream![], ApiError> {
+    let class_id: Option<ObjectId> = class_id.as_deref().map(parse_oid).transpose()?;
+    let of_class = move |event: &FeedEvent| class_id.is_none() || event.attendance.class_id == class_id;
+    let (missed, mut receiver) = feed.subscribe(last_event_id.0);
+
+    Ok(EventStream! {
+        for event in missed.iter().filter(|event| of_class(event)) {
+            yield to_event(event);
+        }
+
+        loop {
+            let event = select! {
+                event = receiver.recv() => match event {
+                    Ok(event) => event,
+                    // Ending the stream makes the client reconnect and catch up with Last-Event-ID
+                    Err(RecvError::Lagged(_)) | Err(RecvError::Closed) => break,
+                },
+                _ = &mut shutdown => break,
+            };
+            if of_class(&event) {
+                yield to_event(&event);
+            }
+        }
+    }.heartbeat(HEARTBEAT))
+}
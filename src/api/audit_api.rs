@@ -0,0 +1,62 @@
+use mongodb::bson::Document;
+use rocket::{get, serde::json::Json, State};
+use crate::models::{Attendance, AuditEntry, Device, Model, Student, Teacher, Webhook, utils::{parse_oid, parse_time}, ApiError};
+use crate::api::{auth::Admin, pagination::{ListParams, Paginated}};
+use crate::database::Database;
+
+/// The audit log for admins, newest first unless `sort` says otherwise.
+/// Every parameter given narrows it down, `from` and `to` are RFC 3339 times.
+#[get("/audit?<entity>&<entity_id>&<action>&<actor>&<actor_id>&<from>&<to>&<list..>")]
+#[allow(clippy::too_many_arguments)]
+pub async fn get_audit(
+    db: &State<Database>,
+    _admin: Admin,
+    entity: Option<String>,
+    entity_id: Option<String>,
+    action: Option<String>,
+    actor: Option<String>,
+    actor_id: Option<String>,
+    from: Option<String>,
+    to: Option<String>,
+    mut list: ListParams
+) -> Result<Json<Paginated<AuditEntry>>, ApiError> {
+    let mut filter = Document::new();
+
+    if let Some(entity) = entity {
+        let name = [Student::NAME, Teacher::NAME, Attendance::NAME, Device::NAME, Webhook::NAME]
+            .into_iter()
+            .find(|name| name.eq_ignore_ascii_case(&entity))
+            .ok_or_else(|| ApiError::BadParams(format!("{} isn't something the audit log records!", entity)))?;
+        filter.insert("entity", name);
+    }
+    if let Some(entity_id) = entity_id {
+        filter.insert("entity_id", parse_oid(&entity_id)?);
+    }
+    if let Some(action) = action {
+        filter.insert("action", action);
+    }
+    if let Some(actor) = actor {
+        filter.insert("actor", actor);
+    }
+    if let Some(actor_id) = actor_id {
+        filter.insert("actor_id", parse_oid(&actor_id)?);
+    }
+
+    let mut at = Document::new();
+    if let Some(from) = from {
+        at.insert("$gte", parse_time(&from, "from")?);
+    }
+    if let Some(to) = to {
+        at.insert("$lte", parse_time(&to, "to")?);
+    }
+    if !at.is_empty() {
+        filter.insert("at", at);
+    }
+
+    if list.sort.is_none() {
+        list.sort = Some(String::from("-at"));
+    }
+    let options = list.to_options::<AuditEntry>()?;
+    let entries = db.audit_database.list_page(filter, options.clone()).await?;
+    Ok(Json(Paginated::new(entries, &options)))
+}
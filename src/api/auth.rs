@@ -0,0 +1,165 @@
+use base64::{engine::general_purpose::STANDARD, Engine};
+use mongodb::bson::{doc, oid::ObjectId};
+use rocket::{http::Status, request::{FromRequest, Outcome, Request}, State};
+use crate::models::{ApiError, utils::not_deleted};
+use crate::database::{AuditContext, Database};
+
+/// The admin token from the config, `None` when admin requests are disabled
+pub struct AdminToken(pub Option<String>);
+
+/// Who is making a request, from its `Authorization` header: `Bearer` with the
+/// admin token, a device's or a student's, or `Basic` with a teacher's name and pass.
+///
+/// Requests without the header are anonymous, ones with wrong credentials are refused.
+#[derive(Debug, Clone)]
+pub enum Actor {
+    Admin,
+    Teacher(ObjectId),
+    Device(ObjectId),
+    Student(ObjectId),
+    Anonymous,
+}
+
+impl Actor {
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Actor::Admin => "admin",
+            Actor::Teacher(_) => "teacher",
+            Actor::Device(_) => "device",
+            Actor::Student(_) => "student",
+            Actor::Anonymous => "anonymous",
+        }
+    }
+
+    async fn from_authorization(request: &Request<'_>, authorization: &str) -> Result<Self, ApiError> {
+        let refused = || ApiError::Unauthorized(String::from("The credentials given aren't valid!"));
+
+        let database = || async {
+            request.guard::<&State<Database>>().await.succeeded()
+                .ok_or_else(|| ApiError::Database(String::from("The database isn't available!")))
+        };
+
+        if let Some(token) = authorization.strip_prefix("Bearer ") {
+            let token = token.trim();
+            let admin_token = request.guard::<&State<AdminToken>>().await.succeeded().and_then(|admin_token| admin_token.0.as_deref());
+            if admin_token == Some(token) {
+                return Ok(Actor::Admin);
+            }
+            let db = database().await?;
+            match db.device_database.get(not_deleted(doc! {"token": token})).await {
+                Ok(device) => return device.id.map(Actor::Device).ok_or_else(refused),
+                Err(ApiError::NotFound(_)) => {},
+                Err(err) => return Err(err)
+            };
+            return match db.student_database.get(not_deleted(doc! {"token": token})).await {
+                Ok(student) => student.id.map(Actor::Student).ok_or_else(refused),
+                Err(ApiError::NotFound(_)) => Err(refused()),
+                Err(err) => Err(err)
+            };
+        }
+
+        let credentials = authorization
+            .strip_prefix("Basic ")
+            .and_then(|encoded| STANDARD.decode(encoded.trim()).ok())
+            .and_then(|decoded| String::from_utf8(decoded).ok())
+            .ok_or_else(refused)?;
+        let (name, pass) = credentials.split_once(':').ok_or_else(refused)?;
+
+        // Only the stored hash can tell the pass, so it's checked here rather than in the filter
+        let teachers = database().await?.teacher_database.list(not_deleted(doc! {"name": name})).await?;
+        teachers.into_iter()
+            .find(|teacher| teacher.verify_pass(pass))
+            .and_then(|teacher| teacher.id)
+            .map(Actor::Teacher)
+            .ok_or_else(refused)
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for Actor {
+    type Error = ApiError;
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        // Worked out once per request, however many guards ask for it
+        let actor = request.local_cache_async(async {
+            match request.headers().get_one("Authorization") {
+                Some(authorization) => Actor::from_authorization(request, authorization).await.map_err(|err| err.to_string()),
+                None => Ok(Actor::Anonymous)
+            }
+        }).await;
+
+        match actor {
+            Ok(actor) => Outcome::Success(actor.clone()),
+            Err(message) => Outcome::Error((Status::Unauthorized, ApiError::Unauthorized(message.clone())))
+        }
+    }
+}
+
+/// Only lets admins through, see `Actor`
+pub struct Admin;
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for Admin {
+    type Error = ApiError;
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        match request.guard::<Actor>().await {
+            Outcome::Success(Actor::Admin) => Outcome::Success(Admin),
+            Outcome::Success(Actor::Anonymous) => Outcome::Error((Status::Unauthorized, ApiError::Unauthorized(String::from("Only admins can do this!")))),
+            Outcome::Success(_) => Outcome::Error((Status::Forbidden, ApiError::Forbidden(String::from("Only admins can do this!")))),
+            Outcome::Error(error) => Outcome::Error(error),
+            Outcome::Forward(status) => Outcome::Forward(status)
+        }
+    }
+}
+
+/// Only lets teachers and admins through, see `Actor`
+pub struct Staff(pub Actor);
+
+impl Staff {
+    /// Admins change any teacher, a teacher only themselves, e.g. their own pass
+    pub fn may_change_teacher(&self, teacher_id: ObjectId) -> Result<(), ApiError> {
+        match self.0 {
+            Actor::Teacher(id) if id != teacher_id => Err(ApiError::Forbidden(String::from("Teachers can only change themselves!"))),
+            _ => Ok(())
+        }
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for Staff {
+    type Error = ApiError;
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        match request.guard::<Actor>().await {
+            Outcome::Success(actor @ (Actor::Admin | Actor::Teacher(_))) => Outcome::Success(Staff(actor)),
+            Outcome::Success(Actor::Anonymous) => Outcome::Error((Status::Unauthorized, ApiError::Unauthorized(String::from("Only teachers and admins can do this!")))),
+            Outcome::Success(_) => Outcome::Error((Status::Forbidden, ApiError::Forbidden(String::from("Only teachers and admins can do this!")))),
+            Outcome::Error(error) => Outcome::Error(error),
+            Outcome::Forward(status) => Outcome::Forward(status)
+        }
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for AuditContext {
+    type Error = ApiError;
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let actor = match request.guard::<Actor>().await {
+            Outcome::Success(actor) => actor,
+            Outcome::Error(error) => return Outcome::Error(error),
+            Outcome::Forward(status) => return Outcome::Forward(status)
+        };
+
+        Outcome::Success(AuditContext {
+            actor: actor.kind().to_string(),
+            actor_id: match actor {
+                Actor::Teacher(id) | Actor::Device(id) | Actor::Student(id) => Some(id),
+                _ => None
+            },
+            route: format!("{} {}", request.method(), request.uri()),
+            client_ip: request.client_ip().map(|ip| ip.to_string()),
+        })
+    }
+}
@@ -0,0 +1,46 @@
+use mongodb::bson::Document;
+use crate::models::{ApiError, Model, utils::not_deleted};
+use crate::api::versioning::IfMatch;
+use crate::database::{Repository, model_to_document};
+
+/// The filter of a bulk change given as a model, the fields set on `body`.
+/// Only `M::FILTER_FIELDS` may be, like with `utils::params_to_filter`.
+pub fn body_to_filter<M: Model>(body: &M) -> Result<Document, ApiError> {
+    let filter = model_to_document(body)?;
+    if let Some(field) = filter.keys().find(|field| !M::FILTER_FIELDS.contains(&field.as_str())) {
+        return Err(ApiError::BadParams(format!("{} can't be filtered on {}!", M::NAME, field)));
+    }
+    Ok(filter)
+}
+
+/// Counts what an update or delete through `filter` would touch before it's run.
+///
+/// An empty filter matches the whole collection, so it's always refused. More than
+/// one match is only allowed with `confirm`, otherwise the count is reported back.
+/// Soft deleted documents don't count.
+pub async fn check_matches<T: Model>(repository: &dyn Repository<T>, filter: &Document, confirm: bool) -> Result<u64, ApiError> {
+    if filter.is_empty() {
+        return Err(ApiError::BadParams(format!("Give at least one field to pick the {} by!", T::NAME.to_lowercase())));
+    }
+
+    let matched_count = repository.count(not_deleted(filter.clone())).await?;
+    if matched_count > 1 && !confirm {
+        return Err(ApiError::ConfirmationRequired(format!(
+            "{} {}s match, send confirm=true to change all of them!", matched_count, T::NAME.to_lowercase()
+        )));
+    }
+    Ok(matched_count)
+}
+
+/// Narrows the filter of a bulk change by `if_match`, like a change of one document.
+/// Unless it's `*`, every one of the `matched_count` documents `filter` matches has to
+/// still be at the version given, changing only the ones that are would mix old and new.
+pub async fn narrow_all<T: Model>(repository: &dyn Repository<T>, filter: Document, if_match: &IfMatch, matched_count: u64) -> Result<Document, ApiError> {
+    let narrowed = if_match.narrow(filter.clone())?;
+    if narrowed != filter && repository.count(not_deleted(narrowed.clone())).await? < matched_count {
+        return Err(ApiError::PreconditionFailed(format!(
+            "Some of the {}s were changed since, get them again before changing them!", T::NAME.to_lowercase()
+        )));
+    }
+    Ok(narrowed)
+}
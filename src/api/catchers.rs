@@ -0,0 +1,16 @@
+use rocket::{catch, http::Status, serde::json::Json, Request};
+use crate::models::{ApiError, ErrorResponse};
+
+/// Answers requests that never reached a route (unknown path, body that isn't
+/// valid JSON, ...) with the same JSON shape as every other error.
+#[catch(default)]
+pub fn default_catcher(status: Status, _request: &Request) -> Json<ErrorResponse> {
+    let error = match status.code {
+        401 => ApiError::Unauthorized(String::from("Give a teacher's name and pass (Basic), or the admin token, a device's or a student's (Bearer) as Authorization!")),
+        403 => ApiError::Forbidden(String::from("You aren't allowed to do this!")),
+        404 => ApiError::NotFound(String::from("There's nothing here!")),
+        400..=499 => ApiError::BadParams(format!("The request couldn't be understood: {}", status)),
+        _ => ApiError::Database(format!("Unexpected error has occured: {}", status)),
+    };
+    Json(ErrorResponse::from(&error))
+}
@@ -0,0 +1,91 @@
+use mongodb::bson::{doc, DateTime};
+use rocket::{get, post, serde::json::Json, State};
+use serde::{Deserialize, Serialize};
+use crate::models::{Attendance, Student, validation::validate, utils::{string_to_oid, not_deleted}, ApiError};
+use crate::database::{Database, AuditContext, Repository, InsertResult};
+use crate::api::{auth::{Actor, Staff}, utils::QrSigner, attendance_api::AttendanceFeed, webhook_delivery::{Webhooks, CHECKED_IN}};
+
+#[derive(Serialize, Deserialize)]
+pub struct QrCode {
+    payload: String,
+    expires_at: u64,
+}
+
+/// Generates the payload the teacher displays as a QR code for a lesson.
+/// The payload is only valid for a short while, so the display should refresh it.
+#[get("/checkin/qr?<class_id>&<lesson>")]
+pub fn get_checkin_qr(qr_signer: &State<QrSigner>, _staff: Staff, class_id: Option<String>, lesson: Option<String>) -> Result<Json<QrCode>, ApiError> {
+    let class_id = class_id.as_deref().and_then(string_to_oid);
+    let lesson = lesson.filter(|lesson| !lesson.trim().is_empty());
+
+    match (class_id, lesson) {
+        (Some(class_id), Some(lesson)) => {
+            let (payload, claims) = qr_signer.sign(class_id, lesson);
+            Ok(Json(QrCode { payload, expires_at: claims.expires_at }))
+        },
+        _ => Err(ApiError::wrong_params())
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct QrCheckin {
+    payload: String,
+}
+
+/// Records the attendance of the student who scanned the lesson's QR code, signed in
+/// with their token (see `POST /student/<id>/token`), and pushes it to the attendance stream
+#[post("/checkin/qr", data = "<checkin>")]
+pub async fn post_checkin_qr(db: &State<Database>, actor: Actor, audit: AuditContext, qr_signer: &State<QrSigner>, feed: &State<AttendanceFeed>, webhooks: &State<Webhooks>, checkin: Json<QrCheckin>) -> Result<Json<InsertResult>, ApiError> {
+    let student_id = match actor {
+        Actor::Student(student_id) => student_id,
+        Actor::Anonymous => return Err(ApiError::Unauthorized(String::from("Only students can check in, with their token as Bearer!"))),
+        _ => return Err(ApiError::Forbidden(String::from("Only students can check in, with their token as Bearer!")))
+    };
+    let claims = match qr_signer.verify(&checkin.payload) {
+        Ok(claims) => claims,
+        Err(err) => return Err(ApiError::QrPayload(err))
+    };
+
+    let student = db.student_database.get(not_deleted(doc! {"_id": student_id})).await?;
+    let attendance = Attendance {
+        class_id: Some(claims.class_id),
+        lesson: Some(claims.lesson),
+        method: Some(String::from("qr")),
+        ..Attendance::default()
+    };
+    check_in(db, &audit, feed, webhooks, &student, attendance).await.map(Json)
+}
+
+/// Records that `student` attended as `attendance` says: its class, lesson and method, and
+/// the device and tap of a card. Pushes the attendance to the attendance stream, and logs
+/// an `attendance.checked_in` delivery for each webhook subscribed to it.
+pub async fn check_in(db: &Database, audit: &AuditContext, feed: &AttendanceFeed, webhooks: &Webhooks, student: &Student, attendance: Attendance) -> Result<InsertResult, ApiError> {
+    if attendance.class_id.is_none() || student.class_id != attendance.class_id {
+        return Err(ApiError::Checkin(String::from("Student doesn't belong to the lesson's class!")));
+    }
+
+    let attendance = validate(Attendance {
+        id: None,
+        student_id: student.id,
+        checked_in_at: Some(DateTime::now()),
+        version: None,
+        ..attendance
+    }, true)?;
+
+    // Refused by the storage itself, see `Attendance::UNIQUE_KEYS`
+    let result = match db.audited(db.attendance_database.as_ref(), audit).insert(&attendance).await {
+        Ok(result) => result,
+        Err(ApiError::Conflict(_)) => return Err(ApiError::Conflict(String::from("Student has already checked in to this lesson!"))),
+        Err(err) => return Err(err)
+    };
+    let attendance = Attendance {
+        id: result.inserted_id.as_object_id(),
+        ..attendance
+    };
+    // The check-in is stored already, refusing it now would only have it sent again
+    if let Err(err) = webhooks.publish(CHECKED_IN, &attendance).await {
+        eprintln!("Can't send the check-in {} to the webhooks, {}", result.inserted_id, err.message());
+    }
+    feed.publish(attendance);
+    Ok(result)
+}
@@ -0,0 +1,139 @@
+use mongodb::bson::{doc, oid::ObjectId, Bson};
+use rocket::{get, post, patch, delete, serde::json::Json, Shutdown, State};
+use rocket::tokio::sync::broadcast;
+use serde::Serialize;
+use serde_json::{Map, Value};
+use crate::models::{Device, validation::validate, utils::{not_deleted, parse_oid}, ApiError};
+use crate::api::{auth::{Actor, Admin}, attendance_api::AttendanceFeed, device_channel::{self, Connection}, pagination::{ListParams, Paginated}, utils::new_token, versioning::{IfMatch, Versioned, missed}, webhook_delivery::Webhooks, websocket::{Channel, WebSocket}};
+use crate::database::{Database, AuditContext, Repository, Patch, DeleteResult};
+
+/// How many changes an open channel may fall behind before it reads its device again
+const NOTICE_CAPACITY: usize = 64;
+
+/// A change made to a device through the API, for its open channels to pass on
+#[derive(Debug, Clone)]
+pub struct DeviceNotice {
+    pub device_id: ObjectId,
+    /// The device as it's now, `None` once it's deleted
+    pub device: Option<Device>,
+}
+
+/// Tells the open device channels about changes made to their device
+pub struct DeviceHub {
+    sender: broadcast::Sender<DeviceNotice>,
+}
+
+impl Default for DeviceHub {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl DeviceHub {
+    pub fn new() -> Self {
+        Self {
+            sender: broadcast::channel(NOTICE_CAPACITY).0
+        }
+    }
+
+    pub fn notify(&self, device_id: ObjectId, device: Option<Device>) {
+        // No channel open isn't an error
+        self.sender.send(DeviceNotice { device_id, device }).ok();
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<DeviceNotice> {
+        self.sender.subscribe()
+    }
+}
+
+#[get("/device?<list..>")]
+pub async fn get_all_devices(db: &State<Database>, _admin: Admin, list: ListParams) -> Result<Json<Paginated<Device>>, ApiError> {
+    let options = list.to_options::<Device>()?;
+    let devices_data = db.device_database.list_page(not_deleted(doc! {}), options.clone()).await?;
+    Ok(Json(Paginated::new(devices_data, &options)))
+}
+
+#[get("/device/<id>")]
+pub async fn get_device_by_id(db: &State<Database>, _admin: Admin, id: &str) -> Result<Versioned<Device>, ApiError> {
+    let id = parse_oid(id)?;
+    let device = db.device_database.get(not_deleted(doc! {"_id": id})).await?;
+    Ok(Versioned::new(device.version, device))
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DeviceCreated {
+    pub inserted_id: Bson,
+    /// What the device connects with, it can't be read again later
+    pub token: String,
+}
+
+/// Adds a device, answering the token it has to be set up with
+#[post("/device", data = "<new_device>")]
+pub async fn post_device(db: &State<Database>, _admin: Admin, audit: AuditContext, new_device: Json<Device>) -> Result<Json<DeviceCreated>, ApiError> {
+    let token = new_token();
+    let new_device_data = Device {
+        token: Some(token.clone()),
+        ..validate(new_device.0, true)?
+    };
+    let result = db.audited(db.device_database.as_ref(), &audit).insert(&new_device_data).await?;
+    Ok(Json(DeviceCreated {
+        inserted_id: result.inserted_id,
+        token
+    }))
+}
+
+/// Applies a JSON merge patch, see `patch_student_by_id`. An open channel
+/// of the device is sent the new configuration right away.
+#[patch("/device/<id>", data = "<patch>")]
+pub async fn patch_device_by_id(db: &State<Database>, hub: &State<DeviceHub>, _admin: Admin, audit: AuditContext, if_match: IfMatch, id: &str, patch: Json<Map<String, Value>>) -> Result<Versioned<Device>, ApiError> {
+    let id = parse_oid(id)?;
+    let patch = Patch::from_json::<Device>(patch.0)?;
+    let filter = if_match.narrow(not_deleted(doc! {"_id": id}))?;
+
+    let result = db.audited(db.device_database.as_ref(), &audit).patch(filter, &patch).await?;
+    if result.matched_count == 0 {
+        return Err(missed(db.device_database.as_ref(), id).await);
+    }
+    let device = db.device_database.get(doc! {"_id": id}).await?;
+    hub.notify(id, Some(device.clone()));
+    Ok(Versioned::new(device.version, device))
+}
+
+/// Soft deletes the device, its token stops working and an open channel is closed
+#[delete("/device/<id>")]
+pub async fn delete_device_by_id(db: &State<Database>, hub: &State<DeviceHub>, _admin: Admin, audit: AuditContext, id: &str) -> Result<Json<DeleteResult>, ApiError> {
+    let id = parse_oid(id)?;
+    let result = db.audited(db.device_database.as_ref(), &audit).soft_delete(doc! {"_id": id}).await?;
+    if result.matched_count == 0 {
+        return Err(ApiError::NotFound(String::from("Device's not found!")));
+    }
+    hub.notify(id, None);
+    Ok(Json(DeleteResult {
+        deleted_count: result.modified_count
+    }))
+}
+
+/// The WebSocket a device keeps open to check students in, see `device_channel`.
+/// The device connects with its token as `Authorization: Bearer <token>`.
+#[get("/device/channel")]
+#[allow(clippy::too_many_arguments)]
+pub async fn get_device_channel<'r>(db: &'r State<Database>, feed: &'r State<AttendanceFeed>, webhooks: &'r State<Webhooks>, hub: &'r State<DeviceHub>, actor: Actor, audit: AuditContext, socket: WebSocket, shutdown: Shutdown) -> Result<Channel<'r>, ApiError> {
+    let device_id = match actor {
+        Actor::Device(device_id) => device_id,
+        Actor::Anonymous => return Err(ApiError::Unauthorized(String::from("Only devices can connect, with their token as Bearer!"))),
+        _ => return Err(ApiError::Forbidden(String::from("Only devices can connect, with their token as Bearer!")))
+    };
+
+    // Listening before the device is read, so no change made in between is missed
+    let notices = hub.subscribe();
+    let device = db.device_database.get(not_deleted(doc! {"_id": device_id})).await?;
+    let connection = Connection {
+        db: db.inner(),
+        feed: feed.inner(),
+        webhooks: webhooks.inner(),
+        audit,
+        device
+    };
+    Ok(socket.channel(move |socket| Box::pin(device_channel::serve(socket, connection, notices, shutdown))))
+}
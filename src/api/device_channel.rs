@@ -0,0 +1,242 @@
+use std::time::Duration;
+
+use futures::{SinkExt, StreamExt};
+use mongodb::bson::{doc, DateTime};
+use rocket::Shutdown;
+use rocket::tokio::{select, sync::broadcast::{self, error::RecvError}, time::{interval_at, Instant}};
+use serde::{Deserialize, Serialize};
+use tokio_tungstenite::tungstenite::{protocol::{frame::coding::CloseCode, CloseFrame}, Message};
+use crate::models::{ApiError, Attendance, Device, Student, utils::not_deleted, validation::normalized};
+use crate::api::{attendance_api::AttendanceFeed, checkin_api::check_in, device_api::DeviceNotice, webhook_delivery::Webhooks, websocket::Socket};
+use crate::database::{AuditContext, Database};
+
+/// How often the server pings a quiet device, so a dead connection is noticed
+const HEARTBEAT: Duration = Duration::from_secs(30);
+/// The `debounce_seconds` of a device that has none set
+pub const DEFAULT_DEBOUNCE_SECONDS: i64 = 3;
+
+/// What a device sends, as JSON text messages tagged by `type`
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum DeviceMessage {
+    /// A card held to the reader during `lesson`. `tap_id` is made up by the device and
+    /// comes back in the `tap_ack`; a tap sent again after a lost connection is answered as
+    /// it was the first time it was accepted, without checking the student in twice.
+    Tap {
+        tap_id: String,
+        card_id: String,
+        lesson: String,
+    },
+    /// Asks for the server's clock, `sent_at` is the device's own in Unix milliseconds
+    TimeSync {
+        sent_at: i64,
+    },
+}
+
+/// What the server sends, as JSON text messages tagged by `type`
+#[derive(Debug, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ServerMessage {
+    /// What the device has to apply, sent once it connects and again whenever it's changed
+    Config {
+        /// The device's version, moving up with every change
+        version: i64,
+        debounce_seconds: i64,
+        display_message: Option<String>,
+        blocked_cards: Vec<String>,
+    },
+    /// Whether a tap checked the student in. A refused tap has the `error_code` and
+    /// `message` the HTTP API answers with, e.g. 6 when the student already checked in.
+    TapAck {
+        tap_id: String,
+        accepted: bool,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        student_name: Option<String>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        error_code: Option<u16>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        message: Option<String>,
+    },
+    /// The answer to `time_sync`, in Unix milliseconds. The device's clock is off by
+    /// `server_time` minus the middle of `sent_at` and when the answer arrived.
+    Time {
+        sent_at: i64,
+        server_time: i64,
+    },
+    /// A message that couldn't be read, the channel stays open
+    Error {
+        message: String,
+    },
+}
+
+impl ServerMessage {
+    fn config(device: &Device) -> Self {
+        ServerMessage::Config {
+            version: device.version.unwrap_or(0),
+            debounce_seconds: device.debounce_seconds.unwrap_or(DEFAULT_DEBOUNCE_SECONDS),
+            display_message: device.display_message.clone(),
+            blocked_cards: device.blocked_cards.clone().unwrap_or_default(),
+        }
+    }
+}
+
+/// Everything an open channel works with
+pub struct Connection<'a> {
+    pub db: &'a Database,
+    pub feed: &'a AttendanceFeed,
+    pub webhooks: &'a Webhooks,
+    /// Taps are audited as the device's, under the request that opened the channel
+    pub audit: AuditContext,
+    /// Kept up to date with the changes made while the channel is open
+    pub device: Device,
+}
+
+impl Connection<'_> {
+    /// The student checked in by an earlier tap of this device with `tap_id`
+    async fn tapped(&self, tap_id: &str) -> Result<Option<Student>, ApiError> {
+        let attendance = match self.db.attendance_database.get(doc! {"device_id": self.device.id, "tap_id": tap_id}).await {
+            Ok(attendance) => attendance,
+            Err(ApiError::NotFound(_)) => return Ok(None),
+            Err(err) => return Err(err)
+        };
+        self.db.student_database.get(doc! {"_id": attendance.student_id}).await.map(Some)
+    }
+
+    async fn tap(&self, tap_id: String, card_id: &str, lesson: String) -> Result<Student, ApiError> {
+        if let Some(student) = self.tapped(&tap_id).await? {
+            return Ok(student);
+        }
+
+        let card_id = normalized::<Student>("card_id", card_id);
+        if self.device.blocked_cards.iter().flatten().any(|blocked| *blocked == card_id) {
+            return Err(ApiError::Checkin(String::from("This card is blocked!")));
+        }
+        let class_id = self.device.class_id.ok_or_else(|| ApiError::Checkin(String::from("The device isn't in a class!")))?;
+
+        let student = match self.db.student_database.get(not_deleted(doc! {"card_id": card_id})).await {
+            Ok(student) => student,
+            Err(ApiError::NotFound(_)) => return Err(ApiError::NotFound(String::from("No student has this card!"))),
+            Err(err) => return Err(err)
+        };
+        let attendance = Attendance {
+            class_id: Some(class_id),
+            lesson: Some(lesson),
+            method: Some(String::from("card")),
+            device_id: self.device.id,
+            tap_id: Some(tap_id.clone()),
+            ..Attendance::default()
+        };
+        match check_in(self.db, &self.audit, self.feed, self.webhooks, &student, attendance).await {
+            Ok(_) => Ok(student),
+            // The same tap sent again while the first one was being recorded
+            Err(ApiError::Conflict(message)) => match self.tapped(&tap_id).await? {
+                Some(student) => Ok(student),
+                None => Err(ApiError::Conflict(message))
+            },
+            Err(err) => Err(err)
+        }
+    }
+
+    /// The reply to one text message of the device
+    pub async fn answer(&self, text: &str) -> ServerMessage {
+        let message = match serde_json::from_str::<DeviceMessage>(text) {
+            Ok(message) => message,
+            Err(err) => return ServerMessage::Error { message: format!("The message can't be read: {}", err) }
+        };
+
+        match message {
+            DeviceMessage::Tap { tap_id, card_id, lesson } => match self.tap(tap_id.clone(), &card_id, lesson).await {
+                Ok(student) => ServerMessage::TapAck {
+                    tap_id,
+                    accepted: true,
+                    student_name: student.name,
+                    error_code: None,
+                    message: None,
+                },
+                Err(err) => ServerMessage::TapAck {
+                    tap_id,
+                    accepted: false,
+                    student_name: None,
+                    error_code: Some(err.code()),
+                    message: Some(err.message().to_string()),
+                }
+            },
+            DeviceMessage::TimeSync { sent_at } => ServerMessage::Time {
+                sent_at,
+                server_time: DateTime::now().timestamp_millis(),
+            },
+        }
+    }
+}
+
+async fn send(socket: &mut Socket, message: &ServerMessage) -> bool {
+    match serde_json::to_string(message) {
+        Ok(json) => socket.send(Message::Text(json)).await.is_ok(),
+        Err(_) => false
+    }
+}
+
+async fn close(socket: &mut Socket, code: CloseCode, reason: &str) {
+    let frame = CloseFrame {
+        code,
+        reason: reason.to_string().into()
+    };
+    socket.close(Some(frame)).await.ok();
+}
+
+/// Runs the channel of a device until either side closes it: answers its messages
+/// one at a time, in order, and pushes its configuration whenever it changes.
+pub async fn serve(mut socket: Socket, mut connection: Connection<'_>, mut notices: broadcast::Receiver<DeviceNotice>, mut shutdown: Shutdown) {
+    let device_id = connection.device.id;
+    if !send(&mut socket, &ServerMessage::config(&connection.device)).await {
+        return;
+    }
+
+    let mut heartbeat = interval_at(Instant::now() + HEARTBEAT, HEARTBEAT);
+    loop {
+        select! {
+            message = socket.next() => {
+                let reply = match message {
+                    Some(Ok(Message::Text(text))) => connection.answer(&text).await,
+                    Some(Ok(Message::Binary(_))) => ServerMessage::Error { message: String::from("Messages have to be JSON text!") },
+                    // Pings are answered by the socket itself
+                    Some(Ok(Message::Ping(_))) | Some(Ok(Message::Pong(_))) | Some(Ok(Message::Frame(_))) => continue,
+                    Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                };
+                if !send(&mut socket, &reply).await {
+                    break;
+                }
+            },
+            notice = notices.recv() => {
+                let device = match notice {
+                    Ok(notice) if Some(notice.device_id) == device_id => notice.device,
+                    Ok(_) => continue,
+                    // Changes were missed, the device as it's now is all that matters
+                    Err(RecvError::Lagged(_)) => connection.db.device_database.get(not_deleted(doc! {"_id": device_id})).await.ok(),
+                    Err(RecvError::Closed) => break,
+                };
+                match device {
+                    Some(device) => {
+                        connection.device = device;
+                        if !send(&mut socket, &ServerMessage::config(&connection.device)).await {
+                            break;
+                        }
+                    },
+                    None => {
+                        close(&mut socket, CloseCode::Policy, "The device was deleted").await;
+                        break;
+                    }
+                }
+            },
+            _ = heartbeat.tick() => {
+                if socket.send(Message::Ping(Vec::new())).await.is_err() {
+                    break;
+                }
+            },
+            _ = &mut shutdown => {
+                close(&mut socket, CloseCode::Away, "The server is shutting down").await;
+                break;
+            },
+        }
+    }
+}
@@ -0,0 +1,93 @@
+use std::time::Duration;
+
+use mongodb::bson::{doc, oid::ObjectId, DateTime};
+use rocket::{post, serde::json::Json, State};
+use serde::{Deserialize, Serialize};
+use crate::models::{Attendance, ApiError, utils::{not_deleted, parse_oid}};
+use crate::api::{auth::Staff, webhook_delivery::{Webhooks, LATE, ABSENT}};
+use crate::database::Database;
+
+/// How long after the start of a lesson a check-in still isn't late
+pub struct LateAfter(pub Duration);
+
+#[derive(Serialize, Deserialize)]
+pub struct LessonClose {
+    class_id: String,
+    lesson: String,
+    /// When the lesson started, in RFC 3339 (e.g. `2024-01-15T08:00:00+07:00`)
+    started_at: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct LessonSummary {
+    pub present: u64,
+    pub late: u64,
+    pub absent: u64,
+    /// How many webhook deliveries the late arrivals and absences were queued as
+    pub deliveries: u64,
+}
+
+/// The data of an `attendance.late` event
+#[derive(Serialize)]
+struct LateArrival<'a> {
+    #[serde(flatten)]
+    attendance: &'a Attendance,
+    student_name: Option<String>,
+    minutes_late: i64,
+}
+
+/// The data of an `attendance.absent` event
+#[derive(Serialize)]
+struct Absence<'a> {
+    student_id: Option<ObjectId>,
+    student_name: Option<String>,
+    class_id: ObjectId,
+    lesson: &'a str,
+    started_at: &'a str,
+}
+
+/// Counts who of the class attended the lesson, who came late and who never checked in,
+/// sending an `attendance.late` or `attendance.absent` event to the webhooks for each of
+/// the latter. Nothing is stored, so closing a lesson twice sends the events twice.
+#[post("/lesson/close", data = "<close>")]
+pub async fn post_lesson_close(db: &State<Database>, webhooks: &State<Webhooks>, late_after: &State<LateAfter>, _staff: Staff, close: Json<LessonClose>) -> Result<Json<LessonSummary>, ApiError> {
+    let class_id = parse_oid(&close.class_id)?;
+    let lesson = close.lesson.trim();
+    if lesson.is_empty() {
+        return Err(ApiError::wrong_params());
+    }
+    let started_at = DateTime::parse_rfc3339_str(close.started_at.trim())
+        .map_err(|_| ApiError::BadParams(String::from("started_at has to be an RFC 3339 time, e.g. 2024-01-15T08:00:00Z!")))?;
+    let late_from = started_at.timestamp_millis().saturating_add(late_after.0.as_millis() as i64);
+
+    let students = db.student_database.list(not_deleted(doc! {"class_id": class_id})).await?;
+    let attendances = db.attendance_database.list(doc! {"class_id": class_id, "lesson": lesson}).await?;
+
+    let mut summary = LessonSummary { present: 0, late: 0, absent: 0, deliveries: 0 };
+    for student in students {
+        let attendance = attendances.iter().find(|attendance| attendance.student_id.is_some() && attendance.student_id == student.id);
+        let checked_in_at = attendance.and_then(|attendance| attendance.checked_in_at).map(|at| at.timestamp_millis());
+        match (attendance, checked_in_at) {
+            (Some(attendance), Some(checked_in_at)) if checked_in_at > late_from => {
+                summary.late += 1;
+                summary.deliveries += webhooks.publish(LATE, &LateArrival {
+                    attendance,
+                    student_name: student.name,
+                    minutes_late: (checked_in_at - started_at.timestamp_millis()) / 60_000,
+                }).await?;
+            },
+            (Some(_), _) => summary.present += 1,
+            (None, _) => {
+                summary.absent += 1;
+                summary.deliveries += webhooks.publish(ABSENT, &Absence {
+                    student_id: student.id,
+                    student_name: student.name,
+                    class_id,
+                    lesson,
+                    started_at: close.started_at.trim(),
+                }).await?;
+            }
+        }
+    }
+    Ok(Json(summary))
+}
@@ -1,3 +1,18 @@
 pub mod student_api;
 pub mod teacher_api;
+pub mod checkin_api;
+pub mod attendance_api;
+pub mod device_api;
+pub mod device_channel;
+pub mod websocket;
+pub mod webhook_api;
+pub mod webhook_delivery;
+pub mod lesson_api;
+pub mod catchers;
+pub mod pagination;
+pub mod bulk;
+pub mod auth;
+pub mod audit_api;
+pub mod admin_api;
+pub mod versioning;
 pub mod utils;
\ No newline at end of file
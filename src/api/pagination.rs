@@ -0,0 +1,135 @@
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use mongodb::bson::Document;
+use rocket::FromForm;
+use serde::{ser::Error, Serialize, Serializer};
+use crate::models::{ApiError, Model, utils::public_json};
+use crate::database::{ListOptions, Page};
+
+/// How many items a list endpoint returns when `limit` isn't given
+pub const DEFAULT_LIMIT: u64 = 50;
+/// The most items a list endpoint returns at once
+pub const MAX_LIMIT: u64 = 500;
+
+/// Query parameters every list endpoint understands:
+/// `?limit=20&cursor=<next_cursor>&sort=-name,_id&fields=name,card_id`
+///
+/// `page` (starting at 1) can be used instead of `cursor` to jump to a page.
+#[derive(Debug, Default, FromForm)]
+pub struct ListParams {
+    pub limit: Option<u64>,
+    pub cursor: Option<String>,
+    pub page: Option<u64>,
+    /// Comma separated fields, a leading `-` sorts that field descending
+    pub sort: Option<String>,
+    /// Comma separated fields to return, `_id` always is
+    pub fields: Option<String>,
+}
+
+fn encode_cursor(skip: u64) -> String {
+    URL_SAFE_NO_PAD.encode(skip.to_string())
+}
+
+fn decode_cursor(cursor: &str) -> Result<u64, ApiError> {
+    URL_SAFE_NO_PAD
+        .decode(cursor)
+        .ok()
+        .and_then(|bytes| String::from_utf8(bytes).ok())
+        .and_then(|skip| skip.parse::<u64>().ok())
+        .ok_or_else(|| ApiError::BadParams(String::from("The cursor isn't one this API handed out!")))
+}
+
+/// Splits a comma separated list of fields, refusing the ones `M` doesn't have.
+/// Secret fields can't be used either, their order would give them away.
+fn model_fields<M: Model>(fields: &str, parameter: &str) -> Result<Vec<String>, ApiError> {
+    let mut result: Vec<String> = Vec::new();
+    for field in fields.split(',').map(str::trim).filter(|field| !field.is_empty()) {
+        let name = field.strip_prefix('-').unwrap_or(field);
+        if !M::FIELDS.contains(&name) || M::SECRET_FIELDS.contains(&name) {
+            return Err(ApiError::BadParams(format!("{} has no field {} to use in {}!", M::NAME, name, parameter)));
+        }
+        result.push(field.to_string());
+    }
+    Ok(result)
+}
+
+impl ListParams {
+    pub fn to_options<M: Model>(&self) -> Result<ListOptions, ApiError> {
+        let limit = self.limit.unwrap_or(DEFAULT_LIMIT);
+        if limit == 0 || limit > MAX_LIMIT {
+            return Err(ApiError::BadParams(format!("limit has to be between 1 and {}!", MAX_LIMIT)));
+        }
+
+        let skip = match (&self.cursor, self.page) {
+            (Some(_), Some(_)) => return Err(ApiError::BadParams(String::from("Give either cursor or page, not both!"))),
+            (Some(cursor), None) => decode_cursor(cursor)?,
+            (None, Some(0)) => return Err(ApiError::BadParams(String::from("page starts at 1!"))),
+            (None, Some(page)) => (page - 1).checked_mul(limit)
+                .ok_or_else(|| ApiError::BadParams(String::from("page is past every list!")))?,
+            (None, None) => 0,
+        };
+        // MongoDB takes skip as a signed 64-bit number
+        if skip > i64::MAX as u64 {
+            return Err(ApiError::BadParams(String::from("page is past every list!")));
+        }
+
+        let mut sort = Document::new();
+        if let Some(fields) = &self.sort {
+            for field in model_fields::<M>(fields, "sort")? {
+                match field.strip_prefix('-') {
+                    Some(name) => sort.insert(name, -1),
+                    None => sort.insert(field, 1),
+                };
+            }
+        }
+        // A unique last key keeps pages stable when sorted values are equal
+        if !sort.contains_key("_id") {
+            sort.insert("_id", 1);
+        }
+
+        let fields = match &self.fields {
+            Some(fields) => Some(model_fields::<M>(fields, "fields")?
+                .into_iter()
+                .filter(|field| !field.starts_with('-'))
+                .collect()),
+            None => None,
+        };
+
+        Ok(ListOptions {
+            skip,
+            limit: Some(limit),
+            sort: Some(sort),
+            fields,
+        })
+    }
+}
+
+/// The envelope list endpoints answer with, items without their secret fields
+#[derive(Debug, Serialize)]
+#[serde(bound(serialize = "T: Model"))]
+pub struct Paginated<T> {
+    #[serde(serialize_with = "public_items")]
+    pub items: Vec<T>,
+    /// How many items match in total, over every page
+    pub total: u64,
+    pub limit: u64,
+    /// Pass as `cursor` to get the next page, `null` on the last one
+    pub next_cursor: Option<String>,
+}
+
+fn public_items<T: Model, S: Serializer>(items: &[T], serializer: S) -> Result<S::Ok, S::Error> {
+    items.iter().map(public_json).collect::<Result<Vec<_>, _>>().map_err(S::Error::custom)?.serialize(serializer)
+}
+
+impl<T> Paginated<T> {
+    pub fn new(page: Page<T>, options: &ListOptions) -> Self {
+        let limit = options.limit.unwrap_or(page.total);
+        let next_skip = options.skip + page.items.len() as u64;
+
+        Self {
+            next_cursor: (!page.items.is_empty() && next_skip < page.total).then(|| encode_cursor(next_skip)),
+            items: page.items,
+            total: page.total,
+            limit,
+        }
+    }
+}
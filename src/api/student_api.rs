@@ -1,71 +1,75 @@
 use std::collections::HashMap;
 
-use mongodb::{results::{InsertOneResult, DeleteResult, UpdateResult}, bson::doc};
-use rocket::{get, post, put, delete, serde::json::Json, State};
+use mongodb::bson::doc;
+use rocket::{get, post, put, patch, delete, serde::json::Json, State};
 use serde::{Deserialize, Serialize};
-use crate::models::{Model, Student, utils::hashmap_to_model_document, ErrorResponse};
-use crate::database::Database;
-
-#[get("/student")]
-pub fn get_all_students(db: &State<Database>) -> Result<Json<Vec<Student>>, Json<ErrorResponse>> {
-    let results = db.student_database.get_datas(doc! {});
-
-    match results {
-        Ok(models_data) => {
-            let mut students_data:Vec<Student> = Vec::<Student>::new();
-            for model in models_data {
-                match model {
-                    Model::Student(student_data) => {
-                        students_data.push(student_data);
-                    },
-                    Model::Teacher(_) => ()
-                }
-            }
-            Ok(Json(students_data))
-        },
-        Err(err) => Err(Json(ErrorResponse::new(err, 0)))
-    }
+use serde_json::{Map, Value};
+use crate::models::{Student, validation::{normalized, validate}, utils::{hashmap_to_model_document, not_deleted, params_to_filter, string_to_oid, parse_oid}, ApiError};
+use crate::api::{auth::Staff, pagination::{ListParams, Paginated}, bulk::{body_to_filter, check_matches, narrow_all}, utils::new_token, versioning::{IfMatch, Versioned, missed}};
+use crate::database::{Database, AuditContext, Repository, Patch, InsertResult, UpdateResult, DeleteResult};
+
+#[get("/student?<list..>")]
+pub async fn get_all_students(db: &State<Database>, list: ListParams) -> Result<Json<Paginated<Student>>, ApiError> {
+    let options = list.to_options::<Student>()?;
+    let students_data = db.student_database.list_page(not_deleted(doc! {}), options.clone()).await?;
+    Ok(Json(Paginated::new(students_data, &options)))
 }
 
 #[get("/student/search?<_id>&<name>&<class_id>&<card_id>")]
-pub fn get_student(db: &State<Database>, _id: Option<String>, name: Option<String>, class_id: Option<String>, card_id: Option<String>) -> Result<Json<Student>, Json<ErrorResponse>> {
+pub async fn get_student(db: &State<Database>, _id: Option<String>, name: Option<String>, class_id: Option<String>, card_id: Option<String>) -> Result<Versioned<Student>, ApiError> {
     let student_params: HashMap<String, Option<String>> = HashMap::<String, Option<String>>::from([
         (String::from("id"), _id),
         (String::from("name"), name),
-        (String::from("card_id"), card_id),
+        (String::from("card_id"), card_id.map(|card_id| normalized::<Student>("card_id", &card_id))),
         (String::from("class_id"), class_id),
     ]);
 
-    let student_params: mongodb::bson::Document = hashmap_to_model_document(&student_params, Model::Student(Student::empty()));
-    println!("{:?}", student_params);
+    let student_params: mongodb::bson::Document = hashmap_to_model_document::<Student>(&student_params);
     
-    if student_params.iter().collect::<Vec<(&String, &mongodb::bson::Bson)>>().len() <= 0 {
-        return Err(Json(ErrorResponse::new(String::from("Well you should try to give the correct parameters next time.."), 1)));
+    if student_params.is_empty() {
+        return Err(ApiError::wrong_params());
     }
     
-    let result: Result<Model, String> = db.student_database.get_data(student_params);
-
-    match result {
-        Ok(data) => {
-            match data {
-                Model::Student(student) => Ok(Json(student)),
-                Model::Teacher(_) => Err(Json(ErrorResponse::new(String::from("Unexpected value : teacher has occured when trying to get student data!"), 2)))
-            }
-        },
-        Err(err) => {
-            Err(Json(ErrorResponse::new(format!("Unexpected error has occured. err : {}", err), 0)))
-        }
+    let student = db.student_database.get(not_deleted(student_params)).await?;
+    Ok(Versioned::new(student.version, student))
+}
+
+/// Every student whose name contains `name` (or starts with it when `match=prefix`),
+/// ignoring case, optionally only in one class. Paginated like `GET /student`.
+#[get("/student/find?<name>&<class_id>&<match>&<list..>")]
+pub async fn find_students(db: &State<Database>, name: Option<String>, class_id: Option<String>, r#match: Option<String>, list: ListParams) -> Result<Json<Paginated<Student>>, ApiError> {
+    let mut filter = mongodb::bson::Document::new();
+
+    if let Some(name) = name.as_deref().map(str::trim).filter(|name| !name.is_empty()) {
+        let pattern = match r#match.as_deref() {
+            Some("prefix") => format!("^{}", regex::escape(name)),
+            Some("contains") | None => regex::escape(name),
+            Some(_) => return Err(ApiError::BadParams(String::from("match has to be prefix or contains!")))
+        };
+        filter.insert("name", doc! {"$regex": pattern, "$options": "i"});
     }
+
+    if let Some(class_id) = class_id {
+        match string_to_oid(&class_id) {
+            Some(class_id) => filter.insert("class_id", class_id),
+            None => return Err(ApiError::wrong_params())
+        };
+    }
+
+    if filter.is_empty() {
+        return Err(ApiError::wrong_params());
+    }
+
+    let options = list.to_options::<Student>()?;
+    let students_data = db.student_database.list_page(not_deleted(filter), options.clone()).await?;
+    Ok(Json(Paginated::new(students_data, &options)))
 }
 
 #[post("/student", data = "<new_student>")]
-pub fn post_student(db: &State<Database>, new_student: Json<Student>) -> Result<Json<InsertOneResult>, Json<ErrorResponse>> {
-    let new_student_data: Student = new_student.0;
-    let result = db.student_database.insert_data(Model::Student(new_student_data));
-    match result {
-        Ok(res) => Ok(Json(res)),
-        Err(err) => Err(Json(ErrorResponse::new(err, 0)))
-    }
+pub async fn post_student(db: &State<Database>, _staff: Staff, audit: AuditContext, new_student: Json<Student>) -> Result<Json<InsertResult>, ApiError> {
+    let new_student_data: Student = validate(new_student.0, true)?;
+    let result = db.audited(db.student_database.as_ref(), &audit).insert(&new_student_data).await;
+    result.map(Json)
 }
 
 #[derive(Serialize, Deserialize)]
@@ -74,35 +78,119 @@ pub struct PutParamsData {
     new_data: Student,
 }
 
-#[put("/student", data = "<params>")]
-pub fn put_student(db: &State<Database>, params: Json<PutParamsData>) -> Result<Json<UpdateResult>, Json<ErrorResponse>> {
-    // Convert new_data: Student to Model
-    let new_data = Model::Student(params.0.new_data);
-    
-    // Convert HashMap<String, String> to HashMap<String, Bson>
-    let params = params.0.params.into_iter().map(|(key, value)| (key, mongodb::bson::Bson::String(value))).collect::<HashMap<String, mongodb::bson::Bson>>();
-    // Convert HashMap<String, Bson> to mongodb::bson::Document
-    let params = mongodb::bson::Document::from_iter(params.into_iter());
-    
-    let result = db.student_database.update_data(params, new_data);
-    match result {
-        Ok(return_value) => Ok(Json(return_value)),
-        Err(err) => Err(Json(ErrorResponse::new(err.to_string(), 0)))
+/// Sets `new_data` on the students matching `params`, which all have to still be at the
+/// version sent as `If-Match` (`*` changes them whatever it is).
+/// A filter matching several students needs `?confirm=true`.
+#[put("/student?<confirm>", data = "<params>")]
+pub async fn put_student(db: &State<Database>, _staff: Staff, audit: AuditContext, if_match: IfMatch, confirm: Option<bool>, params: Json<PutParamsData>) -> Result<Json<UpdateResult>, ApiError> {
+    let PutParamsData { params, new_data } = params.0;
+    let filter = params_to_filter::<Student>(params)?;
+    let new_data = validate(new_data, false).map_err(|err| err.within("new_data"))?;
+
+    let matched_count = check_matches(db.student_database.as_ref(), &filter, confirm.unwrap_or(false)).await?;
+    let filter = narrow_all(db.student_database.as_ref(), filter, &if_match, matched_count).await?;
+    db.audited(db.student_database.as_ref(), &audit).update(not_deleted(filter), &new_data).await.map(Json)
+}
+
+/// Deletes the students matching the fields given in the body, see `delete_student_by_id`.
+/// A filter matching several students needs `?confirm=true`.
+#[delete("/student?<confirm>", data = "<params>")]
+pub async fn delete_student(db: &State<Database>, _staff: Staff, audit: AuditContext, confirm: Option<bool>, params: Json<Student>) -> Result<Json<DeleteResult>, ApiError>{
+    let filter = body_to_filter(&params.0)?;
+
+    check_matches(db.student_database.as_ref(), &filter, confirm.unwrap_or(false)).await?;
+    let result = db.audited(db.student_database.as_ref(), &audit).soft_delete(filter).await?;
+    Ok(Json(DeleteResult {
+        deleted_count: result.modified_count
+    }))
+}
+
+#[get("/student/<id>")]
+pub async fn get_student_by_id(db: &State<Database>, id: &str) -> Result<Versioned<Student>, ApiError> {
+    let id = parse_oid(id)?;
+    let student = db.student_database.get(not_deleted(doc! {"_id": id})).await?;
+    Ok(Versioned::new(student.version, student))
+}
+
+/// Replaces the whole student, fields left out of the body are removed (but its token).
+/// Needs the `ETag` the student was read with as `If-Match`.
+#[put("/student/<id>", data = "<student>")]
+pub async fn put_student_by_id(db: &State<Database>, _staff: Staff, audit: AuditContext, if_match: IfMatch, id: &str, student: Json<Student>) -> Result<Versioned<Student>, ApiError> {
+    let id = parse_oid(id)?;
+    let student = validate(student.0, true)?;
+    let filter = if_match.narrow(not_deleted(doc! {"_id": id}))?;
+    // The token isn't the client's to replace, the student keeps checking in with it
+    let token = db.student_database.get(not_deleted(doc! {"_id": id})).await.ok().and_then(|student| student.token);
+    let student = Student { token, ..student };
+
+    let result = db.audited(db.student_database.as_ref(), &audit).replace(filter, &student).await?;
+    if result.matched_count == 0 {
+        return Err(missed(db.student_database.as_ref(), id).await);
     }
+    let student = db.student_database.get(doc! {"_id": id}).await?;
+    Ok(Versioned::new(student.version, student))
 }
 
-#[delete("/student", data = "<params>")]
-pub fn delete_student(db: &State<Database>, params: Json<Student>) -> Result<Json<DeleteResult>, Json<ErrorResponse>>{
-    let params: HashMap<String, Option<String>> = HashMap::<String, Option<String>>::from([
-    (String::from("_id"), params.0.id.map(|id| id.to_string())),
-        (String::from("name"), params.0.name),
-        (String::from("card_id"), params.0.card_id),
-        (String::from("class_id"), params.0.class_id.map(|id| id.to_string())),
-    ]);
-    let params: mongodb::bson::Document = hashmap_to_model_document(&params, Model::Student(Student::empty()));
-    let result = db.student_database.delete_data(params);
-    match result {
-        Ok(return_value) => Ok(Json(return_value)),
-        Err(err) => Err(Json(ErrorResponse::new(err.to_string(), 0)))
+/// Applies a JSON merge patch: fields left out stay as they are and `null` clears one.
+/// Needs the `ETag` the student was read with as `If-Match`.
+#[patch("/student/<id>", data = "<patch>")]
+pub async fn patch_student_by_id(db: &State<Database>, _staff: Staff, audit: AuditContext, if_match: IfMatch, id: &str, patch: Json<Map<String, Value>>) -> Result<Versioned<Student>, ApiError> {
+    let id = parse_oid(id)?;
+    let patch = Patch::from_json::<Student>(patch.0)?;
+    let filter = if_match.narrow(not_deleted(doc! {"_id": id}))?;
+
+    let result = db.audited(db.student_database.as_ref(), &audit).patch(filter, &patch).await?;
+    if result.matched_count == 0 {
+        return Err(missed(db.student_database.as_ref(), id).await);
+    }
+    let student = db.student_database.get(doc! {"_id": id}).await?;
+    Ok(Versioned::new(student.version, student))
+}
+
+/// Soft deletes the student: it's hidden from every read but kept, with its
+/// attendance, until an admin purges it. `POST /student/<id>/restore` brings it back.
+#[delete("/student/<id>")]
+pub async fn delete_student_by_id(db: &State<Database>, _staff: Staff, audit: AuditContext, id: &str) -> Result<Json<DeleteResult>, ApiError> {
+    let id = parse_oid(id)?;
+    let result = db.audited(db.student_database.as_ref(), &audit).soft_delete(doc! {"_id": id}).await?;
+    if result.matched_count == 0 {
+        return Err(ApiError::NotFound(String::from("Student's not found!")));
+    }
+    Ok(Json(DeleteResult {
+        deleted_count: result.modified_count
+    }))
+}
+
+#[post("/student/<id>/restore")]
+pub async fn restore_student(db: &State<Database>, _staff: Staff, audit: AuditContext, id: &str) -> Result<Versioned<Student>, ApiError> {
+    let id = parse_oid(id)?;
+    let result = db.audited(db.student_database.as_ref(), &audit).restore(doc! {"_id": id}).await?;
+    if result.matched_count == 0 {
+        return Err(ApiError::NotFound(String::from("There's no deleted student with this id!")));
     }
-}
\ No newline at end of file
+    let student = db.student_database.get(doc! {"_id": id}).await?;
+    Ok(Versioned::new(student.version, student))
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct StudentToken {
+    pub token: String,
+}
+
+/// Gives the student a new token to check in with as `Authorization: Bearer <token>`,
+/// the one it had stops working. It's only answered here, it can't be read again later.
+#[post("/student/<id>/token")]
+pub async fn post_student_token(db: &State<Database>, _staff: Staff, audit: AuditContext, id: &str) -> Result<Json<StudentToken>, ApiError> {
+    let id = parse_oid(id)?;
+    let token = new_token();
+    let patch = Patch {
+        set: doc! {"token": &token},
+        unset: Vec::new()
+    };
+
+    let result = db.audited(db.student_database.as_ref(), &audit).patch(not_deleted(doc! {"_id": id}), &patch).await?;
+    if result.matched_count == 0 {
+        return Err(ApiError::NotFound(String::from("Student's not found!")));
+    }
+    Ok(Json(StudentToken { token }))
+}
@@ -1,71 +1,43 @@
 use std::collections::HashMap;
 
-use mongodb::{results::{DeleteResult, InsertOneResult, UpdateResult}, bson::doc};
-use rocket::{get, post, serde::json::Json, State};
+use mongodb::bson::doc;
+use rocket::{get, post, patch, serde::json::Json, State};
 use serde::{Serialize, Deserialize};
-use crate::models::{Model, Teacher, utils::hashmap_to_model_document, ErrorResponse};
-use crate::database::Database;
-
-
-#[get("/teacher")]
-pub fn get_all_teachers(db: &State<Database>) -> Result<Json<Vec<Teacher>>, Json<ErrorResponse>> {
-    let results = db.teacher_database.get_datas(doc! {});
-
-    match results {
-        Ok(models_data) => {
-            let mut teachers_data:Vec<Teacher> = Vec::<Teacher>::new();
-            for model in models_data {
-                match model {
-                    Model::Teacher(teacher_data) => {
-                        teachers_data.push(teacher_data);
-                    },
-                    Model::Student(_) => ()
-                }
-            }
-            Ok(Json(teachers_data))
-        },
-        Err(err) => Err(Json(ErrorResponse::new(err.to_string(), 0)))
-    }
+use serde_json::{Map, Value};
+use crate::models::{Teacher, validation::validate, utils::{hashmap_to_model_document, not_deleted, params_to_filter, parse_oid}, ApiError};
+use crate::api::{auth::{Admin, Staff}, pagination::{ListParams, Paginated}, bulk::{body_to_filter, check_matches, narrow_all}, versioning::{IfMatch, Versioned, missed}};
+use crate::database::{Database, AuditContext, Repository, Patch, InsertResult, UpdateResult, DeleteResult};
+
+
+#[get("/teacher?<list..>")]
+pub async fn get_all_teachers(db: &State<Database>, list: ListParams) -> Result<Json<Paginated<Teacher>>, ApiError> {
+    let options = list.to_options::<Teacher>()?;
+    let teachers_data = db.teacher_database.list_page(not_deleted(doc! {}), options.clone()).await?;
+    Ok(Json(Paginated::new(teachers_data, &options)))
 }
 
-#[get("/teacher/search?<_id>&<name>&<pass>")]
-pub fn get_teacher(db: &State<Database>, _id: Option<String>, name: Option<String>, pass: Option<String>) -> Result<Json<Teacher>, Json<ErrorResponse>> {
+#[get("/teacher/search?<_id>&<name>")]
+pub async fn get_teacher(db: &State<Database>, _id: Option<String>, name: Option<String>) -> Result<Versioned<Teacher>, ApiError> {
     let teacher_params: HashMap<String, Option<String>> = HashMap::<String, Option<String>>::from([
         (String::from("id"), _id),
         (String::from("name"), name),
-        (String::from("pass"), pass),
     ]);
 
-    let teacher_params: mongodb::bson::Document = hashmap_to_model_document(&teacher_params, Model::Teacher(Teacher::empty()));
-    println!("{:?}", teacher_params);
+    let teacher_params: mongodb::bson::Document = hashmap_to_model_document::<Teacher>(&teacher_params);
     
-    if teacher_params.iter().collect::<Vec<(&String, &mongodb::bson::Bson)>>().len() <= 0 {
-        return Err(Json(ErrorResponse::new("Well you should try to give the correct parameters next time..".to_string(), 1)));
+    if teacher_params.is_empty() {
+        return Err(ApiError::wrong_params());
     }
     
-    let result: Result<Model, String> = db.teacher_database.get_data(teacher_params);
-
-    match result {
-        Ok(data) => {
-            match data {
-                Model::Teacher(teacher) => Ok(Json(teacher)),
-                Model::Student(_) => Err(Json(ErrorResponse::new("Unexpected value : student has occured when trying to get teacher data!".to_string(), 2)))
-            }
-        },
-        Err(err) => {
-            Err(Json(ErrorResponse::new(format!("Unexpected error has occured. err : {}", err), 0)))
-        }
-    }
+    let teacher = db.teacher_database.get(not_deleted(teacher_params)).await?;
+    Ok(Versioned::new(teacher.version, teacher))
 }
 
 #[post("/teacher", data = "<new_teacher>")]
-pub fn post_teacher(db: &State<Database>, new_teacher: Json<Teacher>) -> Result<Json<InsertOneResult>, Json<ErrorResponse>> {
-    let new_teacher_data: Teacher = new_teacher.0;
-    let result = db.teacher_database.insert_data(Model::Teacher(new_teacher_data));
-    match result {
-        Ok(res) => Ok(Json(res)),
-        Err(err) => Err(Json(ErrorResponse::new(err, 0)))
-    }
+pub async fn post_teacher(db: &State<Database>, _admin: Admin, audit: AuditContext, new_teacher: Json<Teacher>) -> Result<Json<InsertResult>, ApiError> {
+    let new_teacher_data: Teacher = validate(new_teacher.0, true)?.with_hashed_pass()?;
+    let result = db.audited(db.teacher_database.as_ref(), &audit).insert(&new_teacher_data).await;
+    result.map(Json)
 }
 
 #[derive(Serialize, Deserialize)]
@@ -75,29 +47,100 @@ pub struct PutParamsData {
 }
 
 
-#[put("/teacher", data = "<params>")]
-pub fn put_teacher(db: &State<Database>, params: Json<PutParamsData>) -> Result<Json<UpdateResult>, Json<ErrorResponse>> {
-    let new_data = Model::Teacher(params.0.new_data);
-    let params = params.0.params.into_iter().map(|(key, value)| (key, mongodb::bson::Bson::String(value))).collect::<HashMap<String, mongodb::bson::Bson>>();
-    let params = mongodb::bson::Document::from_iter(params.into_iter());
-    let result = db.teacher_database.update_data(params, new_data);
-    match result {
-        Ok(return_value) => Ok(Json(return_value)),
-        Err(err) => Err(Json(ErrorResponse::new(err.to_string(), 0)))
+/// Sets `new_data` on the teachers matching `params`, which all have to still be at the
+/// version sent as `If-Match` (`*` changes them whatever it is).
+/// A filter matching several teachers needs `?confirm=true`.
+#[put("/teacher?<confirm>", data = "<params>")]
+pub async fn put_teacher(db: &State<Database>, _admin: Admin, audit: AuditContext, if_match: IfMatch, confirm: Option<bool>, params: Json<PutParamsData>) -> Result<Json<UpdateResult>, ApiError> {
+    let PutParamsData { params, new_data } = params.0;
+    let filter = params_to_filter::<Teacher>(params)?;
+    let new_data = validate(new_data, false).map_err(|err| err.within("new_data"))?.with_hashed_pass()?;
+
+    let matched_count = check_matches(db.teacher_database.as_ref(), &filter, confirm.unwrap_or(false)).await?;
+    let filter = narrow_all(db.teacher_database.as_ref(), filter, &if_match, matched_count).await?;
+    db.audited(db.teacher_database.as_ref(), &audit).update(not_deleted(filter), &new_data).await.map(Json)
+}
+
+/// Deletes the teachers matching the fields given in the body, see `delete_teacher_by_id`.
+/// A filter matching several teachers needs `?confirm=true`.
+#[delete("/teacher?<confirm>", data = "<params>")]
+pub async fn delete_teacher(db: &State<Database>, _admin: Admin, audit: AuditContext, confirm: Option<bool>, params: Json<Teacher>) -> Result<Json<DeleteResult>, ApiError>{
+    let filter = body_to_filter(&params.0)?;
+
+    check_matches(db.teacher_database.as_ref(), &filter, confirm.unwrap_or(false)).await?;
+    let result = db.audited(db.teacher_database.as_ref(), &audit).soft_delete(filter).await?;
+    Ok(Json(DeleteResult {
+        deleted_count: result.modified_count
+    }))
+}
+
+#[get("/teacher/<id>")]
+pub async fn get_teacher_by_id(db: &State<Database>, id: &str) -> Result<Versioned<Teacher>, ApiError> {
+    let id = parse_oid(id)?;
+    let teacher = db.teacher_database.get(not_deleted(doc! {"_id": id})).await?;
+    Ok(Versioned::new(teacher.version, teacher))
+}
+
+/// Replaces the whole teacher, fields left out of the body are removed.
+/// Needs the `ETag` the teacher was read with as `If-Match`. Teachers only replace themselves.
+#[put("/teacher/<id>", data = "<teacher>")]
+pub async fn put_teacher_by_id(db: &State<Database>, staff: Staff, audit: AuditContext, if_match: IfMatch, id: &str, teacher: Json<Teacher>) -> Result<Versioned<Teacher>, ApiError> {
+    let id = parse_oid(id)?;
+    staff.may_change_teacher(id)?;
+    let teacher = validate(teacher.0, true)?.with_hashed_pass()?;
+    let filter = if_match.narrow(not_deleted(doc! {"_id": id}))?;
+
+    let result = db.audited(db.teacher_database.as_ref(), &audit).replace(filter, &teacher).await?;
+    if result.matched_count == 0 {
+        return Err(missed(db.teacher_database.as_ref(), id).await);
     }
+    let teacher = db.teacher_database.get(doc! {"_id": id}).await?;
+    Ok(Versioned::new(teacher.version, teacher))
 }
 
-#[delete("/teacher", data = "<params>")]
-pub fn delete_teacher(db: &State<Database>, params: Json<Teacher>) -> Result<Json<DeleteResult>, Json<ErrorResponse>>{
-    let params: HashMap<String, Option<String>> = HashMap::<String, Option<String>>::from([
-        (String::from("_id"), params.0.id.map(|id| id.to_string())),
-        (String::from("name"), params.0.name),
-        (String::from("pass"), params.0.pass),
-    ]);
-    let params: mongodb::bson::Document = hashmap_to_model_document(&params, Model::Teacher(Teacher::empty()));
-    let result = db.teacher_database.delete_data(params);
-    match result {
-        Ok(return_value) => Ok(Json(return_value)),
-        Err(err) => Err(Json(ErrorResponse::new(err.to_string(), 0)))
+/// Applies a JSON merge patch: fields left out stay as they are and `null` clears one.
+/// Needs the `ETag` the teacher was read with as `If-Match`. Teachers only patch themselves,
+/// e.g. to change their pass.
+#[patch("/teacher/<id>", data = "<patch>")]
+pub async fn patch_teacher_by_id(db: &State<Database>, staff: Staff, audit: AuditContext, if_match: IfMatch, id: &str, patch: Json<Map<String, Value>>) -> Result<Versioned<Teacher>, ApiError> {
+    let id = parse_oid(id)?;
+    staff.may_change_teacher(id)?;
+    let mut patch = Patch::from_json::<Teacher>(patch.0)?;
+    if let Ok(pass) = patch.set.get_str("pass") {
+        let hash = Teacher::hash_pass(pass)?;
+        patch.set.insert("pass", hash);
+    }
+    let filter = if_match.narrow(not_deleted(doc! {"_id": id}))?;
+
+    let result = db.audited(db.teacher_database.as_ref(), &audit).patch(filter, &patch).await?;
+    if result.matched_count == 0 {
+        return Err(missed(db.teacher_database.as_ref(), id).await);
+    }
+    let teacher = db.teacher_database.get(doc! {"_id": id}).await?;
+    Ok(Versioned::new(teacher.version, teacher))
+}
+
+/// Soft deletes the teacher: it's hidden from every read but kept, with its
+/// attendance, until an admin purges it. `POST /teacher/<id>/restore` brings it back.
+#[delete("/teacher/<id>")]
+pub async fn delete_teacher_by_id(db: &State<Database>, _admin: Admin, audit: AuditContext, id: &str) -> Result<Json<DeleteResult>, ApiError> {
+    let id = parse_oid(id)?;
+    let result = db.audited(db.teacher_database.as_ref(), &audit).soft_delete(doc! {"_id": id}).await?;
+    if result.matched_count == 0 {
+        return Err(ApiError::NotFound(String::from("Teacher's not found!")));
+    }
+    Ok(Json(DeleteResult {
+        deleted_count: result.modified_count
+    }))
+}
+
+#[post("/teacher/<id>/restore")]
+pub async fn restore_teacher(db: &State<Database>, _admin: Admin, audit: AuditContext, id: &str) -> Result<Versioned<Teacher>, ApiError> {
+    let id = parse_oid(id)?;
+    let result = db.audited(db.teacher_database.as_ref(), &audit).restore(doc! {"_id": id}).await?;
+    if result.matched_count == 0 {
+        return Err(ApiError::NotFound(String::from("There's no deleted teacher with this id!")));
     }
-}
\ No newline at end of file
+    let teacher = db.teacher_database.get(doc! {"_id": id}).await?;
+    Ok(Versioned::new(teacher.version, teacher))
+}
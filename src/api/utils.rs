@@ -0,0 +1,94 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use hmac::{Hmac, Mac};
+use mongodb::bson::oid::ObjectId;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// What a QR code shown by the teacher vouches for
+#[derive(Debug, Serialize, Deserialize)]
+pub struct QrClaims {
+    pub class_id: ObjectId,
+    pub lesson: String,
+    /// Unix timestamp (seconds) after which the payload is rejected
+    pub expires_at: u64,
+}
+
+/// Signs and verifies the short-lived QR payloads used for check-in.
+///
+/// A payload looks like `<claims>.<signature>`, both parts base64url encoded,
+/// where the signature is an HMAC-SHA256 of the encoded claims.
+pub struct QrSigner {
+    secret: Vec<u8>,
+    ttl_seconds: u64,
+}
+
+/// A random bearer token, for devices and students to sign in with
+pub fn new_token() -> String {
+    rand::thread_rng().gen::<[u8; 24]>().iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+pub fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or(0)
+}
+
+impl QrSigner {
+    pub fn new(secret: &str, ttl_seconds: u64) -> Self {
+        Self {
+            secret: secret.as_bytes().to_vec(),
+            ttl_seconds,
+        }
+    }
+
+    fn mac(&self) -> HmacSha256 {
+        HmacSha256::new_from_slice(&self.secret).expect("HMAC accepts keys of any length")
+    }
+
+    pub fn sign(&self, class_id: ObjectId, lesson: String) -> (String, QrClaims) {
+        let claims = QrClaims {
+            class_id,
+            lesson,
+            expires_at: unix_now() + self.ttl_seconds,
+        };
+        let encoded_claims = URL_SAFE_NO_PAD.encode(serde_json::to_vec(&claims).expect("QR claims are always serializable"));
+
+        let mut mac = self.mac();
+        mac.update(encoded_claims.as_bytes());
+        let signature = URL_SAFE_NO_PAD.encode(mac.finalize().into_bytes());
+
+        (format!("{}.{}", encoded_claims, signature), claims)
+    }
+
+    pub fn verify(&self, payload: &str) -> Result<QrClaims, String> {
+        let (encoded_claims, signature) = payload
+            .split_once('.')
+            .ok_or_else(|| String::from("QR payload is malformed!"))?;
+        let signature = URL_SAFE_NO_PAD
+            .decode(signature)
+            .map_err(|_| String::from("QR payload is malformed!"))?;
+
+        let mut mac = self.mac();
+        mac.update(encoded_claims.as_bytes());
+        mac.verify_slice(&signature)
+            .map_err(|_| String::from("QR payload signature is invalid!"))?;
+
+        let claims = URL_SAFE_NO_PAD
+            .decode(encoded_claims)
+            .ok()
+            .and_then(|bytes| serde_json::from_slice::<QrClaims>(&bytes).ok())
+            .ok_or_else(|| String::from("QR payload is malformed!"))?;
+
+        if claims.expires_at < unix_now() {
+            return Err(String::from("QR payload has expired!"));
+        }
+
+        Ok(claims)
+    }
+}
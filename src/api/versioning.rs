@@ -0,0 +1,85 @@
+use mongodb::bson::{doc, oid::ObjectId, Bson, Document};
+use rocket::{http::{Header, Status}, request::{FromRequest, Outcome, Request}, response::{self, Responder}, serde::json::Json};
+use crate::models::{ApiError, Model, utils::{not_deleted, public_json}};
+use crate::database::Repository;
+
+/// An entity answered with its version as a strong `ETag`, e.g. `"3"`, and without its secret fields
+pub struct Versioned<T> {
+    pub version: Option<i64>,
+    pub data: T,
+}
+
+impl<T> Versioned<T> {
+    pub fn new(version: Option<i64>, data: T) -> Self {
+        Self {
+            version,
+            data
+        }
+    }
+}
+
+impl<'r, T: Model> Responder<'r, 'static> for Versioned<T> {
+    fn respond_to(self, request: &'r Request<'_>) -> response::Result<'static> {
+        let data = public_json(&self.data).map_err(|_| Status::InternalServerError)?;
+        let mut response = Json(data).respond_to(request)?;
+        // Documents stored before versions existed are at 0, see `Repository`
+        response.set_header(Header::new("ETag", format!("\"{}\"", self.version.unwrap_or(0))));
+        Ok(response)
+    }
+}
+
+/// The `If-Match` header of a request: `*` or the `ETag` it last saw
+pub enum IfMatch {
+    Missing,
+    Any,
+    Version(i64),
+}
+
+impl IfMatch {
+    /// Narrows `filter` to the version the request is based on.
+    /// Changes without `If-Match` are refused, they'd overwrite whatever happened since.
+    pub fn narrow(&self, mut filter: Document) -> Result<Document, ApiError> {
+        match self {
+            IfMatch::Missing => return Err(ApiError::PreconditionRequired(String::from("Send the ETag you got as If-Match, so changes made since aren't overwritten!"))),
+            IfMatch::Any => {},
+            IfMatch::Version(0) => {
+                filter.insert("version", Bson::Null);
+            },
+            IfMatch::Version(version) => {
+                filter.insert("version", *version);
+            }
+        };
+        Ok(filter)
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for IfMatch {
+    type Error = ApiError;
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let value = match request.headers().get_one("If-Match") {
+            Some(value) => value.trim(),
+            None => return Outcome::Success(IfMatch::Missing)
+        };
+        if value == "*" {
+            return Outcome::Success(IfMatch::Any);
+        }
+
+        let version = value.strip_prefix("W/").unwrap_or(value).trim_matches('"');
+        match version.parse::<i64>() {
+            Ok(version) => Outcome::Success(IfMatch::Version(version)),
+            Err(_) => Outcome::Error((Status::BadRequest, ApiError::BadParams(String::from("If-Match has to be an ETag this API handed out!"))))
+        }
+    }
+}
+
+/// Why a change of the document with `id` narrowed by `IfMatch` matched nothing:
+/// it's gone, or it moved on to another version
+pub async fn missed<T: Model>(repository: &dyn Repository<T>, id: ObjectId) -> ApiError {
+    match repository.count(not_deleted(doc! {"_id": id})).await {
+        Ok(0) => ApiError::NotFound(format!("{}'s not found!", T::NAME)),
+        Ok(_) => ApiError::PreconditionFailed(format!("{} was changed since, get it again before changing it!", T::NAME)),
+        Err(err) => err
+    }
+}
@@ -0,0 +1,92 @@
+use mongodb::bson::{doc, Bson};
+use rocket::{get, post, patch, delete, serde::json::Json, State};
+use serde_json::{Map, Value};
+use crate::models::{Webhook, WebhookDelivery, validation::{validate, Violation}, utils::{not_deleted, parse_oid}, ApiError};
+use crate::api::{auth::Admin, pagination::{ListParams, Paginated}, versioning::{IfMatch, Versioned, missed}, webhook_delivery::{Webhooks, EVENTS}};
+use crate::database::{Database, AuditContext, Repository, Patch, InsertResult, DeleteResult};
+
+/// `events` has to list at least one of `EVENTS`, and nothing else
+fn check_events(events: &[Bson]) -> Result<(), ApiError> {
+    let known = events.iter().all(|event| event.as_str().is_some_and(|event| EVENTS.contains(&event)));
+    if events.is_empty() || !known {
+        return Err(ApiError::Validation(vec![Violation::new("events", &format!("has to list some of {}", EVENTS.join(", ")))]));
+    }
+    Ok(())
+}
+
+#[get("/webhook?<list..>")]
+pub async fn get_all_webhooks(db: &State<Database>, _admin: Admin, list: ListParams) -> Result<Json<Paginated<Webhook>>, ApiError> {
+    let options = list.to_options::<Webhook>()?;
+    let webhooks_data = db.webhook_database.list_page(not_deleted(doc! {}), options.clone()).await?;
+    Ok(Json(Paginated::new(webhooks_data, &options)))
+}
+
+#[get("/webhook/<id>")]
+pub async fn get_webhook_by_id(db: &State<Database>, _admin: Admin, id: &str) -> Result<Versioned<Webhook>, ApiError> {
+    let id = parse_oid(id)?;
+    let webhook = db.webhook_database.get(not_deleted(doc! {"_id": id})).await?;
+    Ok(Versioned::new(webhook.version, webhook))
+}
+
+/// Subscribes `url` to the `events` listed, see `webhook_delivery` for what's sent there
+#[post("/webhook", data = "<new_webhook>")]
+pub async fn post_webhook(db: &State<Database>, _admin: Admin, audit: AuditContext, new_webhook: Json<Webhook>) -> Result<Json<InsertResult>, ApiError> {
+    let new_webhook_data = validate(new_webhook.0, true)?;
+    let events: Vec<Bson> = new_webhook_data.events.iter().flatten().map(|event| Bson::String(event.clone())).collect();
+    check_events(&events)?;
+    db.audited(db.webhook_database.as_ref(), &audit).insert(&new_webhook_data).await.map(Json)
+}
+
+/// Applies a JSON merge patch, see `patch_student_by_id`. Deliveries
+/// not made yet go to the new `url`, signed with the new `secret`.
+#[patch("/webhook/<id>", data = "<patch>")]
+pub async fn patch_webhook_by_id(db: &State<Database>, _admin: Admin, audit: AuditContext, if_match: IfMatch, id: &str, patch: Json<Map<String, Value>>) -> Result<Versioned<Webhook>, ApiError> {
+    let id = parse_oid(id)?;
+    let patch = Patch::from_json::<Webhook>(patch.0)?;
+    if let Ok(events) = patch.set.get_array("events") {
+        check_events(events)?;
+    }
+    let filter = if_match.narrow(not_deleted(doc! {"_id": id}))?;
+
+    let result = db.audited(db.webhook_database.as_ref(), &audit).patch(filter, &patch).await?;
+    if result.matched_count == 0 {
+        return Err(missed(db.webhook_database.as_ref(), id).await);
+    }
+    let webhook = db.webhook_database.get(doc! {"_id": id}).await?;
+    Ok(Versioned::new(webhook.version, webhook))
+}
+
+/// Soft deletes the webhook, the deliveries it still had pending fail
+#[delete("/webhook/<id>")]
+pub async fn delete_webhook_by_id(db: &State<Database>, _admin: Admin, audit: AuditContext, id: &str) -> Result<Json<DeleteResult>, ApiError> {
+    let id = parse_oid(id)?;
+    let result = db.audited(db.webhook_database.as_ref(), &audit).soft_delete(doc! {"_id": id}).await?;
+    if result.matched_count == 0 {
+        return Err(ApiError::NotFound(String::from("Webhook's not found!")));
+    }
+    Ok(Json(DeleteResult {
+        deleted_count: result.modified_count
+    }))
+}
+
+/// The delivery log of a webhook, newest first unless sorted otherwise
+#[get("/webhook/<id>/deliveries?<list..>")]
+pub async fn get_webhook_deliveries(db: &State<Database>, _admin: Admin, id: &str, mut list: ListParams) -> Result<Json<Paginated<WebhookDelivery>>, ApiError> {
+    let id = parse_oid(id)?;
+    // Deleted webhooks keep their log
+    db.webhook_database.get(doc! {"_id": id}).await?;
+
+    list.sort.get_or_insert_with(|| String::from("-created_at"));
+    let options = list.to_options::<WebhookDelivery>()?;
+    let deliveries_data = db.webhook_delivery_database.list_page(doc! {"webhook_id": id}, options.clone()).await?;
+    Ok(Json(Paginated::new(deliveries_data, &options)))
+}
+
+/// Sends the webhook a `ping` event right away, answering the delivery as logged.
+/// It's attempted once, a failure isn't retried.
+#[post("/webhook/<id>/test")]
+pub async fn test_webhook(db: &State<Database>, webhooks: &State<Webhooks>, _admin: Admin, id: &str) -> Result<Json<WebhookDelivery>, ApiError> {
+    let id = parse_oid(id)?;
+    let webhook = db.webhook_database.get(not_deleted(doc! {"_id": id})).await?;
+    webhooks.test_fire(&webhook).await.map(Json)
+}
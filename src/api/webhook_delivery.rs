@@ -0,0 +1,252 @@
+use std::{sync::Arc, time::Duration};
+
+use hmac::{Hmac, Mac};
+use mongodb::bson::{doc, oid::ObjectId, DateTime};
+use rocket::Shutdown;
+use futures::{stream, StreamExt};
+use rocket::tokio::{select, sync::Notify, time::sleep};
+use serde::Serialize;
+use sha2::Sha256;
+use crate::models::{ApiError, Webhook, WebhookDelivery, utils::not_deleted};
+use crate::api::utils::unix_now;
+use crate::database::{Database, Repository};
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// A student checked in, the data is the stored attendance
+pub const CHECKED_IN: &str = "attendance.checked_in";
+/// A student checked in after the lesson's late mark, see `POST /lesson/close`
+pub const LATE: &str = "attendance.late";
+/// A student never checked in to a lesson, see `POST /lesson/close`
+pub const ABSENT: &str = "attendance.absent";
+/// Only sent by a test fire, nothing subscribes to it
+pub const PING: &str = "ping";
+/// The events a webhook can subscribe to
+pub const EVENTS: [&str; 3] = [CHECKED_IN, LATE, ABSENT];
+
+pub const PENDING: &str = "pending";
+pub const DELIVERED: &str = "delivered";
+pub const FAILED: &str = "failed";
+
+/// How long a webhook has to answer before the attempt counts as failed
+const TIMEOUT: Duration = Duration::from_secs(10);
+/// How often the worker looks for retries that became due
+const POLL: Duration = Duration::from_secs(5);
+/// How many deliveries are attempted at once, so a slow webhook doesn't hold back the others
+const CONCURRENT_DELIVERIES: usize = 16;
+
+/// The body of every delivery
+#[derive(Serialize)]
+struct Payload<'a, T: Serialize> {
+    /// The event's, the same for every webhook it's sent to
+    id: String,
+    event: &'a str,
+    /// RFC 3339
+    created_at: String,
+    data: &'a T,
+}
+
+/// The signature of a delivery: the hex HMAC-SHA256 of `<timestamp>.<body>` keyed with the webhook's secret.
+/// It's sent as `X-Presency-Signature: t=<timestamp>,v1=<signature>`, so a receiver can refuse old deliveries replayed.
+pub fn sign(secret: &str, timestamp: u64, body: &str) -> String {
+    let mut mac = HmacSha256::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any length");
+    mac.update(format!("{}.{}", timestamp, body).as_bytes());
+    mac.finalize().into_bytes().iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// Sends attendance events to the webhooks subscribed to them.
+///
+/// Each event is logged as a delivery per webhook before anything is sent, and
+/// `run` keeps attempting it until the webhook answers with a 2xx: a failed attempt is
+/// retried after `retry`, then twice as long after every further failure, `max_attempts` in all.
+#[derive(Clone)]
+pub struct Webhooks {
+    webhooks: Arc<dyn Repository<Webhook>>,
+    deliveries: Arc<dyn Repository<WebhookDelivery>>,
+    client: reqwest::Client,
+    wake: Arc<Notify>,
+    max_attempts: u32,
+    retry: Duration,
+}
+
+impl Webhooks {
+    pub fn new(db: &Database, max_attempts: u32, retry: Duration) -> Self {
+        Self {
+            webhooks: db.webhook_database.clone(),
+            deliveries: db.webhook_delivery_database.clone(),
+            client: reqwest::Client::builder().timeout(TIMEOUT).build().unwrap_or_default(),
+            wake: Arc::new(Notify::new()),
+            max_attempts: max_attempts.max(1),
+            retry,
+        }
+    }
+
+    /// Logs a delivery of `event` for every webhook subscribed to it and has `run` send them,
+    /// answering how many there are
+    pub async fn publish<T: Serialize>(&self, event: &str, data: &T) -> Result<u64, ApiError> {
+        let payload = payload(event, data)?;
+        let now = DateTime::now();
+        let mut queued: u64 = 0;
+        for webhook in self.webhooks.list(not_deleted(doc! {})).await? {
+            if !webhook.events.iter().flatten().any(|subscribed| subscribed == event) {
+                continue;
+            }
+            self.deliveries.insert(&WebhookDelivery {
+                webhook_id: webhook.id,
+                event: Some(event.to_string()),
+                payload: Some(payload.clone()),
+                status: Some(PENDING.to_string()),
+                attempts: Some(0),
+                created_at: Some(now),
+                next_attempt_at: Some(now),
+                ..WebhookDelivery::default()
+            }).await?;
+            queued += 1;
+        }
+        if queued > 0 {
+            self.wake.notify_one();
+        }
+        Ok(queued)
+    }
+
+    /// Sends a `ping` to `webhook` right away and only once, answering the logged delivery
+    pub async fn test_fire(&self, webhook: &Webhook) -> Result<WebhookDelivery, ApiError> {
+        let now = DateTime::now();
+        let mut delivery = WebhookDelivery {
+            webhook_id: webhook.id,
+            event: Some(PING.to_string()),
+            payload: Some(payload(PING, &doc! {"webhook_id": webhook.id})?),
+            status: Some(PENDING.to_string()),
+            attempts: Some(0),
+            created_at: Some(now),
+            // Never due, so `run` doesn't send it as well while it's attempted here
+            next_attempt_at: None,
+            ..WebhookDelivery::default()
+        };
+        // Logged first, so it's there even if the attempt can't be recorded
+        delivery.id = self.deliveries.insert(&delivery).await?.inserted_id.as_object_id();
+        self.attempt(delivery, webhook, 1).await
+    }
+
+    /// Sends what's pending whenever there's something new, and retries as they become due,
+    /// until the server shuts down. Check-ins are logged as deliveries when they're stored, see `check_in`.
+    pub async fn run(self, mut shutdown: Shutdown) {
+        loop {
+            select! {
+                _ = self.wake.notified() => {},
+                _ = sleep(POLL) => {},
+                _ = &mut shutdown => break,
+            }
+
+            if let Err(err) = self.deliver_due().await {
+                eprintln!("Can't make the webhook deliveries, {}", err.message());
+            }
+        }
+    }
+
+    /// Attempts every delivery that's due, a few at a time. One that can't be
+    /// made is logged and left for the next run, the others go on.
+    async fn deliver_due(&self) -> Result<(), ApiError> {
+        let due = self.deliveries.list(doc! {
+            "status": PENDING,
+            "next_attempt_at": {"$lte": DateTime::now()},
+        }).await?;
+
+        stream::iter(due).for_each_concurrent(CONCURRENT_DELIVERIES, |delivery| async move {
+            let id = delivery.id;
+            if let Err(err) = self.deliver(delivery).await {
+                eprintln!("Can't make the webhook delivery {}, {}", id.map(|id| id.to_hex()).unwrap_or_default(), err.message());
+            }
+        }).await;
+        Ok(())
+    }
+
+    async fn deliver(&self, delivery: WebhookDelivery) -> Result<(), ApiError> {
+        match self.webhooks.get(not_deleted(doc! {"_id": delivery.webhook_id})).await {
+            Ok(webhook) => {
+                self.attempt(delivery, &webhook, self.max_attempts).await?;
+            },
+            Err(ApiError::NotFound(_)) => {
+                self.record(WebhookDelivery {
+                    status: Some(FAILED.to_string()),
+                    error: Some(String::from("The webhook was deleted")),
+                    next_attempt_at: None,
+                    ..delivery
+                }).await?;
+            },
+            Err(err) => return Err(err)
+        }
+        Ok(())
+    }
+
+    /// Sends `delivery` to `webhook` and records how it went, scheduling
+    /// the next attempt unless it was the last of `max_attempts`
+    async fn attempt(&self, delivery: WebhookDelivery, webhook: &Webhook, max_attempts: u32) -> Result<WebhookDelivery, ApiError> {
+        let attempts = delivery.attempts.unwrap_or(0) + 1;
+        let now = DateTime::now();
+        let (response_status, error) = match self.send(webhook, &delivery).await {
+            Ok(status) if (200..300).contains(&status) => (Some(status), None),
+            Ok(status) => (Some(status), Some(format!("The webhook answered with {}", status))),
+            Err(err) => (None, Some(err))
+        };
+
+        let (status, next_attempt_at) = if error.is_none() {
+            (DELIVERED, None)
+        } else if attempts < i64::from(max_attempts) {
+            (PENDING, Some(DateTime::from_millis(now.timestamp_millis().saturating_add(self.backoff(attempts).as_millis() as i64))))
+        } else {
+            (FAILED, None)
+        };
+
+        self.record(WebhookDelivery {
+            status: Some(status.to_string()),
+            attempts: Some(attempts),
+            response_status: response_status.map(i64::from),
+            error,
+            next_attempt_at,
+            last_attempt_at: Some(now),
+            ..delivery
+        }).await
+    }
+
+    /// How long to wait after the `attempts`th failure
+    fn backoff(&self, attempts: i64) -> Duration {
+        let doublings = attempts.clamp(1, 17) - 1;
+        self.retry.saturating_mul(1 << doublings)
+    }
+
+    async fn send(&self, webhook: &Webhook, delivery: &WebhookDelivery) -> Result<u16, String> {
+        let url = webhook.url.as_deref().ok_or_else(|| String::from("The webhook has no URL"))?;
+        let body = delivery.payload.clone().unwrap_or_default();
+        let timestamp = unix_now();
+        let signature = sign(webhook.secret.as_deref().unwrap_or_default(), timestamp, &body);
+
+        let response = self.client.post(url)
+            .header("Content-Type", "application/json")
+            .header("X-Presency-Event", delivery.event.as_deref().unwrap_or_default())
+            .header("X-Presency-Delivery", delivery.id.map(|id| id.to_hex()).unwrap_or_default())
+            .header("X-Presency-Signature", format!("t={},v1={}", timestamp, signature))
+            .body(body)
+            .send().await
+            .map_err(|err| format!("The webhook couldn't be reached: {}", err))?;
+        Ok(response.status().as_u16())
+    }
+
+    /// Stores `delivery` as it's now, answering it as stored
+    async fn record(&self, delivery: WebhookDelivery) -> Result<WebhookDelivery, ApiError> {
+        let id = delivery.id;
+        self.deliveries.replace(doc! {"_id": id}, &WebhookDelivery { version: None, ..delivery }).await?;
+        self.deliveries.get(doc! {"_id": id}).await
+    }
+}
+
+fn payload<T: Serialize>(event: &str, data: &T) -> Result<String, ApiError> {
+    let created_at = DateTime::now().try_to_rfc3339_string()
+        .map_err(|err| ApiError::UnexpectedType(format!("The event's time can't be written: {}", err)))?;
+    serde_json::to_string(&Payload {
+        id: ObjectId::new().to_hex(),
+        event,
+        created_at,
+        data,
+    }).map_err(|err| ApiError::UnexpectedType(format!("The event can't be written: {}", err)))
+}
@@ -0,0 +1,68 @@
+use std::{io, pin::Pin};
+
+use futures::future::BoxFuture;
+use rocket::{data::{IoHandler, IoStream}, http::Status, request::{FromRequest, Outcome, Request}, response::{self, Responder, Response}};
+use tokio_tungstenite::{tungstenite::{handshake::derive_accept_key, protocol::Role}, WebSocketStream};
+use crate::models::ApiError;
+
+/// A WebSocket connection once the handshake is done
+pub type Socket = WebSocketStream<IoStream>;
+
+/// A request asking to be upgraded to a WebSocket (RFC 6455), refused otherwise
+pub struct WebSocket {
+    key: String,
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for WebSocket {
+    type Error = ApiError;
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let headers = request.headers();
+        let upgrade = headers.get_one("Upgrade").is_some_and(|upgrade| upgrade.eq_ignore_ascii_case("websocket"));
+        let version = headers.get_one("Sec-WebSocket-Version").is_some_and(|version| version.trim() == "13");
+        match headers.get_one("Sec-WebSocket-Key") {
+            Some(key) if upgrade && version => Outcome::Success(WebSocket { key: key.trim().to_string() }),
+            _ => Outcome::Error((Status::BadRequest, ApiError::BadParams(String::from("Connect with a WebSocket (version 13) here!"))))
+        }
+    }
+}
+
+impl WebSocket {
+    /// Accepts the connection, `handler` is run with it once it's upgraded
+    pub fn channel<'o, F>(self, handler: F) -> Channel<'o>
+    where
+        F: FnOnce(Socket) -> BoxFuture<'o, ()> + Send + 'o
+    {
+        Channel {
+            key: self.key,
+            handler: Box::new(handler)
+        }
+    }
+}
+
+/// The answer to a `WebSocket` request, see `WebSocket::channel`
+pub struct Channel<'o> {
+    key: String,
+    handler: Box<dyn FnOnce(Socket) -> BoxFuture<'o, ()> + Send + 'o>,
+}
+
+impl<'r, 'o: 'r> Responder<'r, 'o> for Channel<'o> {
+    fn respond_to(self, _request: &'r Request<'_>) -> response::Result<'o> {
+        // Rocket answers with 101 Switching Protocols itself once it sees the upgrade
+        Response::build()
+            .raw_header("Sec-WebSocket-Accept", derive_accept_key(self.key.as_bytes()))
+            .upgrade("websocket", self)
+            .ok()
+    }
+}
+
+#[rocket::async_trait]
+impl IoHandler for Channel<'_> {
+    async fn io(self: Pin<Box<Self>>, io: IoStream) -> io::Result<()> {
+        let channel = Pin::into_inner(self);
+        let socket = WebSocketStream::from_raw_socket(io, Role::Server, None).await;
+        (channel.handler)(socket).await;
+        Ok(())
+    }
+}
@@ -0,0 +1,283 @@
+mod roster;
+mod report;
+
+use std::{io::BufRead, path::PathBuf};
+
+use clap::{Parser, Subcommand};
+use mongodb::bson::doc;
+use mi_presency_api::config::Config;
+use mi_presency_api::database::{AuditContext, Database, Repository, backup::{self, Manifest}, migrations::Migrator, seeder::{self, SeedOptions}};
+use mi_presency_api::models::{ApiError, Teacher, utils::{not_deleted, parse_time}, validation::validate};
+
+/// Setup and upkeep of MI Presency API, done straight on its database without starting the server.
+///
+/// Reads the same configuration as the server: Rocket.toml, ROCKET_ environment variables and .env.
+#[derive(Parser)]
+#[command(name = "mi-presency-admin", version)]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Creates a teacher, e.g. the first one of a new school
+    CreateTeacher {
+        #[arg(long)]
+        name: String,
+        /// Read from standard input when not given, so it stays out of the shell history
+        #[arg(long)]
+        pass: Option<String>,
+    },
+    /// Adds the students of a CSV roster with the columns name, card_id and class_id.
+    /// Students whose card is already known are updated instead.
+    ImportRoster {
+        file: PathBuf,
+        /// The class of the rows without a class_id
+        #[arg(long)]
+        class_id: Option<String>,
+        /// Only checks the roster and tells what importing it would do
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Writes the students as a CSV roster, the way import-roster reads it
+    ExportRoster {
+        /// Only the students of this class
+        #[arg(long)]
+        class_id: Option<String>,
+        /// Written to standard output when not given
+        #[arg(long, short)]
+        output: Option<PathBuf>,
+    },
+    /// Applies or undoes schema migrations
+    Migrate {
+        #[command(subcommand)]
+        action: MigrateAction,
+    },
+    /// Drops the MongoDB indexes of the API and creates them again
+    RebuildIndexes,
+    /// Fills an empty database with fake but plausible data, the same for the same seed and --until
+    Seed {
+        #[arg(long)]
+        seed: u64,
+        #[arg(long, default_value_t = 3)]
+        classes: usize,
+        #[arg(long, default_value_t = 25)]
+        students_per_class: usize,
+        #[arg(long, default_value_t = 8)]
+        teachers: usize,
+        /// How many days back the lessons go
+        #[arg(long, default_value_t = 60)]
+        days: u32,
+        /// The RFC 3339 time of the last day with lessons, 2024-06-28 when not given
+        #[arg(long)]
+        until: Option<String>,
+    },
+    /// Writes every collection into a backup archive
+    Backup {
+        /// Saved in the current directory under the archive's own name when not given
+        #[arg(long, short)]
+        output: Option<PathBuf>,
+    },
+    /// Loads a backup archive into an empty database
+    Restore {
+        file: PathBuf,
+        /// Only checks the archive and that the database is empty
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Prints how many of the lessons of a class each of its students attended
+    Report {
+        #[arg(long)]
+        class_id: String,
+        /// Only lessons checked in to from this RFC 3339 time on
+        #[arg(long)]
+        from: Option<String>,
+        /// Only lessons checked in to up to this RFC 3339 time
+        #[arg(long)]
+        to: Option<String>,
+    },
+}
+
+#[derive(Subcommand)]
+enum MigrateAction {
+    /// Lists every migration and whether it's applied
+    Status,
+    /// Applies the pending migrations
+    Up {
+        /// Stops after the migration with this id
+        #[arg(long)]
+        to: Option<i64>,
+        /// Only tells how many documents each migration would change
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Undoes the applied migrations coming after the one with this id
+    Down {
+        #[arg(long)]
+        to: i64,
+        /// Only tells how many documents each migration would change
+        #[arg(long)]
+        dry_run: bool,
+    },
+}
+
+/// Changes made by the tool are audited as an admin's, under the command that made them
+fn audit_context(command: &str) -> AuditContext {
+    AuditContext {
+        actor: String::from("admin"),
+        actor_id: None,
+        route: format!("mi-presency-admin {}", command),
+        client_ip: None,
+    }
+}
+
+async fn create_teacher(db: &Database, name: String, pass: Option<String>) -> Result<(), ApiError> {
+    let pass = match pass {
+        Some(pass) => pass,
+        None => {
+            eprint!("Pass: ");
+            let mut pass = String::new();
+            std::io::stdin().lock().read_line(&mut pass)
+                .map_err(|err| ApiError::BadParams(format!("The pass can't be read: {}", err)))?;
+            pass.trim_end_matches(['\r', '\n']).to_string()
+        }
+    };
+
+    let teacher = validate(Teacher {
+        id: None,
+        name: Some(name),
+        pass: Some(pass),
+        deleted_at: None,
+        version: None,
+    }, true)?.with_hashed_pass()?;
+    // Teachers sign in with their name, two of the same name couldn't be told apart
+    if db.teacher_database.count(not_deleted(doc! {"name": teacher.name.clone()})).await? > 0 {
+        return Err(ApiError::Conflict(format!("A teacher called {} already exists!", teacher.name.unwrap_or_default())));
+    }
+
+    let audit = audit_context("create-teacher");
+    let result = db.audited(db.teacher_database.as_ref(), &audit).insert(&teacher).await?;
+    let id = result.inserted_id.as_object_id().map(|id| id.to_hex()).unwrap_or_default();
+    println!("Created teacher {} with _id {}", teacher.name.unwrap_or_default(), id);
+    Ok(())
+}
+
+async fn migrate(db: &Database, action: MigrateAction) -> Result<(), ApiError> {
+    let mut migrator = Migrator::new(db);
+    let reports = match action {
+        MigrateAction::Status => {
+            for (migration, applied_at) in migrator.status().await? {
+                match applied_at {
+                    Some(applied_at) => println!("{:04} {} applied at {}", migration.id, migration.name, applied_at.try_to_rfc3339_string().unwrap_or_default()),
+                    None => println!("{:04} {} pending", migration.id, migration.name)
+                }
+            }
+            return Ok(());
+        },
+        MigrateAction::Up { to, dry_run } => migrator.up(to, dry_run).await?,
+        MigrateAction::Down { to, dry_run } => migrator.down(to, dry_run).await?,
+    };
+
+    if reports.is_empty() {
+        println!("Nothing to migrate");
+    }
+    for report in reports {
+        println!("{}", report);
+    }
+    Ok(())
+}
+
+async fn rebuild_indexes(config: &Config) -> Result<(), ApiError> {
+    for (collection, count) in Database::rebuild_indexes(config).await? {
+        println!("{:?}: {} indexes created", collection, count);
+    }
+    Ok(())
+}
+
+async fn seed(db: &Database, options: SeedOptions) -> Result<(), ApiError> {
+    let seeded = seeder::seed(db, &options).await?;
+    println!("Seeded {} classes, {} students and {} attendances", seeded.classes.len(), seeded.students, seeded.attendances);
+    for class_id in seeded.classes {
+        println!("class {}", class_id);
+    }
+    for name in seeded.teachers {
+        println!("teacher {} with pass {}", name, seeder::TEACHER_PASS);
+    }
+    Ok(())
+}
+
+fn print_manifest(manifest: &Manifest) {
+    for entry in &manifest.collections {
+        println!("{}: {} documents", entry.collection, entry.count);
+    }
+}
+
+async fn write_backup(db: &Database, output: Option<PathBuf>) -> Result<(), ApiError> {
+    let (manifest, archive) = backup::backup(db).await?;
+    let output = output.unwrap_or_else(|| PathBuf::from(manifest.file_name()));
+    std::fs::write(&output, archive)
+        .map_err(|err| ApiError::BadParams(format!("{} can't be written: {}", output.display(), err)))?;
+
+    print_manifest(&manifest);
+    println!("Backed up to {}", output.display());
+    Ok(())
+}
+
+async fn restore(db: &Database, file: PathBuf, dry_run: bool) -> Result<(), ApiError> {
+    let archive = std::fs::read(&file)
+        .map_err(|err| ApiError::BadParams(format!("{} can't be read: {}", file.display(), err)))?;
+    let manifest = backup::restore(db, &archive, dry_run).await?;
+
+    print_manifest(&manifest);
+    if dry_run {
+        println!("{} can be restored (dry run, nothing was written)", file.display());
+    } else {
+        println!("Restored the backup made at {}", manifest.created_at);
+    }
+    Ok(())
+}
+
+async fn run(config: &Config, command: Command) -> Result<(), ApiError> {
+    let db = Database::connect(config).await?;
+    match command {
+        Command::CreateTeacher { name, pass } => create_teacher(&db, name, pass).await,
+        Command::ImportRoster { file, class_id, dry_run } => roster::import(&db, &file, class_id.as_deref(), dry_run).await,
+        Command::ExportRoster { class_id, output } => roster::export(&db, class_id.as_deref(), output.as_deref()).await,
+        Command::Migrate { action } => migrate(&db, action).await,
+        Command::Report { class_id, from, to } => report::print(&db, &class_id, from.as_deref(), to.as_deref()).await,
+        Command::RebuildIndexes => rebuild_indexes(config).await,
+        Command::Seed { seed: value, classes, students_per_class, teachers, days, until } => seed(&db, SeedOptions {
+            seed: value,
+            classes,
+            students_per_class,
+            teachers,
+            days,
+            until: match until {
+                Some(until) => parse_time(&until, "--until")?,
+                None => seeder::DEFAULT_UNTIL
+            },
+        }).await,
+        Command::Backup { output } => write_backup(&db, output).await,
+        Command::Restore { file, dry_run } => restore(&db, file, dry_run).await,
+    }
+}
+
+#[rocket::main]
+async fn main() {
+    let cli = Cli::parse();
+    let config = Config::load().unwrap_or_else(|err| {
+        eprintln!("Can't start mi-presency-admin, {}", err);
+        std::process::exit(1);
+    });
+
+    if let Err(err) = run(&config, cli.command).await {
+        eprintln!("{}", err.message());
+        if let ApiError::Validation(violations) = &err {
+            for violation in violations {
+                eprintln!("  {}: {}", violation.field, violation.message);
+            }
+        }
+        std::process::exit(1);
+    }
+}
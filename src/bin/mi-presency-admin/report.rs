@@ -0,0 +1,57 @@
+use std::collections::{HashMap, HashSet};
+
+use mongodb::bson::{doc, oid::ObjectId, Document};
+use mi_presency_api::database::{Database, ListOptions};
+use mi_presency_api::models::{ApiError, utils::{not_deleted, parse_oid, parse_time}};
+
+/// Prints, for every student of the class, how many of its lessons they checked in to.
+/// A lesson counts as held once anyone of the class checked in to it.
+pub async fn print(db: &Database, class_id: &str, from: Option<&str>, to: Option<&str>) -> Result<(), ApiError> {
+    let class_id = parse_oid(class_id)?;
+
+    let mut checked_in_at = Document::new();
+    if let Some(from) = from {
+        checked_in_at.insert("$gte", parse_time(from, "--from")?);
+    }
+    if let Some(to) = to {
+        checked_in_at.insert("$lte", parse_time(to, "--to")?);
+    }
+    let mut filter = doc! {"class_id": class_id};
+    if !checked_in_at.is_empty() {
+        filter.insert("checked_in_at", checked_in_at);
+    }
+
+    let mut lessons: HashSet<String> = HashSet::new();
+    let mut attended: HashMap<ObjectId, HashSet<String>> = HashMap::new();
+    for attendance in db.attendance_database.list(filter).await? {
+        if let (Some(student_id), Some(lesson)) = (attendance.student_id, attendance.lesson) {
+            lessons.insert(lesson.clone());
+            attended.entry(student_id).or_default().insert(lesson);
+        }
+    }
+
+    let options = ListOptions {
+        sort: Some(doc! {"name": 1, "_id": 1}),
+        ..ListOptions::default()
+    };
+    let students = db.student_database.list_page(not_deleted(doc! {"class_id": class_id}), options).await?.items;
+
+    println!("Class {}: {} students, {} lessons", class_id, students.len(), lessons.len());
+    let width = students.iter().filter_map(|student| student.name.as_ref()).map(|name| name.chars().count()).max().unwrap_or(0).max(4);
+    println!("{:<width$}  {:<20}  {:>8}  {:>4}", "name", "card_id", "attended", "rate");
+    for student in students {
+        let count = student.id.and_then(|id| attended.get(&id)).map_or(0, HashSet::len);
+        let rate = match lessons.len() {
+            0 => String::from("-"),
+            total => format!("{}%", count * 100 / total)
+        };
+        println!(
+            "{:<width$}  {:<20}  {:>8}  {:>4}",
+            student.name.unwrap_or_default(),
+            student.card_id.unwrap_or_default(),
+            format!("{}/{}", count, lessons.len()),
+            rate
+        );
+    }
+    Ok(())
+}
@@ -0,0 +1,152 @@
+use std::{collections::HashSet, io, path::Path};
+
+use mongodb::bson::{doc, Document};
+use serde::{Deserialize, Serialize};
+use mi_presency_api::database::{Database, ListOptions, Repository};
+use mi_presency_api::models::{ApiError, Student, utils::{not_deleted, parse_oid}, validation::{normalized, validate, Violation}};
+use crate::audit_context;
+
+/// One line of a roster file
+#[derive(Debug, Serialize, Deserialize)]
+struct RosterRow {
+    name: Option<String>,
+    card_id: Option<String>,
+    class_id: Option<String>,
+}
+
+fn csv_error(path: &Path, err: csv::Error) -> ApiError {
+    ApiError::BadParams(format!("{} can't be read: {}", path.display(), err))
+}
+
+/// Reads every row of the roster at `path` as a student. Nothing is returned
+/// unless every row is valid, the violations are listed by line otherwise.
+fn read(path: &Path, class_id: Option<&str>) -> Result<Vec<Student>, ApiError> {
+    let mut reader = csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .from_path(path)
+        .map_err(|err| csv_error(path, err))?;
+
+    let mut students: Vec<Student> = Vec::new();
+    let mut violations: Vec<Violation> = Vec::new();
+    let mut card_ids: HashSet<String> = HashSet::new();
+    for (index, row) in reader.deserialize::<RosterRow>().enumerate() {
+        // The header is line 1
+        let line = format!("line {}", index + 2);
+        let row = row.map_err(|err| csv_error(path, err))?;
+
+        let class_id = match row.class_id.as_deref().or(class_id) {
+            Some(class_id) => match parse_oid(class_id) {
+                Ok(class_id) => Some(class_id),
+                Err(err) => {
+                    violations.push(Violation::new(&format!("{}.class_id", line), err.message()));
+                    continue;
+                }
+            },
+            None => None
+        };
+        // Compared the way they're stored, `0a1b2c3d` is the card `0A1B2C3D`
+        if let Some(card_id) = &row.card_id {
+            if !card_ids.insert(normalized::<Student>("card_id", card_id)) {
+                violations.push(Violation::new(&format!("{}.card_id", line), "is on an earlier line already"));
+                continue;
+            }
+        }
+
+        let student = Student {
+            id: None,
+            name: row.name,
+            card_id: row.card_id,
+            class_id,
+            token: None,
+            deleted_at: None,
+            version: None,
+        };
+        match validate(student, true) {
+            Ok(student) => students.push(student),
+            Err(ApiError::Validation(found)) => violations.extend(
+                found.into_iter().map(|violation| Violation::new(&format!("{}.{}", line, violation.field), &violation.message))
+            ),
+            Err(err) => return Err(err)
+        }
+    }
+
+    if violations.is_empty() {
+        Ok(students)
+    } else {
+        Err(ApiError::Validation(violations))
+    }
+}
+
+/// Adds the students of the roster at `path`, or updates them when a student
+/// with the same card is already stored
+pub async fn import(db: &Database, path: &Path, class_id: Option<&str>, dry_run: bool) -> Result<(), ApiError> {
+    let students = read(path, class_id)?;
+
+    let audit = audit_context("import-roster");
+    let repository = db.audited(db.student_database.as_ref(), &audit);
+    let (mut added, mut updated) = (0, 0);
+    for student in students {
+        let existing = match &student.card_id {
+            Some(card_id) => match repository.get(not_deleted(doc! {"card_id": card_id})).await {
+                Ok(existing) => existing.id,
+                Err(ApiError::NotFound(_)) => None,
+                Err(err) => return Err(err)
+            },
+            None => None
+        };
+
+        match existing {
+            Some(id) => {
+                if !dry_run {
+                    repository.update(doc! {"_id": id}, &student).await?;
+                }
+                updated += 1;
+            },
+            None => {
+                if !dry_run {
+                    repository.insert(&student).await?;
+                }
+                added += 1;
+            }
+        }
+    }
+
+    print!("{} students added, {} updated", added, updated);
+    if dry_run {
+        print!(" (dry run, nothing was written)");
+    }
+    println!();
+    Ok(())
+}
+
+/// Writes the students, of one class or all of them, as a roster sorted by name
+pub async fn export(db: &Database, class_id: Option<&str>, output: Option<&Path>) -> Result<(), ApiError> {
+    let mut filter = Document::new();
+    if let Some(class_id) = class_id {
+        filter.insert("class_id", parse_oid(class_id)?);
+    }
+    let options = ListOptions {
+        sort: Some(doc! {"name": 1, "_id": 1}),
+        ..ListOptions::default()
+    };
+    let students = db.student_database.list_page(not_deleted(filter), options).await?.items;
+
+    let written = |err: csv::Error| ApiError::BadParams(format!("The roster can't be written: {}", err));
+    let output: Box<dyn io::Write> = match output {
+        Some(output) => Box::new(std::fs::File::create(output)
+            .map_err(|err| ApiError::BadParams(format!("{} can't be created: {}", output.display(), err)))?),
+        None => Box::new(io::stdout()),
+    };
+    // The header is written even for an empty roster, so it can be filled in and imported
+    let mut writer = csv::WriterBuilder::new().has_headers(false).from_writer(output);
+    writer.write_record(["name", "card_id", "class_id"]).map_err(written)?;
+    for student in students {
+        writer.serialize(RosterRow {
+            name: student.name,
+            card_id: student.card_id,
+            class_id: student.class_id.map(|class_id| class_id.to_hex()),
+        }).map_err(written)?;
+    }
+    writer.flush().map_err(|err| written(err.into()))?;
+    Ok(())
+}
@@ -0,0 +1,172 @@
+use rocket::figment::{self, error::Kind};
+use serde::Deserialize;
+
+/// Everything the server needs to know before it can start.
+///
+/// Values are read the same way Rocket reads its own settings: from `Rocket.toml`
+/// and from `ROCKET_`-prefixed environment variables (e.g. `ROCKET_MONGODB_URI`),
+/// which may also be put in a `.env` file. The port is Rocket's own `port` setting.
+#[derive(Debug, Deserialize)]
+pub struct Config {
+    /// Where the data lives, MongoDB unless told otherwise
+    #[serde(default)]
+    pub storage: Storage,
+    /// Required when `storage` is `mongodb`
+    #[serde(default)]
+    pub mongodb_uri: String,
+    /// The file holding everything when `storage` is `sqlite`
+    #[serde(default = "default_sqlite_path")]
+    pub sqlite_path: String,
+    pub database_name: String,
+    #[serde(default = "default_student_collection")]
+    pub student_collection: String,
+    #[serde(default = "default_teacher_collection")]
+    pub teacher_collection: String,
+    #[serde(default = "default_attendance_collection")]
+    pub attendance_collection: String,
+    #[serde(default = "default_audit_collection")]
+    pub audit_collection: String,
+    /// Where the applied migrations are tracked
+    #[serde(default = "default_migration_collection")]
+    pub migration_collection: String,
+    #[serde(default = "default_device_collection")]
+    pub device_collection: String,
+    #[serde(default = "default_webhook_collection")]
+    pub webhook_collection: String,
+    /// Where every webhook delivery and its attempts are logged
+    #[serde(default = "default_webhook_delivery_collection")]
+    pub webhook_delivery_collection: String,
+    /// Whether pending migrations are applied before the server starts,
+    /// otherwise they're left to `mi-presency-admin migrate`
+    #[serde(default = "default_migrate_on_startup")]
+    pub migrate_on_startup: bool,
+    /// Key used to sign check-in QR payloads
+    pub qr_secret: String,
+    /// How long a generated check-in QR payload stays valid
+    #[serde(default = "default_qr_ttl_seconds")]
+    pub qr_ttl_seconds: u64,
+    /// How long soft deleted students, teachers, devices and webhooks are kept before an admin can purge them
+    #[serde(default = "default_deleted_retention_days")]
+    pub deleted_retention_days: u64,
+    /// How many times a webhook delivery is attempted before it's given up as failed
+    #[serde(default = "default_webhook_max_attempts")]
+    pub webhook_max_attempts: u32,
+    /// How long a failed webhook delivery waits before it's attempted again,
+    /// doubling after every further failure
+    #[serde(default = "default_webhook_retry_seconds")]
+    pub webhook_retry_seconds: u64,
+    /// How long after the start of a lesson a check-in still isn't late, see `POST /lesson/close`
+    #[serde(default = "default_late_after_minutes")]
+    pub late_after_minutes: i64,
+    /// Fills an empty database with fake data from this seed at startup, for development.
+    /// A database with data already is left as it is.
+    #[serde(default)]
+    pub seed: Option<u64>,
+    /// Bearer token that makes a request an admin one (e.g. for `GET /audit`),
+    /// without it no request is
+    #[serde(default)]
+    pub admin_token: Option<String>,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Storage {
+    #[default]
+    MongoDB,
+    /// Kept in memory only and lost on shutdown, for demos and tests
+    Memory,
+    /// A single SQLite file, for schools without any database server
+    Sqlite,
+}
+
+fn default_sqlite_path() -> String {
+    String::from("mi-presency.sqlite3")
+}
+
+fn default_student_collection() -> String {
+    String::from("students")
+}
+
+fn default_teacher_collection() -> String {
+    String::from("teachers")
+}
+
+fn default_attendance_collection() -> String {
+    String::from("attendances")
+}
+
+fn default_audit_collection() -> String {
+    String::from("audit_log")
+}
+
+fn default_migration_collection() -> String {
+    String::from("migrations")
+}
+
+fn default_device_collection() -> String {
+    String::from("devices")
+}
+
+fn default_webhook_collection() -> String {
+    String::from("webhooks")
+}
+
+fn default_webhook_delivery_collection() -> String {
+    String::from("webhook_deliveries")
+}
+
+fn default_webhook_max_attempts() -> u32 {
+    6
+}
+
+fn default_webhook_retry_seconds() -> u64 {
+    30
+}
+
+fn default_late_after_minutes() -> i64 {
+    10
+}
+
+fn default_migrate_on_startup() -> bool {
+    true
+}
+
+fn default_deleted_retention_days() -> u64 {
+    30
+}
+
+fn default_qr_ttl_seconds() -> u64 {
+    60
+}
+
+impl Config {
+    pub fn load() -> Result<Self, String> {
+        // A missing .env is fine, the values may come from the real environment
+        dotenv::dotenv().ok();
+
+        let config = rocket::Config::figment()
+            .extract::<Config>()
+            .map_err(|err| Self::describe(&err))?;
+
+        if config.storage == Storage::MongoDB && config.mongodb_uri.is_empty() {
+            return Err(Self::missing("mongodb_uri"));
+        }
+
+        Ok(config)
+    }
+
+    fn missing(field: &str) -> String {
+        format!(
+            "missing configuration value `{}`: set it in Rocket.toml or as ROCKET_{}",
+            field,
+            field.to_uppercase()
+        )
+    }
+
+    fn describe(err: &figment::Error) -> String {
+        match &err.kind {
+            Kind::MissingField(field) => Self::missing(field),
+            _ => format!("invalid configuration: {}", err),
+        }
+    }
+}
@@ -0,0 +1,189 @@
+use mongodb::bson::{doc, oid::ObjectId, Bson, DateTime, Document};
+use crate::models::{ApiError, AuditEntry, Model};
+use crate::database::repository::{Repository, ListOptions, Page, Patch, InsertResult, UpdateResult, DeleteResult, model_to_document};
+
+/// Who made a request and how, copied into every audit entry it causes
+#[derive(Debug, Clone)]
+pub struct AuditContext {
+    /// `"admin"`, `"teacher"`, `"device"`, `"student"` or `"anonymous"`
+    pub actor: String,
+    pub actor_id: Option<ObjectId>,
+    /// Method and URI of the request
+    pub route: String,
+    pub client_ip: Option<String>,
+}
+
+/// `Repository` recording every insert, update and delete made through it in the audit log,
+/// with a snapshot of each changed document before and after the change. Reads go straight through.
+pub struct AuditedRepository<'a, T> {
+    repository: &'a dyn Repository<T>,
+    log: &'a dyn Repository<AuditEntry>,
+    context: &'a AuditContext,
+}
+
+impl<'a, T: Model> AuditedRepository<'a, T> {
+    pub fn new(repository: &'a dyn Repository<T>, log: &'a dyn Repository<AuditEntry>, context: &'a AuditContext) -> Self {
+        Self {
+            repository,
+            log,
+            context
+        }
+    }
+
+    /// `document` without the values that must never end up in the log,
+    /// only their presence is kept
+    fn redact(mut document: Document) -> Document {
+        for field in T::SECRET_FIELDS {
+            if document.contains_key(*field) {
+                document.insert(*field, "[redacted]");
+            }
+        }
+        document
+    }
+
+    /// Snapshots of every document matching `filter`, together with their `_id`
+    async fn snapshots(&self, filter: Document) -> Result<Vec<(Bson, Document)>, ApiError> {
+        let mut snapshots: Vec<(Bson, Document)> = Vec::new();
+        for data in self.repository.list(filter).await? {
+            let snapshot = model_to_document(&data)?;
+            if let Some(id) = snapshot.get("_id") {
+                snapshots.push((id.clone(), snapshot));
+            }
+        }
+        Ok(snapshots)
+    }
+
+    /// The snapshot of the document with `id`, `None` once it's gone
+    async fn current(&self, id: &Bson) -> Result<Option<Document>, ApiError> {
+        match self.repository.get(doc! {"_id": id.clone()}).await {
+            Ok(data) => Ok(Some(model_to_document(&data)?)),
+            Err(ApiError::NotFound(_)) => Ok(None),
+            Err(err) => Err(err)
+        }
+    }
+
+    async fn record(&self, action: &str, entity_id: Bson, before: Option<Document>, after: Option<Document>) -> Result<(), ApiError> {
+        self.log.insert(&AuditEntry {
+            id: None,
+            action: Some(action.to_string()),
+            entity: Some(T::NAME.to_string()),
+            entity_id: Some(entity_id),
+            before: before.map(Self::redact),
+            after: after.map(Self::redact),
+            actor: Some(self.context.actor.clone()),
+            actor_id: self.context.actor_id,
+            route: Some(self.context.route.clone()),
+            client_ip: self.context.client_ip.clone(),
+            at: Some(DateTime::now()),
+        }).await?;
+        Ok(())
+    }
+
+    async fn patch_as(&self, action: &str, filter: Document, patch: &Patch) -> Result<UpdateResult, ApiError> {
+        let before = self.snapshots(filter.clone()).await?;
+        let result = self.repository.patch(filter, patch).await?;
+        self.record_changes(action, before).await?;
+        Ok(result)
+    }
+
+    /// Marks every document matching `filter` that isn't deleted yet as deleted.
+    /// They stay stored, and can be restored, until `purge` removes them.
+    pub async fn soft_delete(&self, mut filter: Document) -> Result<UpdateResult, ApiError> {
+        filter.insert("deleted_at", Bson::Null);
+        let patch = Patch {
+            set: doc! {"deleted_at": DateTime::now()},
+            unset: Vec::new()
+        };
+        self.patch_as("delete", filter, &patch).await
+    }
+
+    /// Undoes `soft_delete` for every deleted document matching `filter`
+    pub async fn restore(&self, mut filter: Document) -> Result<UpdateResult, ApiError> {
+        filter.insert("deleted_at", doc! {"$ne": Bson::Null});
+        let patch = Patch {
+            set: Document::new(),
+            unset: vec![String::from("deleted_at")]
+        };
+        self.patch_as("restore", filter, &patch).await
+    }
+
+    /// Removes for good every document soft deleted before `deleted_before`
+    pub async fn purge(&self, deleted_before: DateTime) -> Result<DeleteResult, ApiError> {
+        let filter = doc! {"deleted_at": {"$lt": deleted_before}};
+        let before = self.snapshots(filter.clone()).await?;
+        let result = self.repository.delete_many(filter).await?;
+        self.record_changes("purge", before).await?;
+        Ok(result)
+    }
+
+    /// Records the documents in `before` that a change left different
+    async fn record_changes(&self, action: &str, before: Vec<(Bson, Document)>) -> Result<(), ApiError> {
+        for (id, before) in before {
+            let after = self.current(&id).await?;
+            if after.as_ref() != Some(&before) {
+                self.record(action, id, Some(before), after).await?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[rocket::async_trait]
+impl<T: Model> Repository<T> for AuditedRepository<'_, T> {
+    async fn get(&self, filter: Document) -> Result<T, ApiError> {
+        self.repository.get(filter).await
+    }
+
+    async fn list(&self, filter: Document) -> Result<Vec<T>, ApiError> {
+        self.repository.list(filter).await
+    }
+
+    async fn list_page(&self, filter: Document, options: ListOptions) -> Result<Page<T>, ApiError> {
+        self.repository.list_page(filter, options).await
+    }
+
+    async fn count(&self, filter: Document) -> Result<u64, ApiError> {
+        self.repository.count(filter).await
+    }
+
+    async fn insert(&self, data: &T) -> Result<InsertResult, ApiError> {
+        let result = self.repository.insert(data).await?;
+        let after = self.current(&result.inserted_id).await?;
+        self.record("insert", result.inserted_id.clone(), None, after).await?;
+        Ok(result)
+    }
+
+    async fn update(&self, filter: Document, data: &T) -> Result<UpdateResult, ApiError> {
+        let before = self.snapshots(filter.clone()).await?;
+        let result = self.repository.update(filter, data).await?;
+        self.record_changes("update", before).await?;
+        Ok(result)
+    }
+
+    async fn patch(&self, filter: Document, patch: &Patch) -> Result<UpdateResult, ApiError> {
+        self.patch_as("update", filter, patch).await
+    }
+
+    async fn replace(&self, filter: Document, data: &T) -> Result<UpdateResult, ApiError> {
+        // Narrowed to the one document that gets replaced, so the snapshot is of that one
+        let before = match self.snapshots(filter).await?.into_iter().next() {
+            Some(before) => before,
+            None => return Ok(UpdateResult { matched_count: 0, modified_count: 0 })
+        };
+        // Only while it's still as snapshotted, a change made in between isn't overwritten
+        let mut filter = doc! {"_id": before.0.clone()};
+        filter.insert("version", before.1.get("version").cloned().unwrap_or(Bson::Null));
+        let result = self.repository.replace(filter, data).await?;
+        if result.matched_count > 0 {
+            self.record_changes("replace", vec![before]).await?;
+        }
+        Ok(result)
+    }
+
+    async fn delete_many(&self, filter: Document) -> Result<DeleteResult, ApiError> {
+        let before = self.snapshots(filter.clone()).await?;
+        let result = self.repository.delete_many(filter).await?;
+        self.record_changes("delete", before).await?;
+        Ok(result)
+    }
+}
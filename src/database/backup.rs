@@ -0,0 +1,248 @@
+use std::{collections::{HashMap, HashSet}, io::Read};
+
+use flate2::{read::GzDecoder, write::GzEncoder, Compression};
+use mongodb::bson::{doc, Bson, DateTime, Document};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use crate::models::{ApiError, AppliedMigration, Attendance, AuditEntry, Device, Model, Student, Teacher, Webhook, WebhookDelivery};
+use crate::database::{Collection, Database, migrations::MIGRATIONS, repository::document_to_model};
+
+/// The archive layout written by `backup`, moved up whenever it changes
+pub const FORMAT_VERSION: u32 = 1;
+
+/// What a backup holds, stored as `manifest.json` next to one JSON Lines file per collection
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Manifest {
+    pub format_version: u32,
+    /// The version of the API that wrote the archive
+    pub api_version: String,
+    /// RFC 3339
+    pub created_at: String,
+    pub collections: Vec<ManifestEntry>,
+}
+
+impl Manifest {
+    /// What the archive is called when saved, e.g. `mi-presency-2024-01-31T07-00-00Z.tar.gz`
+    pub fn file_name(&self) -> String {
+        let created_at: String = self.created_at
+            .chars()
+            .take("2024-01-31T07:00:00".len())
+            .map(|character| if character == ':' { '-' } else { character })
+            .collect();
+        format!("mi-presency-{}Z.tar.gz", created_at)
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ManifestEntry {
+    /// See `Collection::key`
+    pub collection: String,
+    pub file: String,
+    /// How many documents, and so lines, the file has
+    pub count: u64,
+    /// SHA-256 of the whole file, in hexadecimal
+    pub sha256: String,
+}
+
+fn unreadable(err: impl std::fmt::Display) -> ApiError {
+    ApiError::BadParams(format!("The archive can't be read: {}", err))
+}
+
+fn unwritable(err: impl std::fmt::Display) -> ApiError {
+    ApiError::Database(format!("The archive can't be written: {}", err))
+}
+
+fn sha256(bytes: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(bytes))
+}
+
+/// Writes every collection into a gzipped tar archive. Documents are kept as
+/// canonical extended JSON, so every BSON type comes back as it was.
+pub async fn backup(db: &Database) -> Result<(Manifest, Vec<u8>), ApiError> {
+    let mut files: Vec<(String, Vec<u8>)> = Vec::new();
+    let mut entries: Vec<ManifestEntry> = Vec::new();
+    for collection in Collection::ALL {
+        let mut lines: Vec<u8> = Vec::new();
+        let mut count = 0;
+        for document in db.documents(collection).list(Document::new()).await? {
+            let json = serde_json::to_string(&Bson::Document(document).into_canonical_extjson()).map_err(unwritable)?;
+            lines.extend_from_slice(json.as_bytes());
+            lines.push(b'\n');
+            count += 1;
+        }
+
+        let file = format!("{}.jsonl", collection.key());
+        entries.push(ManifestEntry {
+            collection: collection.key().to_string(),
+            file: file.clone(),
+            count,
+            sha256: sha256(&lines),
+        });
+        files.push((file, lines));
+    }
+
+    let manifest = Manifest {
+        format_version: FORMAT_VERSION,
+        api_version: env!("CARGO_PKG_VERSION").to_string(),
+        created_at: DateTime::now().try_to_rfc3339_string().map_err(unwritable)?,
+        collections: entries,
+    };
+    let manifest_json = serde_json::to_vec_pretty(&manifest).map_err(unwritable)?;
+
+    let mut archive = tar::Builder::new(GzEncoder::new(Vec::new(), Compression::default()));
+    let modified = DateTime::now().timestamp_millis() / 1000;
+    for (path, bytes) in std::iter::once((String::from("manifest.json"), manifest_json)).chain(files) {
+        let mut header = tar::Header::new_gnu();
+        header.set_size(bytes.len() as u64);
+        header.set_mode(0o644);
+        header.set_mtime(modified as u64);
+        header.set_cksum();
+        archive.append_data(&mut header, &path, bytes.as_slice()).map_err(unwritable)?;
+    }
+    let archive = archive.into_inner().and_then(|encoder| encoder.finish()).map_err(unwritable)?;
+
+    Ok((manifest, archive))
+}
+
+/// Checks that `document` reads as the model its collection holds
+fn check_model(collection: Collection, document: &Document) -> Result<(), ApiError> {
+    fn read<M: Model>(document: &Document) -> Result<(), ApiError> {
+        document_to_model::<M>(document.clone()).map(|_| ())
+    }
+    match collection {
+        Collection::Students => read::<Student>(document),
+        Collection::Teachers => read::<Teacher>(document),
+        Collection::Attendances => read::<Attendance>(document),
+        Collection::AuditLog => read::<AuditEntry>(document),
+        Collection::Migrations => read::<AppliedMigration>(document),
+        Collection::Devices => read::<Device>(document),
+        Collection::Webhooks => read::<Webhook>(document),
+        Collection::WebhookDeliveries => read::<WebhookDelivery>(document),
+    }
+}
+
+/// The documents of each collection in an archive
+type Contents = Vec<(Collection, Vec<Document>)>;
+
+/// Reads a `backup` archive, checking it against its manifest and every document against its model
+fn read_archive(archive: &[u8]) -> Result<(Manifest, Contents), ApiError> {
+    let mut files: HashMap<String, Vec<u8>> = HashMap::new();
+    let mut unpacked = tar::Archive::new(GzDecoder::new(archive));
+    for entry in unpacked.entries().map_err(unreadable)? {
+        let mut entry = entry.map_err(unreadable)?;
+        let path = entry.path().map_err(unreadable)?.to_string_lossy().to_string();
+        let mut bytes: Vec<u8> = Vec::new();
+        entry.read_to_end(&mut bytes).map_err(unreadable)?;
+        files.insert(path, bytes);
+    }
+
+    let manifest: Manifest = files.get("manifest.json")
+        .ok_or_else(|| unreadable("manifest.json is missing"))
+        .and_then(|bytes| serde_json::from_slice(bytes).map_err(unreadable))?;
+    if manifest.format_version > FORMAT_VERSION {
+        return Err(unreadable(format!("it was written by a newer version of the API ({})", manifest.api_version)));
+    }
+
+    let mut collections: Contents = Vec::new();
+    for entry in &manifest.collections {
+        let collection = Collection::ALL
+            .into_iter()
+            .find(|collection| collection.key() == entry.collection)
+            .ok_or_else(|| unreadable(format!("{} isn't a collection of the API", entry.collection)))?;
+        let bytes = files.get(&entry.file).ok_or_else(|| unreadable(format!("{} is missing", entry.file)))?;
+        if sha256(bytes) != entry.sha256 {
+            return Err(unreadable(format!("{} doesn't match its checksum", entry.file)));
+        }
+
+        let mut documents: Vec<Document> = Vec::new();
+        let mut ids: HashSet<String> = HashSet::new();
+        for (index, line) in bytes.split(|byte| *byte == b'\n').filter(|line| !line.is_empty()).enumerate() {
+            let at = |problem: String| unreadable(format!("line {} of {} {}", index + 1, entry.file, problem));
+            let json: serde_json::Value = serde_json::from_slice(line).map_err(|err| at(format!("isn't JSON: {}", err)))?;
+            let document = match Bson::try_from(json) {
+                Ok(Bson::Document(document)) => document,
+                _ => return Err(at(String::from("isn't a document")))
+            };
+            match document.get("_id") {
+                Some(id) if ids.insert(id.to_string()) => {},
+                Some(_) => return Err(at(String::from("repeats an _id"))),
+                None => return Err(at(String::from("has no _id")))
+            }
+            check_model(collection, &document).map_err(|err| at(err.message().to_string()))?;
+            documents.push(document);
+        }
+        if documents.len() as u64 != entry.count {
+            return Err(unreadable(format!("{} has {} documents instead of {}", entry.file, documents.len(), entry.count)));
+        }
+
+        if collection == Collection::Migrations {
+            for document in &documents {
+                let known = MIGRATIONS.iter().any(|migration| document.get("_id") == Some(&Bson::Int64(migration.id)));
+                if !known {
+                    return Err(unreadable(format!("it went through migrations of a newer version of the API ({})", manifest.api_version)));
+                }
+            }
+        }
+        collections.push((collection, documents));
+    }
+
+    Ok((manifest, collections))
+}
+
+/// Loads a `backup` archive into an empty database, after checking all of it.
+/// Only the migrations recorded may already be there, the ones of the archive replace them.
+/// When a document can't be stored, what was loaded until then is taken out again.
+/// On a dry run the checks are all that's done.
+pub async fn restore(db: &Database, archive: &[u8], dry_run: bool) -> Result<Manifest, ApiError> {
+    let (manifest, collections) = read_archive(archive)?;
+
+    for collection in Collection::ALL {
+        if collection == Collection::Migrations {
+            continue;
+        }
+        let count = db.documents(collection).count(Document::new()).await?;
+        if count > 0 {
+            return Err(ApiError::Conflict(format!("Backups are only restored into an empty database, {} has {} documents!", collection.key(), count)));
+        }
+    }
+    if dry_run {
+        return Ok(manifest);
+    }
+
+    // What's put back if the restore fails half way
+    let migrations = db.documents(Collection::Migrations).list(Document::new()).await?;
+    let mut inserted: Vec<(Collection, Bson)> = Vec::new();
+    if let Err(err) = load(db, collections, &mut inserted).await {
+        return match roll_back(db, &inserted, &migrations).await {
+            Ok(()) => Err(err),
+            Err(rollback_err) => Err(ApiError::Database(format!("{} Rolling the restore back failed too, the database has part of the backup: {}", err.message(), rollback_err.message())))
+        };
+    }
+    Ok(manifest)
+}
+
+/// Inserts the documents of an archive, keeping track of each one inserted in `inserted`
+async fn load(db: &Database, collections: Contents, inserted: &mut Vec<(Collection, Bson)>) -> Result<(), ApiError> {
+    db.documents(Collection::Migrations).delete_many(Document::new()).await?;
+    for (collection, documents) in collections {
+        let repository = db.documents(collection);
+        for document in documents {
+            let result = repository.insert(&document).await?;
+            inserted.push((collection, result.inserted_id));
+        }
+    }
+    Ok(())
+}
+
+/// Takes out what `load` inserted and puts the migrations recorded before back
+async fn roll_back(db: &Database, inserted: &[(Collection, Bson)], migrations: &[Document]) -> Result<(), ApiError> {
+    for (collection, id) in inserted.iter().rev() {
+        db.documents(*collection).delete_many(doc! {"_id": id.clone()}).await?;
+    }
+    let repository = db.documents(Collection::Migrations);
+    repository.delete_many(Document::new()).await?;
+    for migration in migrations {
+        repository.insert(migration).await?;
+    }
+    Ok(())
+}
@@ -0,0 +1,240 @@
+use std::{cmp::Ordering, collections::HashMap};
+
+use mongodb::bson::{Bson, Document};
+use regex::{Regex, RegexBuilder};
+use crate::models::ApiError;
+use crate::database::repository::{ListOptions, version_of};
+
+/// A MongoDB query document ready to be evaluated against documents, for
+/// storages that aren't MongoDB.
+///
+/// Only the part of the query language the API uses is understood: fields
+/// compared for equality (a `null` also matches a missing field),
+/// `{"$regex": ..., "$options": "i"}`, `$ne` and the ranges `$gt`, `$gte`,
+/// `$lt` and `$lte`. Anything else is refused rather than
+/// silently matching the wrong documents.
+pub struct Matcher<'a> {
+    filter: &'a Document,
+    /// The `$regex` of each field having one, compiled once for every document
+    regexes: HashMap<&'a str, Regex>,
+}
+
+impl<'a> Matcher<'a> {
+    pub fn new(filter: &'a Document) -> Result<Self, ApiError> {
+        let mut regexes = HashMap::new();
+        for (key, expected) in filter {
+            if key.starts_with('$') {
+                return Err(ApiError::BadParams(format!("Unsupported query operator {}!", key)));
+            }
+            if let Bson::Document(operators) = expected {
+                if let Some(pattern) = operators.get("$regex") {
+                    regexes.insert(key.as_str(), compile_regex(pattern, operators.get_str("$options").unwrap_or(""))?);
+                }
+            }
+        }
+        Ok(Self { filter, regexes })
+    }
+
+    pub fn matches(&self, document: &Document) -> Result<bool, ApiError> {
+        for (key, expected) in self.filter {
+            let value = document.get(key).unwrap_or(&Bson::Null);
+            if !self.matches_value(key, value, expected)? {
+                return Ok(false);
+            }
+        }
+
+        Ok(true)
+    }
+
+    fn matches_value(&self, key: &str, value: &Bson, expected: &Bson) -> Result<bool, ApiError> {
+        let operators = match expected {
+            Bson::Document(operators) if operators.keys().any(|key| key.starts_with('$')) => operators,
+            _ => return Ok(value == expected)
+        };
+
+        for (operator, argument) in operators {
+            let matched = match operator.as_str() {
+                "$regex" => match (value, self.regexes.get(key)) {
+                    (Bson::String(text), Some(regex)) => regex.is_match(text),
+                    _ => false
+                },
+                // Only meaningful next to $regex
+                "$options" => true,
+                "$ne" => value != argument,
+                "$gt" => compares(value, argument, |ordering| ordering == Ordering::Greater),
+                "$gte" => compares(value, argument, |ordering| ordering != Ordering::Less),
+                "$lt" => compares(value, argument, |ordering| ordering == Ordering::Less),
+                "$lte" => compares(value, argument, |ordering| ordering != Ordering::Greater),
+                _ => return Err(ApiError::BadParams(format!("Unsupported query operator {}!", operator)))
+            };
+            if !matched {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+}
+
+/// Like MongoDB, a range only matches values of the same type as its bound
+fn compares(value: &Bson, bound: &Bson, accept: impl Fn(Ordering) -> bool) -> bool {
+    type_rank(value) == type_rank(bound) && accept(compare_bson(value, bound))
+}
+
+fn compile_regex(pattern: &Bson, options: &str) -> Result<Regex, ApiError> {
+    let (pattern, options) = match pattern {
+        Bson::String(pattern) => (pattern.as_str(), options),
+        Bson::RegularExpression(regex) => (regex.pattern.as_str(), regex.options.as_str()),
+        _ => return Err(ApiError::BadParams(String::from("$regex needs a pattern!")))
+    };
+
+    RegexBuilder::new(pattern)
+        .case_insensitive(options.contains('i'))
+        .multi_line(options.contains('m'))
+        .build()
+        .map_err(|err| ApiError::BadParams(format!("Invalid $regex: {}", err)))
+}
+
+/// Sets every field of `changes` on `document`, the storage counterpart of
+/// MongoDB's `$set`. Tells whether anything actually changed.
+pub fn apply_changes(document: &mut Document, changes: &Document) -> bool {
+    let mut modified = false;
+    for (key, value) in changes {
+        if document.get(key) != Some(value) {
+            document.insert(key, value.clone());
+            modified = true;
+        }
+    }
+    modified
+}
+
+/// Moves `document` to its next version, the storage counterpart of
+/// MongoDB's `$inc: {"version": 1}`
+pub fn bump_version(document: &mut Document) {
+    let version = version_of(document) + 1;
+    document.insert("version", version);
+}
+
+/// Removes every one of `fields` from `document`, the storage counterpart of
+/// MongoDB's `$unset`. Tells whether anything actually changed.
+pub fn apply_unset(document: &mut Document, fields: &[String]) -> bool {
+    let mut modified = false;
+    for field in fields {
+        if document.remove(field).is_some() {
+            modified = true;
+        }
+    }
+    modified
+}
+
+/// Replaces every field of `document` but `_id` with the ones of `replacement`,
+/// the storage counterpart of MongoDB's `replaceOne`. Tells whether anything changed.
+pub fn apply_replacement(document: &mut Document, replacement: &Document) -> bool {
+    let mut replaced = Document::new();
+    if let Some(id) = document.get("_id") {
+        replaced.insert("_id", id.clone());
+    }
+    for (key, value) in replacement {
+        if key != "_id" {
+            replaced.insert(key, value.clone());
+        }
+    }
+
+    let modified = replaced != *document;
+    *document = replaced;
+    modified
+}
+
+/// Where a value's type sits in MongoDB's sort order
+fn type_rank(value: &Bson) -> u8 {
+    match value {
+        Bson::MinKey => 0,
+        Bson::Null | Bson::Undefined => 1,
+        Bson::Int32(_) | Bson::Int64(_) | Bson::Double(_) | Bson::Decimal128(_) => 2,
+        Bson::String(_) | Bson::Symbol(_) => 3,
+        Bson::Document(_) => 4,
+        Bson::Array(_) => 5,
+        Bson::Binary(_) => 6,
+        Bson::ObjectId(_) => 7,
+        Bson::Boolean(_) => 8,
+        Bson::DateTime(_) => 9,
+        Bson::Timestamp(_) => 10,
+        Bson::RegularExpression(_) => 11,
+        _ => 12,
+    }
+}
+
+fn as_f64(value: &Bson) -> Option<f64> {
+    match value {
+        Bson::Int32(number) => Some(*number as f64),
+        Bson::Int64(number) => Some(*number as f64),
+        Bson::Double(number) => Some(*number),
+        _ => None,
+    }
+}
+
+/// Orders two values the way MongoDB sorts them: by type first, then by value
+pub fn compare_bson(left: &Bson, right: &Bson) -> Ordering {
+    let by_type = type_rank(left).cmp(&type_rank(right));
+    if by_type != Ordering::Equal {
+        return by_type;
+    }
+
+    match (left, right) {
+        (Bson::String(left), Bson::String(right)) => left.cmp(right),
+        (Bson::ObjectId(left), Bson::ObjectId(right)) => left.cmp(right),
+        (Bson::Boolean(left), Bson::Boolean(right)) => left.cmp(right),
+        (Bson::DateTime(left), Bson::DateTime(right)) => left.cmp(right),
+        _ => match (as_f64(left), as_f64(right)) {
+            (Some(left), Some(right)) => left.partial_cmp(&right).unwrap_or(Ordering::Equal),
+            _ => Ordering::Equal,
+        },
+    }
+}
+
+/// Sorts documents by a MongoDB sort document, e.g. `{"name": 1, "_id": -1}`.
+/// Missing fields sort like `null`, first when ascending.
+pub fn sort_documents(documents: &mut [Document], sort: &Document) {
+    documents.sort_by(|left, right| {
+        for (key, direction) in sort {
+            let ordering = compare_bson(
+                left.get(key).unwrap_or(&Bson::Null),
+                right.get(key).unwrap_or(&Bson::Null),
+            );
+            let descending = as_f64(direction).map(|direction| direction < 0.0).unwrap_or(false);
+            let ordering = if descending { ordering.reverse() } else { ordering };
+            if ordering != Ordering::Equal {
+                return ordering;
+            }
+        }
+        Ordering::Equal
+    });
+}
+
+/// Keeps only `fields` (and `_id`) of a document
+pub fn project(document: Document, fields: &[String]) -> Document {
+    document
+        .into_iter()
+        .filter(|(key, _)| key == "_id" || fields.contains(key))
+        .collect()
+}
+
+/// Applies the sort, skip, limit and projection of `options` to every matching
+/// document, answering the page together with how many documents matched
+pub fn paginate(mut documents: Vec<Document>, options: &ListOptions) -> (Vec<Document>, u64) {
+    let total = documents.len() as u64;
+    if let Some(sort) = &options.sort {
+        sort_documents(&mut documents, sort);
+    }
+
+    let page = documents
+        .into_iter()
+        .skip(options.skip as usize)
+        .take(options.limit.map(|limit| limit as usize).unwrap_or(usize::MAX))
+        .map(|document| match &options.fields {
+            Some(fields) => project(document, fields),
+            None => document,
+        })
+        .collect();
+
+    (page, total)
+}
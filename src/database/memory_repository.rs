@@ -0,0 +1,251 @@
+use std::{collections::HashSet, marker::PhantomData, sync::{Arc, Mutex}};
+
+use mongodb::bson::{oid::ObjectId, Bson, Document};
+use crate::models::{ApiError, Model};
+use crate::database::{filter::{Matcher, apply_changes, apply_unset, apply_replacement, bump_version, paginate}, repository::{Repository, ListOptions, Page, Patch, InsertResult, UpdateResult, DeleteResult, model_to_document, document_to_model, version_of, unique_key_filters}};
+
+/// The documents of a collection, in insertion order
+#[derive(Default)]
+struct Documents {
+    list: Vec<Document>,
+    /// The `_id` of every document in `list`, so an insert doesn't have to look through all of them
+    ids: HashSet<String>,
+}
+
+/// `Repository` keeping its documents in memory, for demos and tests.
+/// Everything is gone when the server stops.
+pub struct MemoryRepository<T> {
+    documents: Arc<Mutex<Documents>>,
+    model: PhantomData<fn() -> T>,
+}
+
+impl<T> MemoryRepository<T> {
+    pub fn new() -> Self {
+        Self {
+            documents: Arc::new(Mutex::new(Documents::default())),
+            model: PhantomData
+        }
+    }
+
+    /// Another repository over the very same documents, reading them as `U`
+    pub fn view<U>(&self) -> MemoryRepository<U> {
+        MemoryRepository {
+            documents: self.documents.clone(),
+            model: PhantomData
+        }
+    }
+}
+
+impl<T> Default for MemoryRepository<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Where the documents matching `filter` are in `list`, all found before anything is changed
+/// so a bad filter leaves every document as it was
+fn matching_positions(list: &[Document], filter: &Document) -> Result<Vec<usize>, ApiError> {
+    let matcher = Matcher::new(filter)?;
+    let mut positions = Vec::new();
+    for (position, document) in list.iter().enumerate() {
+        if matcher.matches(document)? {
+            positions.push(position);
+        }
+    }
+    Ok(positions)
+}
+
+impl<T> MemoryRepository<T> {
+    fn documents(&self) -> std::sync::MutexGuard<'_, Documents> {
+        // A panic while holding the lock can't leave a half written document behind
+        self.documents.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+#[rocket::async_trait]
+impl<T: Model> Repository<T> for MemoryRepository<T> {
+    async fn get(&self, filter: Document) -> Result<T, ApiError> {
+        let matcher = Matcher::new(&filter)?;
+        for document in self.documents().list.iter() {
+            if matcher.matches(document)? {
+                return document_to_model(document.clone());
+            }
+        }
+        Err(ApiError::NotFound(format!("{}'s not found!", T::NAME)))
+    }
+
+    async fn list(&self, filter: Document) -> Result<Vec<T>, ApiError> {
+        let matcher = Matcher::new(&filter)?;
+        let mut datas: Vec<T> = Vec::new();
+        for document in self.documents().list.iter() {
+            if matcher.matches(document)? {
+                datas.push(document_to_model(document.clone())?);
+            }
+        }
+        Ok(datas)
+    }
+
+    async fn list_page(&self, filter: Document, options: ListOptions) -> Result<Page<T>, ApiError> {
+        let matcher = Matcher::new(&filter)?;
+        let mut matching_documents: Vec<Document> = Vec::new();
+        for document in self.documents().list.iter() {
+            if matcher.matches(document)? {
+                matching_documents.push(document.clone());
+            }
+        }
+
+        let (documents, total) = paginate(matching_documents, &options);
+        Ok(Page {
+            items: documents.into_iter().map(document_to_model).collect::<Result<Vec<T>, ApiError>>()?,
+            total
+        })
+    }
+
+    async fn count(&self, filter: Document) -> Result<u64, ApiError> {
+        let matcher = Matcher::new(&filter)?;
+        let mut count = 0;
+        for document in self.documents().list.iter() {
+            if matcher.matches(document)? {
+                count += 1;
+            }
+        }
+        Ok(count)
+    }
+
+    async fn insert(&self, data: &T) -> Result<InsertResult, ApiError> {
+        let mut document = model_to_document(data)?;
+        let id = match document.get("_id") {
+            Some(id) => id.clone(),
+            None => Bson::ObjectId(ObjectId::new())
+        };
+        document.insert("_id", id.clone());
+        if !document.contains_key("version") {
+            document.insert("version", 1i64);
+        }
+
+        let mut documents = self.documents();
+        if documents.ids.contains(&id.to_string()) {
+            return Err(ApiError::Conflict(format!("{} with _id {} already exists!", T::NAME, id)));
+        }
+        // Checked under the same lock as the insert, so racing inserts can't both pass
+        for (filter, clash) in unique_key_filters::<T>(&document) {
+            let matcher = Matcher::new(&filter)?;
+            for existing in documents.list.iter() {
+                if matcher.matches(existing)? {
+                    return Err(clash);
+                }
+            }
+        }
+        documents.ids.insert(id.to_string());
+        documents.list.push(document);
+
+        Ok(InsertResult {
+            inserted_id: id
+        })
+    }
+
+    async fn update(&self, filter: Document, data: &T) -> Result<UpdateResult, ApiError> {
+        let mut changes = model_to_document(data)?;
+        changes.remove("_id");
+        changes.remove("version");
+        if changes.is_empty() {
+            return Ok(UpdateResult {
+                matched_count: self.count(filter).await?,
+                modified_count: 0
+            });
+        }
+
+        let mut documents = self.documents();
+        let positions = matching_positions(&documents.list, &filter)?;
+        for &position in positions.iter() {
+            let document = &mut documents.list[position];
+            apply_changes(document, &changes);
+            bump_version(document);
+        }
+        let matched_count = positions.len() as u64;
+
+        // Like MongoDB's $inc, moving the version modifies every matched document
+        Ok(UpdateResult {
+            matched_count,
+            modified_count: matched_count
+        })
+    }
+
+    async fn patch(&self, filter: Document, patch: &Patch) -> Result<UpdateResult, ApiError> {
+        if patch.is_empty() {
+            return Ok(UpdateResult {
+                matched_count: self.count(filter).await?,
+                modified_count: 0
+            });
+        }
+
+        let mut documents = self.documents();
+        let positions = matching_positions(&documents.list, &filter)?;
+        for &position in positions.iter() {
+            let document = &mut documents.list[position];
+            apply_changes(document, &patch.set);
+            apply_unset(document, &patch.unset);
+            bump_version(document);
+        }
+        let matched_count = positions.len() as u64;
+
+        Ok(UpdateResult {
+            matched_count,
+            modified_count: matched_count
+        })
+    }
+
+    async fn replace(&self, filter: Document, data: &T) -> Result<UpdateResult, ApiError> {
+        let replacement = model_to_document(data)?;
+
+        let matcher = Matcher::new(&filter)?;
+        let mut documents = self.documents();
+        let mut position = None;
+        for (index, document) in documents.list.iter().enumerate() {
+            if matcher.matches(document)? {
+                position = Some(index);
+                break;
+            }
+        }
+        let Some(position) = position else {
+            return Ok(UpdateResult {
+                matched_count: 0,
+                modified_count: 0
+            });
+        };
+
+        let document = &mut documents.list[position];
+        let version = version_of(document);
+        apply_replacement(document, &replacement);
+        document.insert("version", version + 1);
+        Ok(UpdateResult {
+            matched_count: 1,
+            modified_count: 1
+        })
+    }
+
+    async fn delete_many(&self, filter: Document) -> Result<DeleteResult, ApiError> {
+        let mut documents = self.documents();
+        let positions = matching_positions(&documents.list, &filter)?;
+        let mut keep: Vec<bool> = vec![true; documents.list.len()];
+        for position in positions {
+            keep[position] = false;
+        }
+
+        let Documents { list, ids } = &mut *documents;
+        let before = list.len();
+        let mut keep = keep.into_iter();
+        list.retain(|document| {
+            let kept = keep.next().unwrap_or(true);
+            if !kept {
+                if let Some(id) = document.get("_id") {
+                    ids.remove(&id.to_string());
+                }
+            }
+            kept
+        });
+        Ok(DeleteResult {
+            deleted_count: (before - list.len()) as u64
+        })
+    }
+}
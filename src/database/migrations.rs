@@ -0,0 +1,303 @@
+use std::{collections::{hash_map::Entry, HashMap}, fmt};
+
+use mongodb::bson::{doc, Bson, DateTime, Document};
+use crate::models::{ApiError, AppliedMigration, Teacher};
+use crate::database::{Collection, Database, repository::version_of};
+
+/// A change to the shape of stored documents, applied once to every database.
+///
+/// Steps run over every document of their collection, deleted ones included, and
+/// must leave documents they already changed alone: a migration stopped half way
+/// is run again in full.
+pub struct Migration {
+    /// Migrations are applied in the order of their `id`, which is never reused
+    pub id: i64,
+    pub name: &'static str,
+    pub steps: &'static [Step],
+}
+
+/// What one migration does to one collection
+pub struct Step {
+    pub collection: Collection,
+    /// Reshapes a document, answering whether anything changed
+    pub up: fn(&mut Document) -> bool,
+    /// Undoes `up`, `None` when what it changed can't be told back
+    pub down: Option<fn(&mut Document) -> bool>,
+}
+
+impl Migration {
+    pub fn reversible(&self) -> bool {
+        self.steps.iter().all(|step| step.down.is_some())
+    }
+}
+
+/// Every migration there is, oldest first
+pub const MIGRATIONS: &[Migration] = &[
+    Migration {
+        id: 1,
+        name: "trim_text_fields",
+        steps: &[
+            Step { collection: Collection::Students, up: trim_name, down: None },
+            Step { collection: Collection::Teachers, up: trim_name, down: None },
+            Step { collection: Collection::Attendances, up: trim_lesson, down: None },
+        ]
+    },
+    Migration {
+        id: 2,
+        name: "hash_teacher_passes",
+        steps: &[
+            Step { collection: Collection::Teachers, up: hash_pass, down: None },
+        ]
+    },
+    Migration {
+        id: 3,
+        name: "uppercase_card_ids",
+        steps: &[
+            Step { collection: Collection::Students, up: uppercase_card_id, down: None },
+            Step { collection: Collection::Devices, up: uppercase_blocked_cards, down: None },
+        ]
+    },
+];
+
+/// Text stored before validation existed may have whitespace around it
+fn trim_field(document: &mut Document, field: &str) -> bool {
+    match document.get_mut(field) {
+        Some(Bson::String(text)) if text.trim() != text => {
+            *text = text.trim().to_string();
+            true
+        },
+        _ => false
+    }
+}
+
+fn trim_name(document: &mut Document) -> bool {
+    trim_field(document, "name")
+}
+
+fn trim_lesson(document: &mut Document) -> bool {
+    trim_field(document, "lesson")
+}
+
+/// Teachers' passes used to be stored as they were given
+fn hash_pass(document: &mut Document) -> bool {
+    let hash = match document.get_str("pass") {
+        Ok(pass) if !Teacher::is_hashed(pass) => Teacher::hash_pass(pass),
+        _ => return false
+    };
+    match hash {
+        Ok(hash) => {
+            document.insert("pass", hash);
+            true
+        },
+        // Left as it is for the next run, signing in with it keeps failing meanwhile
+        Err(_) => false
+    }
+}
+
+/// Card ids used to be stored the way they were written, taps are looked up uppercase
+fn uppercase_text(text: &mut String) -> bool {
+    let uppercase = text.to_uppercase();
+    let changed = uppercase != *text;
+    *text = uppercase;
+    changed
+}
+
+fn uppercase_card_id(document: &mut Document) -> bool {
+    match document.get_mut("card_id") {
+        Some(Bson::String(card_id)) => uppercase_text(card_id),
+        _ => false
+    }
+}
+
+fn uppercase_blocked_cards(document: &mut Document) -> bool {
+    match document.get_mut("blocked_cards") {
+        Some(Bson::Array(cards)) => cards.iter_mut().fold(false, |changed, card| match card {
+            Bson::String(card) => uppercase_text(card) || changed,
+            _ => changed
+        }),
+        _ => false
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Direction {
+    Up,
+    Down,
+}
+
+/// What applying or undoing one migration did, or would do on a dry run
+#[derive(Debug)]
+pub struct Report {
+    pub id: i64,
+    pub name: &'static str,
+    pub direction: Direction,
+    /// How many documents of each collection the migration changed
+    pub affected: Vec<(Collection, u64)>,
+    pub dry_run: bool,
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let direction = match self.direction {
+            Direction::Up => "applied",
+            Direction::Down => "undone",
+        };
+        let affected: Vec<String> = self.affected
+            .iter()
+            .map(|(collection, count)| format!("{:?} {}", collection, count))
+            .collect();
+        write!(f, "{:04} {} {}, documents changed: {}", self.id, self.name, direction, affected.join(", "))?;
+        if self.dry_run {
+            write!(f, " (dry run, nothing was written)")?;
+        }
+        Ok(())
+    }
+}
+
+/// Applies and undoes `MIGRATIONS`, tracking the applied ones in `Database::migration_database`
+pub struct Migrator<'a> {
+    db: &'a Database,
+    migrations: &'static [Migration],
+    /// Every document read so far, kept as the steps leave them
+    /// so a dry run of several migrations sees what the earlier ones did
+    documents: HashMap<Collection, Vec<Document>>,
+}
+
+impl<'a> Migrator<'a> {
+    pub fn new(db: &'a Database) -> Self {
+        Self::with_migrations(db, MIGRATIONS)
+    }
+
+    /// Applies and undoes `migrations` instead, oldest first like `MIGRATIONS`
+    pub fn with_migrations(db: &'a Database, migrations: &'static [Migration]) -> Self {
+        Self {
+            db,
+            migrations,
+            documents: HashMap::new()
+        }
+    }
+
+    /// Every migration, with when it was applied or `None` while it's pending
+    pub async fn status(&self) -> Result<Vec<(&'static Migration, Option<DateTime>)>, ApiError> {
+        let applied = self.applied().await?;
+        Ok(self.migrations.iter().map(|migration| (migration, applied.get(&migration.id).copied())).collect())
+    }
+
+    /// Applies every pending migration up to `target` (all of them when `None`), oldest first
+    pub async fn up(&mut self, target: Option<i64>, dry_run: bool) -> Result<Vec<Report>, ApiError> {
+        let applied = self.applied().await?;
+        let mut reports: Vec<Report> = Vec::new();
+        for migration in self.migrations {
+            if applied.contains_key(&migration.id) || target.is_some_and(|target| migration.id > target) {
+                continue;
+            }
+
+            let affected = self.run(migration, Direction::Up, dry_run).await?;
+            if !dry_run {
+                self.db.migration_database.insert(&AppliedMigration {
+                    id: Some(migration.id),
+                    name: Some(migration.name.to_string()),
+                    applied_at: Some(DateTime::now()),
+                }).await?;
+            }
+            reports.push(Report {
+                id: migration.id,
+                name: migration.name,
+                direction: Direction::Up,
+                affected,
+                dry_run
+            });
+        }
+        Ok(reports)
+    }
+
+    /// Undoes every applied migration after `target`, newest first.
+    /// Nothing is undone when one of them can't be.
+    pub async fn down(&mut self, target: i64, dry_run: bool) -> Result<Vec<Report>, ApiError> {
+        let applied = self.applied().await?;
+        let undone: Vec<&Migration> = self.migrations
+            .iter()
+            .rev()
+            .filter(|migration| migration.id > target && applied.contains_key(&migration.id))
+            .collect();
+        if let Some(migration) = undone.iter().find(|migration| !migration.reversible()) {
+            return Err(ApiError::BadParams(format!("Migration {:04} {} can't be undone!", migration.id, migration.name)));
+        }
+
+        let mut reports: Vec<Report> = Vec::new();
+        for migration in undone {
+            let affected = self.run(migration, Direction::Down, dry_run).await?;
+            if !dry_run {
+                self.db.migration_database.delete_many(doc! {"_id": migration.id}).await?;
+            }
+            reports.push(Report {
+                id: migration.id,
+                name: migration.name,
+                direction: Direction::Down,
+                affected,
+                dry_run
+            });
+        }
+        Ok(reports)
+    }
+
+    /// When each applied migration was applied, by `id`
+    async fn applied(&self) -> Result<HashMap<i64, DateTime>, ApiError> {
+        let mut applied: HashMap<i64, DateTime> = HashMap::new();
+        for migration in self.db.migration_database.list(Document::new()).await? {
+            if let Some(id) = migration.id {
+                applied.insert(id, migration.applied_at.unwrap_or(DateTime::MIN));
+            }
+        }
+        Ok(applied)
+    }
+
+    async fn run(&mut self, migration: &Migration, direction: Direction, dry_run: bool) -> Result<Vec<(Collection, u64)>, ApiError> {
+        let db = self.db;
+        let steps: Vec<&Step> = match direction {
+            Direction::Up => migration.steps.iter().collect(),
+            Direction::Down => migration.steps.iter().rev().collect(),
+        };
+
+        let mut affected: Vec<(Collection, u64)> = Vec::new();
+        for step in steps {
+            let change = match direction {
+                Direction::Up => step.up,
+                Direction::Down => step.down.ok_or_else(|| ApiError::BadParams(format!("Migration {:04} {} can't be undone!", migration.id, migration.name)))?,
+            };
+            let repository = db.documents(step.collection);
+            if let Entry::Vacant(entry) = self.documents.entry(step.collection) {
+                entry.insert(repository.list(Document::new()).await?);
+            }
+
+            let mut count = 0;
+            for document in self.documents.get_mut(&step.collection).into_iter().flatten() {
+                let mut changed = document.clone();
+                if !change(&mut changed) {
+                    continue;
+                }
+                count += 1;
+
+                if !dry_run {
+                    // Only the version read is replaced, a document changed meanwhile is left to the next run
+                    let version = version_of(document);
+                    let filter = doc! {
+                        "_id": document.get("_id").cloned().unwrap_or(Bson::Null),
+                        "version": if version == 0 { Bson::Null } else { Bson::Int64(version) }
+                    };
+                    if repository.replace(filter, &changed).await?.matched_count == 0 {
+                        return Err(ApiError::Conflict(format!("A document of {:?} changed while migration {:04} {} ran, run it again!", step.collection, migration.id, migration.name)));
+                    }
+                    changed.insert("version", version + 1);
+                }
+                *document = changed;
+            }
+
+            match affected.iter_mut().find(|(collection, _)| *collection == step.collection) {
+                Some((_, total)) => *total += count,
+                None => affected.push((step.collection, count))
+            }
+        }
+        Ok(affected)
+    }
+}
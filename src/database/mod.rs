@@ -1,307 +1,240 @@
-use std::collections::HashMap;
-
-use crate::{Student, Teacher, models::Model};
-
-use mongodb::{bson::doc, results::{DeleteResult, InsertOneResult, UpdateResult}, sync::{Client, Collection}};
-use crate::models::utils::hashmap_to_model_document;
-
-pub struct Database {
-    pub student_database: DatabaseType,
-    pub teacher_database: DatabaseType
+mod repository;
+mod filter;
+mod mongo_repository;
+mod memory_repository;
+mod sqlite_repository;
+mod audited_repository;
+pub mod migrations;
+pub mod backup;
+pub mod seeder;
+
+pub use repository::{Repository, ListOptions, Page, Patch, InsertResult, UpdateResult, DeleteResult, model_to_document, unique_key_filters};
+pub use mongo_repository::MongoRepository;
+pub use memory_repository::MemoryRepository;
+pub use sqlite_repository::SqliteRepository;
+pub use audited_repository::{AuditedRepository, AuditContext};
+
+use std::sync::{Arc, Mutex};
+
+use crate::{config::{Config, Storage}, models::{Student, Teacher, Attendance, AuditEntry, AppliedMigration, Device, Webhook, WebhookDelivery, ApiError, Model}};
+
+use mongodb::{bson::{doc, Document}, options::IndexOptions, Client, IndexModel};
+
+/// The collections of `Database`, whatever they're called in the config
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Collection {
+    Students,
+    Teachers,
+    Attendances,
+    AuditLog,
+    Migrations,
+    Devices,
+    Webhooks,
+    WebhookDeliveries,
 }
 
-impl Database{
-    pub fn new(mongodb_uri: String, database_name: String) -> Self {
-        let student_database: DatabaseType = DatabaseType::new(mongodb_uri.clone(), database_name.clone(), DatabaseTypeVariant::StudentDB { collection: None });
-        let teacher_database: DatabaseType = DatabaseType::new(mongodb_uri.clone(), database_name.clone(), DatabaseTypeVariant::TeacherDB { collection: None });
+impl Collection {
+    pub const ALL: [Collection; 8] = [Collection::Students, Collection::Teachers, Collection::Attendances, Collection::AuditLog, Collection::Migrations, Collection::Devices, Collection::Webhooks, Collection::WebhookDeliveries];
+
+    /// Names the collection the same way whatever the config calls it, e.g. in backups
+    pub fn key(self) -> &'static str {
+        match self {
+            Collection::Students => "students",
+            Collection::Teachers => "teachers",
+            Collection::Attendances => "attendances",
+            Collection::AuditLog => "audit_log",
+            Collection::Migrations => "migrations",
+            Collection::Devices => "devices",
+            Collection::Webhooks => "webhooks",
+            Collection::WebhookDeliveries => "webhook_deliveries",
+        }
+    }
 
-        Self {
-            student_database,
-            teacher_database,
+    /// What the collection is called in `config`
+    pub fn name(self, config: &Config) -> &str {
+        match self {
+            Collection::Students => &config.student_collection,
+            Collection::Teachers => &config.teacher_collection,
+            Collection::Attendances => &config.attendance_collection,
+            Collection::AuditLog => &config.audit_collection,
+            Collection::Migrations => &config.migration_collection,
+            Collection::Devices => &config.device_collection,
+            Collection::Webhooks => &config.webhook_collection,
+            Collection::WebhookDeliveries => &config.webhook_delivery_collection,
         }
     }
 }
 
-pub struct DatabaseType {
-    mongodb_uri: String,
-    database_name: String,
-    database: DatabaseTypeVariant,
+/// All collections of the API, living in the storage picked by the config
+pub struct Database {
+    pub student_database: Box<dyn Repository<Student>>,
+    pub teacher_database: Box<dyn Repository<Teacher>>,
+    pub attendance_database: Box<dyn Repository<Attendance>>,
+    /// Append-only, written by `AuditedRepository`
+    pub audit_database: Box<dyn Repository<AuditEntry>>,
+    /// Written by `migrations::Migrator`
+    pub migration_database: Box<dyn Repository<AppliedMigration>>,
+    pub device_database: Box<dyn Repository<Device>>,
+    /// Shared, as the webhook delivery worker outlives any request
+    pub webhook_database: Arc<dyn Repository<Webhook>>,
+    pub webhook_delivery_database: Arc<dyn Repository<WebhookDelivery>>,
+    /// Every collection again as plain documents, in the order of `Collection::ALL`
+    documents: Vec<Box<dyn Repository<Document>>>
 }
 
+impl Database{
+    pub async fn connect(config: &Config) -> Result<Self, ApiError> {
+        match config.storage {
+            Storage::MongoDB => Ok(Self::connect_mongodb(config).await?),
+            Storage::Memory => Ok(Self::memory()),
+            Storage::Sqlite => Ok(Self::sqlite(config)?),
+        }
+    }
 
-enum DatabaseTypeVariant {
-    StudentDB {
-        collection: Option<Collection<Student>>
-    },
-    TeacherDB {
-        collection: Option<Collection<Teacher>>
+    /// `repository` recording its changes in the audit log under `context`
+    pub fn audited<'a, T: Model>(&'a self, repository: &'a dyn Repository<T>, context: &'a AuditContext) -> AuditedRepository<'a, T> {
+        AuditedRepository::new(repository, self.audit_database.as_ref(), context)
     }
-}
 
-impl DatabaseType {
-    fn new(mongodb_uri: String, database_name: String, variant: DatabaseTypeVariant) -> Self {
-        Self {
-            mongodb_uri,
-            database_name,
-            database: variant
-        }
+    /// `collection` as plain documents, for changes that don't go through a model
+    pub fn documents(&self, collection: Collection) -> &dyn Repository<Document> {
+        self.documents[collection as usize].as_ref()
     }
 
-    pub fn init(&mut self, collection_name:&str) -> Result<bool, bool> {
-        let client = Client::with_uri_str(&self.mongodb_uri);
-        match client {
-            Ok(client) => {
-                let db = client.database(self.database_name.as_str());
-                match &mut self.database {
-                    DatabaseTypeVariant::StudentDB { collection } => {
-                        let col: Collection<Student> = db.collection(&collection_name);
-                        *collection = Some(col);
-                    },
-                    DatabaseTypeVariant::TeacherDB { collection } => {
-                        let col: Collection<Teacher> = db.collection(&collection_name);
-                        *collection = Some(col);
-                    }
-                };
-                Ok(true)
-            }
-            Err(_) => Err(false)
-        }
+    /// Connects to MongoDB and makes sure the server actually answers,
+    /// so a wrong URI or unreachable cluster is reported at startup instead of on the first request.
+    /// All collections share one client, and so one connection pool.
+    pub async fn connect_mongodb(config: &Config) -> Result<Self, mongodb::error::Error> {
+        let client = Client::with_uri_str(&config.mongodb_uri).await?;
+        let database = client.database(&config.database_name);
+        database.run_command(doc! {"ping": 1}, None).await?;
+
+        Self::create_indexes(&database, config).await?;
+
+        Ok(Self {
+            // Collections handed out by the same database handle reuse its client
+            student_database: Box::new(MongoRepository::new(database.collection(&config.student_collection))),
+            teacher_database: Box::new(MongoRepository::new(database.collection(&config.teacher_collection))),
+            attendance_database: Box::new(MongoRepository::new(database.collection(&config.attendance_collection))),
+            audit_database: Box::new(MongoRepository::new(database.collection(&config.audit_collection))),
+            migration_database: Box::new(MongoRepository::new(database.collection(&config.migration_collection))),
+            device_database: Box::new(MongoRepository::new(database.collection(&config.device_collection))),
+            webhook_database: Arc::new(MongoRepository::new(database.collection(&config.webhook_collection))),
+            webhook_delivery_database: Arc::new(MongoRepository::new(database.collection(&config.webhook_delivery_collection))),
+            documents: Collection::ALL.iter().map(|collection| {
+                Box::new(MongoRepository::new(database.collection::<Document>(collection.name(config)))) as Box<dyn Repository<Document>>
+            }).collect(),
+        })
     }
 
-    pub fn get_data(&self, params: mongodb::bson::Document) -> Result<Model, String> {
-        match &self.database {
-            //? HANDLE STUDENT DATABASE VARIANT
-            DatabaseTypeVariant::StudentDB { collection } => {
-                match collection {
-                    Some(col) => {
-                        let result: Result<Option<Student>, mongodb::error::Error>= col.find_one(params, None);
-                        match result {
-                            Ok(student_data) => {
-                                match student_data {
-                                    Some(data) => Ok(Model::Student(data)),
-                                    None => Err(String::from("Student's not found!"))
-                                }
-                            },
-                            Err(err) => Err(String::from(format!("Error has found when trying to get student data! Error: {}", err)))
-                        }
-                    }
-                    None => {
-                        Err(String::from("student collection is not initialized yet!"))
-                    }
-                }
-            },
-            //? HANDLE TEACHER DATABASE VARIANT
-            DatabaseTypeVariant::TeacherDB { collection } => {
-                match collection {
-                    Some(col) => {
-                        let result: Result<Option<Teacher>, mongodb::error::Error>= col.find_one(params, None);
-                        match result {
-                            Ok(teacher_data) => {
-                                match teacher_data {
-                                    Some(data) => Ok(Model::Teacher(data)),
-                                    None => Err(String::from("Teacher's not found!"))
-                                }
-                            },
-                            Err(err) => Err(String::from(format!("Error has found when trying to get teacher data! Error: {}", err)))
-                        }
+    /// The indexes MongoDB keeps for the API, by collection
+    fn indexes() -> Vec<(Collection, Vec<IndexModel>)> {
+        let index = |keys: Document, name: &str| {
+            IndexModel::builder().keys(keys).options(IndexOptions::builder().name(name.to_string()).build()).build()
+        };
+        vec![
+            // Name searches are case-insensitive regexes, these let MongoDB scan index keys instead of whole documents
+            (Collection::Students, vec![
+                index(doc! {"name": 1}, "name"),
+                index(doc! {"class_id": 1, "name": 1}, "class_id_name"),
+            ]),
+            // See `Attendance::UNIQUE_KEYS`, the other storages check it themselves
+            (Collection::Attendances, vec![
+                IndexModel::builder()
+                    .keys(doc! {"student_id": 1, "class_id": 1, "lesson": 1})
+                    .options(IndexOptions::builder().name(String::from("student_id_class_id_lesson")).unique(true).build())
+                    .build(),
+                // Only taps have these, other check-ins would all clash on the missing fields
+                IndexModel::builder()
+                    .keys(doc! {"device_id": 1, "tap_id": 1})
+                    .options(IndexOptions::builder()
+                        .name(String::from("device_id_tap_id"))
+                        .unique(true)
+                        .partial_filter_expression(doc! {"tap_id": {"$exists": true}})
+                        .build())
+                    .build(),
+            ]),
+            // The log is read newest first, mostly about one document
+            (Collection::AuditLog, vec![
+                index(doc! {"at": -1}, "at"),
+                index(doc! {"entity": 1, "entity_id": 1, "at": -1}, "entity_entity_id_at"),
+            ]),
+            // Every request of a device looks it up by its token
+            (Collection::Devices, vec![
+                index(doc! {"token": 1}, "token"),
+            ]),
+            // The delivery worker looks for what's due, the log is read newest first by webhook
+            (Collection::WebhookDeliveries, vec![
+                index(doc! {"status": 1, "next_attempt_at": 1}, "status_next_attempt_at"),
+                index(doc! {"webhook_id": 1, "created_at": -1}, "webhook_id_created_at"),
+            ]),
+        ]
+    }
 
-                    }
-                    None => {
-                        Err(String::from("teacher collection is not initialized yet!"))
-                    }
-                }
-            }
+    async fn create_indexes(database: &mongodb::Database, config: &Config) -> Result<(), mongodb::error::Error> {
+        for (collection, indexes) in Self::indexes() {
+            database.collection::<Document>(collection.name(config)).create_indexes(indexes, None).await?;
         }
+        Ok(())
     }
 
-    pub fn get_datas(&self, params: mongodb::bson::Document) -> Result<Vec<Model>, String>{
-        match &self.database {
-            //? HANDLE STUDENT DATABASE VARIANT
-            DatabaseTypeVariant::StudentDB { collection } => {
-                match collection {
-                    Some(col) => {
-                        let result: Result<mongodb::sync::Cursor<Student>, mongodb::error::Error> = col.find(params, None);
-                        match result {
-                            Ok(cursor) => {
-                                let mut students_data: Vec<Model> = Vec::<Model>::new();
-                                for cursor_item in cursor {
-                                    match cursor_item {
-                                        Ok(data) => {
-                                            students_data.push(Model::Student(data))
-                                        },
-                                        Err(_) => ()
-                                    }
-                                }
-
-                                Ok(students_data)
-                            },
-                            Err(err) => Err(err.to_string())
-                        }
-                    }
-                    None => {
-                        Err(String::from("student collection is not initialized yet!"))
-                    }
-                }
-            },
-            //? HANDLE TEACHER DATABASE VARIANT
-            DatabaseTypeVariant::TeacherDB { collection } => {
-                match collection {
-                    Some(col) => {
-                        let result: Result<mongodb::sync::Cursor<Teacher>, mongodb::error::Error> = col.find(params, None);
-                        match result {
-                            Ok(cursor) => {
-                                let mut teachers_data: Vec<Model> = Vec::<Model>::new();
-                                for cursor_item in cursor {
-                                    match cursor_item {
-                                        Ok(data) => {
-                                            teachers_data.push(Model::Teacher(data))
-                                        },
-                                        Err(_) => ()
-                                    }
-                                }
-
-                                Ok(teachers_data)
-                            },
-                            Err(err) => Err(err.to_string())
-                        }
-                    }
-                    None => {
-                        Err(String::from("teacher collection is not initialized yet!"))
-                    }
-                }
-            }
+    /// Drops every index of the collections MongoDB keeps for the API and creates them again,
+    /// answering how many were created for each collection
+    pub async fn rebuild_indexes(config: &Config) -> Result<Vec<(Collection, usize)>, ApiError> {
+        if config.storage != Storage::MongoDB {
+            return Err(ApiError::BadParams(format!("Only MongoDB keeps indexes, there are none to rebuild for {:?}!", config.storage)));
         }
-    }
 
-    pub fn insert_data(&self, new_data: Model) -> Result<InsertOneResult, String> {
-        match &self.database {
-            //? HANDLE STUDENT DATABASE VARIANT
-            DatabaseTypeVariant::StudentDB { collection } => {
-                match collection {
-                    Some(col) => {
-                        match new_data {
-                            Model::Student(data) => {
-                                match col.insert_one(data, None) {
-                                    Ok(res) => Ok(res),
-                                    Err(err) => Err(err.to_string())
-                                }
-                            },
-                            _ => {
-                                Err(String::from("Unexpected variable type when trying to insert data"))
-                            }
-                        }
-                    },
-                    None => {
-                        Err(String::from("teacher collection is not initialized yet!"))
-                    }
-                }
-            },
-            //? HANDLE TEACHER DATABASE VARIANT
-            DatabaseTypeVariant::TeacherDB { collection } => {
-                match collection {
-                    Some(col) => {
-                        match new_data {
-                            Model::Teacher(data) => {
-                                match col.insert_one(data, None) {
-                                    Ok(res) => Ok(res),
-                                    Err(err) => Err(err.to_string())
-                                }
-                            }
-                            _ => {
-                                Err(String::from("Unexpected variable type when trying to insert data"))
-                            }
-                        }
-                    },
-                    None => {
-                        Err(String::from("teacher collection is not initialized yet!"))
-                    }
-                }
+        let client = Client::with_uri_str(&config.mongodb_uri).await?;
+        let database = client.database(&config.database_name);
+        let mut rebuilt: Vec<(Collection, usize)> = Vec::new();
+        for (collection, indexes) in Self::indexes() {
+            let documents = database.collection::<Document>(collection.name(config));
+            // A collection nothing was stored in yet has no indexes to drop
+            let existing: Vec<String> = documents.list_index_names().await.unwrap_or_default();
+            if !existing.is_empty() {
+                documents.drop_indexes(None).await?;
             }
+            let count = indexes.len();
+            documents.create_indexes(indexes, None).await?;
+            rebuilt.push((collection, count));
         }
+        Ok(rebuilt)
     }
 
-    pub fn update_data(&self, params: mongodb::bson::Document, new_data: Model) -> Result<UpdateResult, String> {
-        match &self.database {
-            //? HANDLE STUDENT DATABASE VARIANT
-            DatabaseTypeVariant::StudentDB { collection } => {
-                match collection {
-                    Some(col) => {
-                        match new_data {
-                            Model::Student(student) => {
-                                let student_data_hashmap = HashMap::<String, Option<String>>::from([
-                                    (String::from("_id"), student.id.and_then(|id| Some(id.to_string()))),
-                                    (String::from("name"), student.name),
-                                    (String::from("class_id"), student.class_id.and_then(|id| Some(id.to_string()))),
-                                    (String::from("card_id"), student.card_id),
-                                ]);
-                                
-                                let student: mongodb::bson::Document = hashmap_to_model_document(&student_data_hashmap, Model::Student(Student::empty()));
-                                let result = col.update_many(params, doc! {"$set": student}, None);
-                                match result {
-                                    Ok(res) => Ok(res),
-                                    Err(err) => Err(err.to_string())
-                                }
-                            },
-                            _ => Err("Unexpected variable type when trying to insert data".to_string())
-                        }
-                    },
-                    None => Err("student collection is not initialized yet!".to_string())
-                }
-            },
-            //? HANDLE TEACHER DATABASE VARIANT
-            DatabaseTypeVariant::TeacherDB { collection } => {
-                match collection {
-                    Some(col) => {
-                        match new_data {
-                            Model::Teacher(teacher) => {
-                                let teacher_data_hashmap = HashMap::<String, Option<String>>::from([
-                                    (String::from("_id"), teacher.id.and_then(|id| Some(id.to_string()))),
-                                    (String::from("name"), teacher.name),
-                                    (String::from("pass"), teacher.pass),
-                                ]);
-                                
-                                let teacher: mongodb::bson::Document = hashmap_to_model_document(&teacher_data_hashmap, Model::Teacher(Teacher::empty()));
-                                let result = col.update_many(params, doc! {"$set": teacher}, None);
-                                match result {
-                                    Ok(res) => Ok(res),
-                                    Err(err) => Err(err.to_string())
-                                }
-                            },
-                            _ => Err("Unexpected variable type when trying to insert data".to_string())
-                        }
-                    },
-                    None => Err("teacher collection is not initialized yet!".to_string())
-                }
-            }
+    /// Empty collections kept in memory, nothing survives a restart
+    pub fn memory() -> Self {
+        // The models read the same documents the plain repositories hold
+        let documents: Vec<MemoryRepository<Document>> = Collection::ALL.iter().map(|_| MemoryRepository::new()).collect();
+        Self {
+            student_database: Box::new(documents[Collection::Students as usize].view()),
+            teacher_database: Box::new(documents[Collection::Teachers as usize].view()),
+            attendance_database: Box::new(documents[Collection::Attendances as usize].view()),
+            audit_database: Box::new(documents[Collection::AuditLog as usize].view()),
+            migration_database: Box::new(documents[Collection::Migrations as usize].view()),
+            device_database: Box::new(documents[Collection::Devices as usize].view()),
+            webhook_database: Arc::new(documents[Collection::Webhooks as usize].view()),
+            webhook_delivery_database: Arc::new(documents[Collection::WebhookDeliveries as usize].view()),
+            documents: documents.into_iter().map(|repository| Box::new(repository) as Box<dyn Repository<Document>>).collect(),
         }
     }
 
-    pub fn delete_data(&self, params: mongodb::bson::Document) -> Result<DeleteResult, String> {
-        match &self.database {
-            DatabaseTypeVariant::StudentDB { collection } => {
-                match collection {
-                    Some(col) => {
-                        let result = col.delete_one(params, None);
-                        match result {
-                            Ok(return_value) => Ok(return_value),
-                            Err(err) => Err(err.to_string())
-                        }
-                    },
-                    None => Err("student collection is not initialized yet!".to_string())
-                }
-            },
-            DatabaseTypeVariant::TeacherDB { collection } => {
-                match collection {
-                    Some(col) => {
-                        let result = col.delete_one(params, None);
-                        match result {
-                            Ok(return_value) => Ok(return_value),
-                            Err(err) => Err(err.to_string())
-                        }
-                    },
-                    None => Err("teacher collection is not initialized yet!".to_string())
-                }
-            }
-        }
+    /// Opens (or creates) the SQLite file from the config, with one table per collection
+    pub fn sqlite(config: &Config) -> Result<Self, rusqlite::Error> {
+        let connection = Arc::new(Mutex::new(rusqlite::Connection::open(&config.sqlite_path)?));
+
+        Ok(Self {
+            student_database: Box::new(SqliteRepository::open(connection.clone(), &config.student_collection)?),
+            teacher_database: Box::new(SqliteRepository::open(connection.clone(), &config.teacher_collection)?),
+            attendance_database: Box::new(SqliteRepository::open(connection.clone(), &config.attendance_collection)?),
+            audit_database: Box::new(SqliteRepository::open(connection.clone(), &config.audit_collection)?),
+            migration_database: Box::new(SqliteRepository::open(connection.clone(), &config.migration_collection)?),
+            device_database: Box::new(SqliteRepository::open(connection.clone(), &config.device_collection)?),
+            webhook_database: Arc::new(SqliteRepository::open(connection.clone(), &config.webhook_collection)?),
+            webhook_delivery_database: Arc::new(SqliteRepository::open(connection.clone(), &config.webhook_delivery_collection)?),
+            documents: Collection::ALL.iter().map(|collection| {
+                Ok(Box::new(SqliteRepository::open(connection.clone(), collection.name(config))?) as Box<dyn Repository<Document>>)
+            }).collect::<Result<Vec<_>, rusqlite::Error>>()?,
+        })
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,138 @@
+use futures::TryStreamExt;
+use mongodb::{bson::{doc, Bson, Document}, options::FindOptions, Collection};
+use crate::models::{ApiError, Model};
+use crate::database::repository::{Repository, ListOptions, Page, Patch, InsertResult, UpdateResult, DeleteResult, model_to_document, version_of};
+
+/// `Repository` backed by a MongoDB collection
+pub struct MongoRepository<T> {
+    collection: Collection<T>,
+}
+
+impl<T> MongoRepository<T> {
+    pub fn new(collection: Collection<T>) -> Self {
+        Self {
+            collection
+        }
+    }
+}
+
+#[rocket::async_trait]
+impl<T: Model> Repository<T> for MongoRepository<T> {
+    async fn get(&self, filter: Document) -> Result<T, ApiError> {
+        match self.collection.find_one(filter, None).await? {
+            Some(data) => Ok(data),
+            None => Err(ApiError::NotFound(format!("{}'s not found!", T::NAME)))
+        }
+    }
+
+    async fn list(&self, filter: Document) -> Result<Vec<T>, ApiError> {
+        let cursor = self.collection.find(filter, None).await?;
+        Ok(cursor.try_collect().await?)
+    }
+
+    async fn list_page(&self, filter: Document, options: ListOptions) -> Result<Page<T>, ApiError> {
+        let total = self.collection.count_documents(filter.clone(), None).await?;
+
+        let projection = options.fields.map(|fields| {
+            fields.into_iter().map(|field| (field, 1.into())).collect::<Document>()
+        });
+        let find_options = FindOptions::builder()
+            .skip(options.skip)
+            .limit(options.limit.map(|limit| limit as i64))
+            .sort(options.sort)
+            .projection(projection)
+            .build();
+
+        let cursor = self.collection.find(filter, find_options).await?;
+        Ok(Page {
+            items: cursor.try_collect().await?,
+            total
+        })
+    }
+
+    async fn count(&self, filter: Document) -> Result<u64, ApiError> {
+        Ok(self.collection.count_documents(filter, None).await?)
+    }
+
+    async fn insert(&self, data: &T) -> Result<InsertResult, ApiError> {
+        let mut document = model_to_document(data)?;
+        if !document.contains_key("version") {
+            document.insert("version", 1i64);
+        }
+        let result = self.collection.clone_with_type::<Document>().insert_one(document, None).await?;
+        Ok(InsertResult {
+            inserted_id: result.inserted_id
+        })
+    }
+
+    async fn update(&self, filter: Document, data: &T) -> Result<UpdateResult, ApiError> {
+        let mut changes = model_to_document(data)?;
+        // The id identifies the document and the version moves by itself, neither is set
+        changes.remove("_id");
+        changes.remove("version");
+        // MongoDB refuses an empty $set, but there's simply nothing to do
+        if changes.is_empty() {
+            return Ok(UpdateResult {
+                matched_count: self.collection.count_documents(filter, None).await?,
+                modified_count: 0
+            });
+        }
+
+        let result = self.collection.update_many(filter, doc! {"$set": changes, "$inc": {"version": 1i64}}, None).await?;
+        Ok(UpdateResult {
+            matched_count: result.matched_count,
+            modified_count: result.modified_count
+        })
+    }
+
+    async fn patch(&self, filter: Document, patch: &Patch) -> Result<UpdateResult, ApiError> {
+        if patch.is_empty() {
+            return Ok(UpdateResult {
+                matched_count: self.collection.count_documents(filter, None).await?,
+                modified_count: 0
+            });
+        }
+
+        let mut changes = doc! {"$inc": {"version": 1i64}};
+        if !patch.set.is_empty() {
+            changes.insert("$set", patch.set.clone());
+        }
+        if !patch.unset.is_empty() {
+            changes.insert("$unset", patch.unset.iter().map(|field| (field.clone(), Bson::from(""))).collect::<Document>());
+        }
+
+        let result = self.collection.update_many(filter, changes, None).await?;
+        Ok(UpdateResult {
+            matched_count: result.matched_count,
+            modified_count: result.modified_count
+        })
+    }
+
+    async fn replace(&self, filter: Document, data: &T) -> Result<UpdateResult, ApiError> {
+        let collection = self.collection.clone_with_type::<Document>();
+        let current = match collection.find_one(filter, None).await? {
+            Some(current) => current,
+            None => return Ok(UpdateResult { matched_count: 0, modified_count: 0 })
+        };
+
+        let mut replacement = model_to_document(data)?;
+        replacement.remove("_id");
+        replacement.insert("version", version_of(&current) + 1);
+
+        // A replacement can't $inc, so it only goes through while the version read above is still stored
+        let mut filter = doc! {"_id": current.get("_id").cloned().unwrap_or(Bson::Null)};
+        filter.insert("version", current.get("version").cloned().unwrap_or(Bson::Null));
+        let result = collection.replace_one(filter, replacement, None).await?;
+        Ok(UpdateResult {
+            matched_count: result.matched_count,
+            modified_count: result.modified_count
+        })
+    }
+
+    async fn delete_many(&self, filter: Document) -> Result<DeleteResult, ApiError> {
+        let result = self.collection.delete_many(filter, None).await?;
+        Ok(DeleteResult {
+            deleted_count: result.deleted_count
+        })
+    }
+}
@@ -0,0 +1,173 @@
+use mongodb::bson::{Bson, Document};
+use serde::Serialize;
+use crate::models::{ApiError, Model, validation::{validate_document, Violation}};
+
+/// What `Repository::insert` answers with, shaped like MongoDB's own result
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct InsertResult {
+    pub inserted_id: Bson,
+}
+
+/// What `Repository::update` answers with, shaped like MongoDB's own result
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UpdateResult {
+    pub matched_count: u64,
+    pub modified_count: u64,
+}
+
+/// What `Repository::delete` answers with, shaped like MongoDB's own result
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DeleteResult {
+    pub deleted_count: u64,
+}
+
+/// A JSON merge patch (RFC 7396) over the top level fields of a model
+#[derive(Debug, Default, Clone)]
+pub struct Patch {
+    /// Fields given a value, MongoDB's `$set`
+    pub set: Document,
+    /// Fields given `null`, MongoDB's `$unset`
+    pub unset: Vec<String>,
+}
+
+impl Patch {
+    /// Reads a merge patch body for `T`: absent fields are left alone and `null` clears one.
+    /// Fields `T` doesn't have, `_id` which can't change and values breaking `T::RULES`
+    /// are all reported at once.
+    pub fn from_json<T: Model>(body: serde_json::Map<String, serde_json::Value>) -> Result<Self, ApiError> {
+        let mut violations: Vec<Violation> = Vec::new();
+        let mut unset: Vec<String> = Vec::new();
+        let mut values = serde_json::Map::new();
+        for (field, value) in body {
+            if field == "_id" {
+                violations.push(Violation::new(&field, "can't be changed"));
+            } else if !T::FIELDS.contains(&field.as_str()) {
+                violations.push(Violation::new(&field, &format!("isn't a field of {}", T::NAME)));
+            } else if value.is_null() {
+                unset.push(field);
+            } else {
+                values.insert(field, value);
+            }
+        }
+
+        // Reading the values as a `T` checks each has the type its field expects
+        let data: T = serde_json::from_value(serde_json::Value::Object(values))
+            .map_err(|err| ApiError::BadParams(format!("{} can't be read: {}", T::NAME, err)))?;
+        let mut set = model_to_document(&data)?;
+        match validate_document::<T>(&mut set, &unset, false) {
+            Ok(()) => {},
+            Err(ApiError::Validation(found)) => violations.extend(found),
+            Err(err) => return Err(err)
+        }
+
+        if !violations.is_empty() {
+            return Err(ApiError::Validation(violations));
+        }
+        Ok(Self {
+            set,
+            unset
+        })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.set.is_empty() && self.unset.is_empty()
+    }
+}
+
+/// Which slice of the matching documents `Repository::list_page` returns, and how
+#[derive(Debug, Default, Clone)]
+pub struct ListOptions {
+    /// How many matching documents are skipped
+    pub skip: u64,
+    /// At most this many documents are returned, all of them when `None`
+    pub limit: Option<u64>,
+    /// Shaped like MongoDB's sort document, e.g. `{"name": 1, "_id": -1}`
+    pub sort: Option<Document>,
+    /// Only these fields are returned (`_id` always is)
+    pub fields: Option<Vec<String>>,
+}
+
+/// One slice of the documents matching a filter
+#[derive(Debug)]
+pub struct Page<T> {
+    pub items: Vec<T>,
+    /// How many documents match the filter in total
+    pub total: u64,
+}
+
+/// Create, read, update and delete over one collection of `T`.
+///
+/// Filters are MongoDB query documents, so `doc! {"name": "Budi"}` means the same
+/// thing whatever storage ends up answering it.
+///
+/// Every stored document has a `version`: inserts start it at 1 and each update,
+/// patch or replace moves it up by one, so a change can be made conditional on
+/// the version a client last saw.
+#[rocket::async_trait]
+pub trait Repository<T: Send>: Send + Sync {
+    /// The first `T` matching `filter`, or `ApiError::NotFound`
+    async fn get(&self, filter: Document) -> Result<T, ApiError>;
+
+    /// Every `T` matching `filter`
+    async fn list(&self, filter: Document) -> Result<Vec<T>, ApiError>;
+
+    /// The slice of the `T`s matching `filter` described by `options`
+    async fn list_page(&self, filter: Document, options: ListOptions) -> Result<Page<T>, ApiError>;
+
+    /// How many `T`s match `filter`
+    async fn count(&self, filter: Document) -> Result<u64, ApiError>;
+
+    async fn insert(&self, data: &T) -> Result<InsertResult, ApiError>;
+
+    /// Sets the fields of `data` that aren't `None` on every `T` matching `filter`
+    async fn update(&self, filter: Document, data: &T) -> Result<UpdateResult, ApiError>;
+
+    /// Applies `patch` to every `T` matching `filter`
+    async fn patch(&self, filter: Document, patch: &Patch) -> Result<UpdateResult, ApiError>;
+
+    /// Replaces the first `T` matching `filter` with `data`, keeping its `_id`
+    async fn replace(&self, filter: Document, data: &T) -> Result<UpdateResult, ApiError>;
+
+    /// Deletes every `T` matching `filter`
+    async fn delete_many(&self, filter: Document) -> Result<DeleteResult, ApiError>;
+}
+
+/// The version a stored document is at, see `Repository`.
+/// Documents stored before versions existed are at 0.
+pub fn version_of(document: &Document) -> i64 {
+    match document.get("version") {
+        Some(Bson::Int64(version)) => *version,
+        Some(Bson::Int32(version)) => *version as i64,
+        _ => 0
+    }
+}
+
+/// Turns a model into the document a storage keeps
+pub fn model_to_document<T: Model>(data: &T) -> Result<Document, ApiError> {
+    mongodb::bson::to_document(data)
+        .map_err(|err| ApiError::UnexpectedType(format!("{} can't be stored: {}", T::NAME, err)))
+}
+
+/// Reads a model back from the document a storage kept
+pub fn document_to_model<T: Model>(document: Document) -> Result<T, ApiError> {
+    mongodb::bson::from_document(document)
+        .map_err(|err| ApiError::UnexpectedType(format!("{} can't be read: {}", T::NAME, err)))
+}
+
+/// For each of `T::UNIQUE_KEYS` that `document` has every field of, the filter matching
+/// the stored documents it would clash with, next to the error answered when one does
+pub fn unique_key_filters<T: Model>(document: &Document) -> Vec<(Document, ApiError)> {
+    T::UNIQUE_KEYS.iter().filter_map(|keys| {
+        let mut filter = Document::new();
+        for key in keys.iter() {
+            match document.get(*key) {
+                Some(Bson::Null) | None => return None,
+                Some(value) => filter.insert(*key, value.clone())
+            };
+        }
+        Some((filter, ApiError::Conflict(format!("{} with the same {} already exists!", T::NAME, keys.join(", ")))))
+    }).collect()
+}
@@ -0,0 +1,239 @@
+use std::collections::HashSet;
+
+use mongodb::bson::{oid::ObjectId, DateTime, Document};
+use rand::{seq::SliceRandom, Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+use crate::models::{ApiError, Attendance, Student, Teacher};
+use crate::database::{Collection, Database};
+
+const DAY_MILLIS: i64 = 24 * 60 * 60 * 1000;
+const MINUTE_MILLIS: i64 = 60 * 1000;
+
+const GIVEN_NAMES: &[&str] = &[
+    "Adi", "Agus", "Andi", "Ani", "Arif", "Ayu", "Bayu", "Budi", "Citra", "Dewi", "Dian", "Eka",
+    "Fajar", "Fitri", "Gita", "Hadi", "Indah", "Intan", "Joko", "Kartika", "Lestari", "Maya", "Nur", "Putri",
+    "Rahmat", "Rina", "Rizki", "Sari", "Siti", "Teguh", "Wahyu", "Wulan", "Yogi", "Yuni",
+];
+const FAMILY_NAMES: &[&str] = &[
+    "Hidayat", "Kurniawan", "Lubis", "Nasution", "Pratama", "Putra", "Saputra", "Setiawan", "Siregar",
+    "Sitompul", "Susanto", "Wibowo", "Wijaya", "Yulianto", "Harahap", "Gunawan", "Hakim", "Permana",
+];
+const SUBJECTS: &[&str] = &[
+    "Mathematics", "Physics", "Chemistry", "Biology", "Bahasa Indonesia", "English",
+    "History", "Geography", "Economics", "Religion", "Civics", "Arts", "Sports", "Informatics",
+];
+/// When each lesson of a school day starts, in minutes after midnight UTC
+const LESSON_STARTS: &[i64] = &[7 * 60, 8 * 60 + 30, 10 * 60 + 15, 12 * 60 + 30];
+
+/// What `seed` generates
+#[derive(Debug, Clone)]
+pub struct SeedOptions {
+    /// The same seed (and `until`) always gives the very same data
+    pub seed: u64,
+    pub classes: usize,
+    pub students_per_class: usize,
+    pub teachers: usize,
+    /// How many days of lessons, weekends are left out
+    pub days: u32,
+    /// The last day with lessons
+    pub until: DateTime,
+}
+
+/// The last day with lessons unless told otherwise, 2024-06-28 (a Friday).
+/// Fixed rather than today, so the same seed gives the same data whenever it's run.
+pub const DEFAULT_UNTIL: DateTime = DateTime::from_millis(1_719_532_800_000);
+
+impl SeedOptions {
+    /// A few classes with a couple of months of lessons, up to `DEFAULT_UNTIL`
+    pub fn new(seed: u64) -> Self {
+        Self {
+            seed,
+            classes: 3,
+            students_per_class: 25,
+            teachers: 8,
+            days: 60,
+            until: DEFAULT_UNTIL,
+        }
+    }
+}
+
+/// What `seed` stored
+#[derive(Debug)]
+pub struct Seeded {
+    pub classes: Vec<ObjectId>,
+    /// Name of each teacher, they all sign in with `TEACHER_PASS`
+    pub teachers: Vec<String>,
+    pub students: u64,
+    pub attendances: u64,
+}
+
+/// The pass of every seeded teacher
+pub const TEACHER_PASS: &str = "development";
+
+/// How one student tends to show up
+struct Habits {
+    /// Chance of missing a whole school day
+    absence: f64,
+    /// Chance of checking in late to a lesson
+    lateness: f64,
+}
+
+impl Habits {
+    fn draw(rng: &mut ChaCha8Rng) -> Self {
+        // Most students are there nearly every day, a few miss a lot
+        let absence = if rng.gen_bool(0.1) { rng.gen_range(0.15..0.35) } else { rng.gen_range(0.01..0.08) };
+        let lateness = if rng.gen_bool(0.2) { rng.gen_range(0.15..0.40) } else { rng.gen_range(0.0..0.10) };
+        Self {
+            absence,
+            lateness
+        }
+    }
+
+    /// How long after the lesson started the student checks in, `None` when they miss it
+    fn check_in_offset(&self, rng: &mut ChaCha8Rng) -> Option<i64> {
+        // Skipping a single lesson of a day they're there
+        if rng.gen_bool(0.02) {
+            return None;
+        }
+        if rng.gen_bool(self.lateness) {
+            // Late arrivals thin out the later it gets, roughly exponentially
+            let late: f64 = 5.0 - 12.0 * (1.0 - rng.gen::<f64>()).ln();
+            Some((late.min(45.0) * MINUTE_MILLIS as f64) as i64)
+        } else {
+            // Around the start, most a few minutes early
+            let jitter: i64 = (0..3).map(|_| rng.gen_range(-4..=2)).sum();
+            Some(jitter * MINUTE_MILLIS + rng.gen_range(0..MINUTE_MILLIS))
+        }
+    }
+}
+
+fn object_id(rng: &mut ChaCha8Rng) -> ObjectId {
+    ObjectId::from_bytes(rng.gen())
+}
+
+fn person_name(rng: &mut ChaCha8Rng) -> String {
+    format!("{} {}", GIVEN_NAMES.choose(rng).unwrap_or(&"Budi"), FAMILY_NAMES.choose(rng).unwrap_or(&"Santoso"))
+}
+
+/// A card UID of 4 or 7 bytes no other student has
+fn card_id(rng: &mut ChaCha8Rng, taken: &mut HashSet<String>) -> String {
+    loop {
+        let length = if rng.gen_bool(0.7) { 4 } else { 7 };
+        let card_id: String = (0..length).map(|_| format!("{:02X}", rng.gen::<u8>())).collect();
+        if taken.insert(card_id.clone()) {
+            return card_id;
+        }
+    }
+}
+
+/// Monday is 0, 1970-01-01 was a Thursday
+fn weekday(day: i64) -> i64 {
+    (day + 3).rem_euclid(7)
+}
+
+/// The first of the collections `seed` fills that has documents already, with how many
+pub async fn filled_collection(db: &Database) -> Result<Option<(Collection, u64)>, ApiError> {
+    for collection in [Collection::Students, Collection::Teachers, Collection::Attendances] {
+        let count = db.documents(collection).count(Document::new()).await?;
+        if count > 0 {
+            return Ok(Some((collection, count)));
+        }
+    }
+    Ok(None)
+}
+
+/// Fills an empty database with plausible classes, teachers, students and attendance,
+/// through the repositories of `db` like any other write. Nothing is audited.
+pub async fn seed(db: &Database, options: &SeedOptions) -> Result<Seeded, ApiError> {
+    if let Some((collection, count)) = filled_collection(db).await? {
+        return Err(ApiError::Conflict(format!("Only an empty database is seeded, {} has {} documents!", collection.key(), count)));
+    }
+
+    let mut rng = ChaCha8Rng::seed_from_u64(options.seed);
+
+    // Hashing is slow on purpose, and every teacher has the same pass anyway
+    let pass = Teacher::hash_pass(TEACHER_PASS)?;
+    let mut teachers: Vec<String> = Vec::new();
+    while teachers.len() < options.teachers {
+        // Teachers sign in with their name, so no two share one
+        let name = format!("{} {}", if rng.gen_bool(0.5) { "Pak" } else { "Bu" }, person_name(&mut rng));
+        if teachers.contains(&name) {
+            continue;
+        }
+        db.teacher_database.insert(&Teacher {
+            id: Some(object_id(&mut rng)),
+            name: Some(name.clone()),
+            pass: Some(pass.clone()),
+            deleted_at: None,
+            version: None,
+        }).await?;
+        teachers.push(name);
+    }
+
+    let classes: Vec<ObjectId> = (0..options.classes).map(|_| object_id(&mut rng)).collect();
+    let mut card_ids: HashSet<String> = HashSet::new();
+    // Each student with the index of their class
+    let mut students: Vec<(ObjectId, usize, Habits)> = Vec::new();
+    for (class, class_id) in classes.iter().enumerate() {
+        for _ in 0..options.students_per_class {
+            let id = object_id(&mut rng);
+            db.student_database.insert(&Student {
+                id: Some(id),
+                name: Some(person_name(&mut rng)),
+                card_id: Some(card_id(&mut rng, &mut card_ids)),
+                class_id: Some(*class_id),
+                token: None,
+                deleted_at: None,
+                version: None,
+            }).await?;
+            students.push((id, class, Habits::draw(&mut rng)));
+        }
+    }
+
+    // Each class has its own timetable, the same every week
+    let timetables: Vec<Vec<Vec<&str>>> = classes
+        .iter()
+        .map(|_| (0..5).map(|_| SUBJECTS.choose_multiple(&mut rng, LESSON_STARTS.len()).copied().collect()).collect())
+        .collect();
+
+    let last_day = options.until.timestamp_millis().div_euclid(DAY_MILLIS);
+    let mut attendances = 0;
+    for day in (last_day - options.days as i64 + 1)..=last_day {
+        if weekday(day) >= 5 {
+            continue;
+        }
+        let date = DateTime::from_millis(day * DAY_MILLIS).try_to_rfc3339_string().unwrap_or_default();
+        let date = date.get(..10).unwrap_or_default();
+
+        for (id, class, habits) in &students {
+            if rng.gen_bool(habits.absence) {
+                continue;
+            }
+            for (subject, start) in timetables[*class][weekday(day) as usize].iter().zip(LESSON_STARTS) {
+                let offset = match habits.check_in_offset(&mut rng) {
+                    Some(offset) => offset,
+                    None => continue
+                };
+                db.attendance_database.insert(&Attendance {
+                    id: Some(object_id(&mut rng)),
+                    student_id: Some(*id),
+                    class_id: Some(classes[*class]),
+                    lesson: Some(format!("{} {}", subject, date)),
+                    method: Some(String::from("qr")),
+                    device_id: None,
+                    tap_id: None,
+                    checked_in_at: Some(DateTime::from_millis(day * DAY_MILLIS + start * MINUTE_MILLIS + offset)),
+                    version: None,
+                }).await?;
+                attendances += 1;
+            }
+        }
+    }
+
+    Ok(Seeded {
+        classes,
+        teachers,
+        students: students.len() as u64,
+        attendances
+    })
+}
@@ -0,0 +1,343 @@
+use std::{marker::PhantomData, sync::{Arc, Mutex}};
+
+use mongodb::bson::{oid::ObjectId, Bson, Document};
+use rusqlite::{params, Connection};
+use crate::models::{ApiError, Model};
+use crate::database::{filter::{Matcher, apply_changes, apply_unset, apply_replacement, bump_version, paginate}, repository::{Repository, ListOptions, Page, Patch, InsertResult, UpdateResult, DeleteResult, model_to_document, document_to_model, version_of, unique_key_filters}};
+
+/// `Repository` keeping a collection in a table of an SQLite file, for
+/// deployments without any database server.
+///
+/// Each row holds one document as BSON next to its `_id`. Filters are evaluated
+/// on the rows read, narrowed first by the primary key when the filter names an
+/// `_id`, or else by the keys table when it asks for a top-level field to equal
+/// some text or id, as the token lookups and unique key checks do. Any other
+/// filter reads the whole table, which is plenty for the size of one school.
+pub struct SqliteRepository<T> {
+    connection: Arc<Mutex<Connection>>,
+    table: Arc<str>,
+    model: PhantomData<fn() -> T>,
+}
+
+/// The primary key a document's `_id` is stored under
+fn id_key(id: &Bson) -> String {
+    match id {
+        Bson::ObjectId(oid) => oid.to_hex(),
+        Bson::String(text) => text.clone(),
+        other => other.to_string()
+    }
+}
+
+/// Text longer than this, like a webhook payload, isn't put in the keys table
+const MAX_KEY_LENGTH: usize = 128;
+
+/// The quoted name `table` has with `suffix` appended
+fn suffixed(table: &str, suffix: &str) -> String {
+    format!("{}{}\"", &table[..table.len() - 1], suffix)
+}
+
+/// The keys table next to `table`, holding a row per field a document can be looked up by
+fn keys_table(table: &str) -> String {
+    suffixed(table, "_keys")
+}
+
+/// How an equality on `value` is found in the keys table, `None` for values that aren't kept there
+fn lookup_key(value: &Bson) -> Option<String> {
+    match value {
+        Bson::ObjectId(oid) => Some(format!("o:{}", oid.to_hex())),
+        Bson::String(text) if text.len() <= MAX_KEY_LENGTH => Some(format!("s:{}", text)),
+        _ => None
+    }
+}
+
+/// Rewrites the rows of the keys table for the document stored under `id`
+fn write_keys(connection: &Connection, table: &str, id: &str, document: &Document) -> Result<(), rusqlite::Error> {
+    let keys = keys_table(table);
+    connection.execute(&format!("DELETE FROM {} WHERE id = ?1", keys), params![id])?;
+    for (field, value) in document {
+        if let (false, Some(key)) = (field == "_id", lookup_key(value)) {
+            connection.execute(&format!("INSERT INTO {} (id, field, value) VALUES (?1, ?2, ?3)", keys), params![id, field, key])?;
+        }
+    }
+    Ok(())
+}
+
+/// Stores `document` under `id`, which is already in `table`
+fn write(connection: &Connection, table: &str, id: &str, document: &Document) -> Result<(), ApiError> {
+    connection.execute(
+        &format!("UPDATE {} SET document = ?1 WHERE id = ?2", table),
+        params![document_to_bytes(document)?, id],
+    )?;
+    Ok(write_keys(connection, table, id, document)?)
+}
+
+fn document_to_bytes(document: &Document) -> Result<Vec<u8>, ApiError> {
+    let mut bytes: Vec<u8> = Vec::new();
+    document.to_writer(&mut bytes)
+        .map_err(|err| ApiError::UnexpectedType(format!("Document can't be stored: {}", err)))?;
+    Ok(bytes)
+}
+
+fn bytes_to_document(bytes: &[u8]) -> Result<Document, ApiError> {
+    Document::from_reader(bytes)
+        .map_err(|err| ApiError::Database(format!("Stored document is corrupted: {}", err)))
+}
+
+/// The rows `query` selects, as `(id, document)`
+fn select(connection: &Connection, query: &str, params: impl rusqlite::Params) -> Result<Vec<(String, Vec<u8>)>, rusqlite::Error> {
+    let mut statement = connection.prepare(query)?;
+    let rows = statement.query_map(params, |row| Ok((row.get::<_, String>(0)?, row.get::<_, Vec<u8>>(1)?)))?;
+    rows.collect()
+}
+
+/// Every row of `table` matching `filter`, in insertion order
+fn matching(connection: &Connection, table: &str, filter: &Document) -> Result<Vec<(String, Document)>, ApiError> {
+    let matcher = Matcher::new(filter)?;
+    let lookup = filter
+        .iter()
+        .filter(|(field, _)| !field.starts_with('$') && !field.contains('.') && *field != "_id")
+        .find_map(|(field, value)| lookup_key(value).map(|key| (field, key)));
+
+    let rows = match (filter.get("_id"), lookup) {
+        (Some(id), _) if !matches!(id, Bson::Document(_)) => {
+            select(connection, &format!("SELECT id, document FROM {} WHERE id = ?1", table), params![id_key(id)])?
+        },
+        (_, Some((field, key))) => select(
+            connection,
+            &format!("SELECT t.id, t.document FROM {} t JOIN {} k ON k.id = t.id WHERE k.field = ?1 AND k.value = ?2 ORDER BY t.rowid", table, keys_table(table)),
+            params![field, key],
+        )?,
+        _ => select(connection, &format!("SELECT id, document FROM {} ORDER BY rowid", table), [])?
+    };
+
+    let mut matching_rows: Vec<(String, Document)> = Vec::new();
+    for (id, bytes) in rows {
+        let document = bytes_to_document(&bytes)?;
+        if matcher.matches(&document)? {
+            matching_rows.push((id, document));
+        }
+    }
+    Ok(matching_rows)
+}
+
+impl<T> SqliteRepository<T> {
+    /// Uses `table` of the shared connection, creating it and its keys table when they don't exist yet
+    pub fn open(connection: Arc<Mutex<Connection>>, table: &str) -> Result<Self, rusqlite::Error> {
+        let keys_name = format!("{}_keys", table);
+        let table = format!("\"{}\"", table.replace('"', "\"\""));
+        let keys = keys_table(&table);
+        let mut guard = connection.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        let transaction = guard.transaction()?;
+        transaction.execute(&format!("CREATE TABLE IF NOT EXISTS {} (id TEXT PRIMARY KEY, document BLOB NOT NULL)", table), [])?;
+        let keys_exist = transaction
+            .prepare("SELECT 1 FROM sqlite_master WHERE type = 'table' AND name = ?1")?
+            .exists(params![keys_name])?;
+        transaction.execute(&format!("CREATE TABLE IF NOT EXISTS {} (id TEXT NOT NULL, field TEXT NOT NULL, value TEXT NOT NULL)", keys), [])?;
+        transaction.execute(&format!("CREATE INDEX IF NOT EXISTS {} ON {} (field, value)", suffixed(&keys, "_lookup"), keys), [])?;
+        transaction.execute(&format!("CREATE INDEX IF NOT EXISTS {} ON {} (id)", suffixed(&keys, "_id"), keys), [])?;
+
+        // A file written before the keys table existed gets the keys of its rows now
+        if !keys_exist {
+            for (id, bytes) in select(&transaction, &format!("SELECT id, document FROM {}", table), [])? {
+                let document = Document::from_reader(bytes.as_slice())
+                    .map_err(|err| rusqlite::Error::FromSqlConversionFailure(1, rusqlite::types::Type::Blob, Box::new(err)))?;
+                write_keys(&transaction, &table, &id, &document)?;
+            }
+        }
+        transaction.commit()?;
+        drop(guard);
+
+        Ok(Self {
+            connection,
+            table: table.into(),
+            model: PhantomData
+        })
+    }
+
+    /// Runs `query` on the blocking thread pool, SQLite calls would otherwise stall the async workers
+    async fn run<R, F>(&self, query: F) -> Result<R, ApiError>
+    where
+        R: Send + 'static,
+        F: FnOnce(&mut Connection, &str) -> Result<R, ApiError> + Send + 'static,
+    {
+        let connection = self.connection.clone();
+        let table = self.table.clone();
+        rocket::tokio::task::spawn_blocking(move || {
+            let mut connection = connection.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+            query(&mut connection, &table)
+        })
+        .await
+        .map_err(|err| ApiError::Database(format!("SQLite query didn't finish: {}", err)))?
+    }
+}
+
+#[rocket::async_trait]
+impl<T: Model> Repository<T> for SqliteRepository<T> {
+    async fn get(&self, filter: Document) -> Result<T, ApiError> {
+        let document = self.run(move |connection, table| {
+            Ok(matching(connection, table, &filter)?.into_iter().next())
+        }).await?;
+
+        match document {
+            Some((_, document)) => document_to_model(document),
+            None => Err(ApiError::NotFound(format!("{}'s not found!", T::NAME)))
+        }
+    }
+
+    async fn list(&self, filter: Document) -> Result<Vec<T>, ApiError> {
+        self.run(move |connection, table| matching(connection, table, &filter))
+            .await?
+            .into_iter()
+            .map(|(_, document)| document_to_model(document))
+            .collect()
+    }
+
+    async fn list_page(&self, filter: Document, options: ListOptions) -> Result<Page<T>, ApiError> {
+        let matching_documents = self.run(move |connection, table| matching(connection, table, &filter))
+            .await?
+            .into_iter()
+            .map(|(_, document)| document)
+            .collect();
+
+        let (documents, total) = paginate(matching_documents, &options);
+        Ok(Page {
+            items: documents.into_iter().map(document_to_model).collect::<Result<Vec<T>, ApiError>>()?,
+            total
+        })
+    }
+
+    async fn count(&self, filter: Document) -> Result<u64, ApiError> {
+        self.run(move |connection, table| Ok(matching(connection, table, &filter)?.len() as u64)).await
+    }
+
+    async fn insert(&self, data: &T) -> Result<InsertResult, ApiError> {
+        let mut document = model_to_document(data)?;
+        let id = match document.get("_id") {
+            Some(id) => id.clone(),
+            None => Bson::ObjectId(ObjectId::new())
+        };
+        document.insert("_id", id.clone());
+        if !document.contains_key("version") {
+            document.insert("version", 1i64);
+        }
+
+        let key = id_key(&id);
+        let unique_keys = unique_key_filters::<T>(&document);
+        self.run(move |connection, table| {
+            // Every query holds the connection, so nothing is inserted between the check and the insert
+            for (filter, clash) in unique_keys {
+                if !matching(connection, table, &filter)?.is_empty() {
+                    return Err(clash);
+                }
+            }
+            let transaction = connection.transaction()?;
+            transaction.execute(&format!("INSERT INTO {} (id, document) VALUES (?1, ?2)", table), params![key, document_to_bytes(&document)?])?;
+            write_keys(&transaction, table, &key, &document)?;
+            transaction.commit()?;
+            Ok(())
+        }).await?;
+
+        Ok(InsertResult {
+            inserted_id: id
+        })
+    }
+
+    async fn update(&self, filter: Document, data: &T) -> Result<UpdateResult, ApiError> {
+        let mut changes = model_to_document(data)?;
+        changes.remove("_id");
+        changes.remove("version");
+        if changes.is_empty() {
+            return Ok(UpdateResult {
+                matched_count: self.count(filter).await?,
+                modified_count: 0
+            });
+        }
+
+        self.run(move |connection, table| {
+            let transaction = connection.transaction()?;
+            let matching_rows = matching(&transaction, table, &filter)?;
+
+            let matched_count = matching_rows.len() as u64;
+            for (id, mut document) in matching_rows {
+                apply_changes(&mut document, &changes);
+                bump_version(&mut document);
+                write(&transaction, table, &id, &document)?;
+            }
+            transaction.commit()?;
+
+            // Like MongoDB's $inc, moving the version modifies every matched document
+            Ok(UpdateResult {
+                matched_count,
+                modified_count: matched_count
+            })
+        }).await
+    }
+
+    async fn patch(&self, filter: Document, patch: &Patch) -> Result<UpdateResult, ApiError> {
+        if patch.is_empty() {
+            return Ok(UpdateResult {
+                matched_count: self.count(filter).await?,
+                modified_count: 0
+            });
+        }
+        let patch = patch.clone();
+
+        self.run(move |connection, table| {
+            let transaction = connection.transaction()?;
+            let matching_rows = matching(&transaction, table, &filter)?;
+
+            let matched_count = matching_rows.len() as u64;
+            for (id, mut document) in matching_rows {
+                apply_changes(&mut document, &patch.set);
+                apply_unset(&mut document, &patch.unset);
+                bump_version(&mut document);
+                write(&transaction, table, &id, &document)?;
+            }
+            transaction.commit()?;
+
+            Ok(UpdateResult {
+                matched_count,
+                modified_count: matched_count
+            })
+        }).await
+    }
+
+    async fn replace(&self, filter: Document, data: &T) -> Result<UpdateResult, ApiError> {
+        let replacement = model_to_document(data)?;
+
+        self.run(move |connection, table| {
+            let (id, mut document) = match matching(connection, table, &filter)?.into_iter().next() {
+                Some(row) => row,
+                None => return Ok(UpdateResult { matched_count: 0, modified_count: 0 })
+            };
+
+            let version = version_of(&document);
+            apply_replacement(&mut document, &replacement);
+            document.insert("version", version + 1);
+            let transaction = connection.transaction()?;
+            write(&transaction, table, &id, &document)?;
+            transaction.commit()?;
+
+            Ok(UpdateResult {
+                matched_count: 1,
+                modified_count: 1
+            })
+        }).await
+    }
+
+    async fn delete_many(&self, filter: Document) -> Result<DeleteResult, ApiError> {
+        self.run(move |connection, table| {
+            let transaction = connection.transaction()?;
+            let mut deleted_count = 0;
+            for (id, _) in matching(&transaction, table, &filter)? {
+                deleted_count += transaction.execute(&format!("DELETE FROM {} WHERE id = ?1", table), params![id])?;
+                transaction.execute(&format!("DELETE FROM {} WHERE id = ?1", keys_table(table)), params![id])?;
+            }
+            transaction.commit()?;
+
+            Ok(DeleteResult {
+                deleted_count: deleted_count as u64
+            })
+        }).await
+    }
+}
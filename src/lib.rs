@@ -0,0 +1,119 @@
+//! Everything behind MI Presency API, shared by the server (`mi-presency-api`)
+//! and the admin command-line tool (`mi-presency-admin`).
+
+#[macro_use]
+extern crate rocket;
+
+pub mod models;
+pub mod database;
+pub mod api;
+pub mod config;
+
+use std::time::Duration;
+
+use rocket::{fairing::AdHoc, Build, Rocket};
+use crate::database::Database;
+use crate::config::Config;
+use crate::api::{
+    student_api::{
+        get_student, 
+        post_student, 
+        get_all_students,
+        find_students,
+        put_student,
+        delete_student,
+        get_student_by_id,
+        put_student_by_id,
+        patch_student_by_id,
+        delete_student_by_id,
+        restore_student,
+        post_student_token
+    }, 
+    teacher_api::{
+        get_teacher, 
+        post_teacher, 
+        get_all_teachers,
+        put_teacher,
+        delete_teacher,
+        get_teacher_by_id,
+        put_teacher_by_id,
+        patch_teacher_by_id,
+        delete_teacher_by_id,
+        restore_teacher
+    },
+    checkin_api::{
+        get_checkin_qr,
+        post_checkin_qr
+    },
+    attendance_api::{get_attendance_stream, AttendanceFeed},
+    device_api::{get_all_devices, get_device_by_id, post_device, patch_device_by_id, delete_device_by_id, get_device_channel, DeviceHub},
+    webhook_api::{get_all_webhooks, get_webhook_by_id, post_webhook, patch_webhook_by_id, delete_webhook_by_id, get_webhook_deliveries, test_webhook},
+    webhook_delivery::Webhooks,
+    lesson_api::{post_lesson_close, LateAfter},
+    audit_api::get_audit,
+    admin_api::{purge_deleted, get_backup, post_restore, DeletedRetention},
+    auth::AdminToken,
+    utils::QrSigner,
+    catchers::default_catcher
+};
+
+/// The server with every route, catcher and piece of state, ready to launch or to be
+/// handed to a local client. `db` is used as given, nothing is migrated or seeded.
+/// Webhook deliveries are made in the background once it's launched.
+pub fn rocket(config: &Config, db: Database) -> Rocket<Build> {
+    let qr_signer = QrSigner::new(&config.qr_secret, config.qr_ttl_seconds);
+    let admin_token = AdminToken(config.admin_token.clone());
+    let deleted_retention = DeletedRetention(Duration::from_secs(config.deleted_retention_days * 24 * 60 * 60));
+    let late_after = LateAfter(Duration::from_secs(config.late_after_minutes.max(0) as u64 * 60));
+    let webhooks = Webhooks::new(&db, config.webhook_max_attempts, Duration::from_secs(config.webhook_retry_seconds));
+    let delivery_worker = AdHoc::on_liftoff("Webhook deliveries", |rocket| Box::pin(async move {
+        if let Some(webhooks) = rocket.state::<Webhooks>() {
+            rocket::tokio::spawn(webhooks.clone().run(rocket.shutdown()));
+        }
+    }));
+    rocket::build().manage(db).manage(qr_signer).manage(admin_token).manage(deleted_retention).manage(late_after).manage(AttendanceFeed::new()).manage(DeviceHub::new()).manage(webhooks).attach(delivery_worker).mount("/", routes![
+        get_student, 
+        get_teacher, 
+        post_teacher, 
+        post_student, 
+        get_all_students, 
+        find_students,
+        get_all_teachers,
+        put_student,
+        put_teacher,
+        delete_student,
+        delete_teacher,
+        get_student_by_id,
+        put_student_by_id,
+        patch_student_by_id,
+        delete_student_by_id,
+        get_teacher_by_id,
+        put_teacher_by_id,
+        patch_teacher_by_id,
+        delete_teacher_by_id,
+        restore_student,
+        restore_teacher,
+        post_student_token,
+        get_checkin_qr,
+        post_checkin_qr,
+        get_attendance_stream,
+        get_all_devices,
+        get_device_by_id,
+        post_device,
+        patch_device_by_id,
+        delete_device_by_id,
+        get_device_channel,
+        get_all_webhooks,
+        get_webhook_by_id,
+        post_webhook,
+        patch_webhook_by_id,
+        delete_webhook_by_id,
+        get_webhook_deliveries,
+        test_webhook,
+        post_lesson_close,
+        get_audit,
+        purge_deleted,
+        get_backup,
+        post_restore
+    ]).register("/", catchers![default_catcher])
+}
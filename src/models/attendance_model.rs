@@ -0,0 +1,27 @@
+use mongodb::bson::{oid::ObjectId, DateTime};
+use serde::{Serialize, Deserialize};
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Attendance {
+    #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
+    pub id: Option<ObjectId>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub student_id: Option<ObjectId>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub class_id: Option<ObjectId>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub lesson: Option<String>,
+    /// How the student checked in, e.g. `"qr"`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub method: Option<String>,
+    /// The device a card was tapped on, with the id it gave the tap
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub device_id: Option<ObjectId>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tap_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub checked_in_at: Option<DateTime>,
+    /// Moves up with every change, see `Repository`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub version: Option<i64>,
+}
@@ -0,0 +1,37 @@
+use mongodb::bson::{oid::ObjectId, Bson, DateTime, Document};
+use serde::{Serialize, Deserialize};
+
+/// One change made to a stored document, kept in an append-only collection
+#[derive(Debug, Serialize, Deserialize)]
+pub struct AuditEntry {
+    #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
+    pub id: Option<ObjectId>,
+    /// `"insert"`, `"update"`, `"replace"`, `"delete"`, `"restore"` or `"purge"`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub action: Option<String>,
+    /// Which model was changed, e.g. `"Student"`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub entity: Option<String>,
+    /// The `_id` of the changed document
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub entity_id: Option<Bson>,
+    /// The document before the change, missing for inserts
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub before: Option<Document>,
+    /// The document after the change, missing for deletes
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub after: Option<Document>,
+    /// `"admin"`, `"teacher"`, `"device"`, `"student"` or `"anonymous"`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub actor: Option<String>,
+    /// The teacher's, device's or student's `_id`, when `actor` is one of them
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub actor_id: Option<ObjectId>,
+    /// Method and URI of the request, e.g. `"DELETE /student/65a0..."`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub route: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub client_ip: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub at: Option<DateTime>,
+}
@@ -0,0 +1,32 @@
+use mongodb::bson::{oid::ObjectId, DateTime};
+use serde::{Serialize, Deserialize};
+
+/// A card reader in a classroom, connected over `GET /device/channel`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Device {
+    #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
+    pub id: Option<ObjectId>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    /// The class whose lessons the taps are checked in to
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub class_id: Option<ObjectId>,
+    /// What the device signs in with as `Authorization: Bearer <token>`, made up when it's added
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub token: Option<String>,
+    /// Taps of the same card this close together are taken as one by the device
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub debounce_seconds: Option<i64>,
+    /// Shown on the device's display while it waits for taps
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub display_message: Option<String>,
+    /// Cards refused whoever they belong to, e.g. reported lost
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub blocked_cards: Option<Vec<String>>,
+    /// When the device was deleted, deleted devices can't connect any more
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub deleted_at: Option<DateTime>,
+    /// Moves up with every change, see `Repository`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub version: Option<i64>,
+}
@@ -0,0 +1,174 @@
+use rocket::{http::Status, request::Request, response::{self, Responder}, serde::json::Json};
+use crate::models::{ErrorResponse, validation::Violation};
+
+/// Every way a request can fail.
+///
+/// Each variant has a stable numeric code and name (see `Error Code MI Presency API.txt`)
+/// so clients can match on them, plus the HTTP status it is answered with.
+#[derive(Debug)]
+pub enum ApiError {
+    /// The database failed or isn't reachable
+    Database(String),
+    /// The request parameters or body are missing or malformed
+    BadParams(String),
+    /// The database handed back a different model than the one asked for
+    UnexpectedType(String),
+    /// The scanned QR payload is malformed, forged or expired
+    QrPayload(String),
+    /// The student isn't allowed to check in to the lesson
+    Checkin(String),
+    /// Nothing matches the given parameters
+    NotFound(String),
+    /// The change clashes with data that already exists
+    Conflict(String),
+    /// The change would touch several documents and wasn't confirmed
+    ConfirmationRequired(String),
+    /// Fields of the request don't follow the model's rules, each one is listed
+    Validation(Vec<Violation>),
+    /// The request has no credentials, or wrong ones, for what it asks
+    Unauthorized(String),
+    /// The credentials are fine but don't allow what the request asks
+    Forbidden(String),
+    /// The document changed since the version the request was based on
+    PreconditionFailed(String),
+    /// The change has to say which version it's based on, with `If-Match`
+    PreconditionRequired(String),
+}
+
+impl ApiError {
+    pub fn code(&self) -> u16 {
+        match self {
+            ApiError::Database(_) => 0,
+            ApiError::BadParams(_) => 1,
+            ApiError::UnexpectedType(_) => 2,
+            ApiError::QrPayload(_) => 3,
+            ApiError::Checkin(_) => 4,
+            ApiError::NotFound(_) => 5,
+            ApiError::Conflict(_) => 6,
+            ApiError::ConfirmationRequired(_) => 7,
+            ApiError::Validation(_) => 8,
+            ApiError::Unauthorized(_) => 9,
+            ApiError::Forbidden(_) => 10,
+            ApiError::PreconditionFailed(_) => 11,
+            ApiError::PreconditionRequired(_) => 12,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            ApiError::Database(_) => "database_error",
+            ApiError::BadParams(_) => "user_params_error",
+            ApiError::UnexpectedType(_) => "type_error",
+            ApiError::QrPayload(_) => "qr_payload_error",
+            ApiError::Checkin(_) => "checkin_error",
+            ApiError::NotFound(_) => "not_found_error",
+            ApiError::Conflict(_) => "conflict_error",
+            ApiError::ConfirmationRequired(_) => "confirmation_required_error",
+            ApiError::Validation(_) => "validation_error",
+            ApiError::Unauthorized(_) => "unauthorized_error",
+            ApiError::Forbidden(_) => "forbidden_error",
+            ApiError::PreconditionFailed(_) => "precondition_failed_error",
+            ApiError::PreconditionRequired(_) => "precondition_required_error",
+        }
+    }
+
+    pub fn status(&self) -> Status {
+        match self {
+            ApiError::Database(_) | ApiError::UnexpectedType(_) => Status::InternalServerError,
+            ApiError::BadParams(_) | ApiError::QrPayload(_) => Status::BadRequest,
+            ApiError::Checkin(_) => Status::Forbidden,
+            ApiError::NotFound(_) => Status::NotFound,
+            ApiError::Conflict(_) => Status::Conflict,
+            ApiError::ConfirmationRequired(_) => Status::PreconditionRequired,
+            ApiError::Validation(_) => Status::UnprocessableEntity,
+            ApiError::Unauthorized(_) => Status::Unauthorized,
+            ApiError::Forbidden(_) => Status::Forbidden,
+            ApiError::PreconditionFailed(_) => Status::PreconditionFailed,
+            ApiError::PreconditionRequired(_) => Status::PreconditionRequired,
+        }
+    }
+
+    pub fn message(&self) -> &str {
+        match self {
+            ApiError::Database(message)
+            | ApiError::BadParams(message)
+            | ApiError::UnexpectedType(message)
+            | ApiError::QrPayload(message)
+            | ApiError::Checkin(message)
+            | ApiError::NotFound(message)
+            | ApiError::Conflict(message)
+            | ApiError::ConfirmationRequired(message)
+            | ApiError::Unauthorized(message)
+            | ApiError::Forbidden(message)
+            | ApiError::PreconditionFailed(message)
+            | ApiError::PreconditionRequired(message) => message,
+            ApiError::Validation(_) => "Some fields aren't valid, see violations!",
+        }
+    }
+
+    /// Moves the fields of a validation error under `parent`, for models nested in a request body
+    pub fn within(self, parent: &str) -> Self {
+        match self {
+            ApiError::Validation(violations) => ApiError::Validation(
+                violations
+                    .into_iter()
+                    .map(|violation| Violation {
+                        field: format!("{}.{}", parent, violation.field),
+                        message: violation.message
+                    })
+                    .collect()
+            ),
+            other => other
+        }
+    }
+
+    /// The error every route answers with when its parameters don't make sense
+    pub fn wrong_params() -> Self {
+        ApiError::BadParams(String::from("Well you should try to give the correct parameters next time.."))
+    }
+}
+
+impl std::fmt::Display for ApiError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} ({}): {}", self.name(), self.code(), self.message())
+    }
+}
+
+impl From<mongodb::error::Error> for ApiError {
+    fn from(err: mongodb::error::Error) -> Self {
+        use mongodb::error::{ErrorKind, WriteFailure};
+
+        // 11000 is MongoDB's duplicate key error
+        match err.kind.as_ref() {
+            ErrorKind::Write(WriteFailure::WriteError(write_error)) if write_error.code == 11000 => {
+                ApiError::Conflict(write_error.message.clone())
+            },
+            _ => ApiError::Database(err.to_string())
+        }
+    }
+}
+
+impl From<rusqlite::Error> for ApiError {
+    fn from(err: rusqlite::Error) -> Self {
+        match err.sqlite_error_code() {
+            Some(rusqlite::ErrorCode::ConstraintViolation) => ApiError::Conflict(err.to_string()),
+            _ => ApiError::Database(err.to_string())
+        }
+    }
+}
+
+impl From<&ApiError> for ErrorResponse {
+    fn from(err: &ApiError) -> Self {
+        let mut response = ErrorResponse::new(err.message().to_string(), err.code(), err.name());
+        if let ApiError::Validation(violations) = err {
+            response.violations = violations.clone();
+        }
+        response
+    }
+}
+
+impl<'r> Responder<'r, 'static> for ApiError {
+    fn respond_to(self, request: &'r Request<'_>) -> response::Result<'static> {
+        (self.status(), Json(ErrorResponse::from(&self))).respond_to(request)
+    }
+}
@@ -0,0 +1,14 @@
+use mongodb::bson::DateTime;
+use serde::{Serialize, Deserialize};
+
+/// A migration that has been applied to the stored documents, see `database::migrations`
+#[derive(Debug, Serialize, Deserialize)]
+pub struct AppliedMigration {
+    /// The `id` of the migration
+    #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
+    pub id: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub applied_at: Option<DateTime>,
+}
@@ -1,30 +1,183 @@
 mod student_model;
 mod teacher_model;
+mod attendance_model;
+mod audit_model;
+mod migration_model;
+mod device_model;
+mod webhook_model;
+mod error;
 pub mod utils;
+pub mod validation;
 
-use serde::{Deserialize, Serialize};
+use mongodb::bson::Document;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
 pub use student_model::Student;
 pub use teacher_model::Teacher;
+pub use attendance_model::Attendance;
+pub use audit_model::AuditEntry;
+pub use migration_model::AppliedMigration;
+pub use device_model::Device;
+pub use webhook_model::{Webhook, WebhookDelivery};
+pub use error::ApiError;
+use validation::{Rule, Violation};
 
-#[derive(Debug)]
-pub enum Model {
-    Student(Student),
-    Teacher(Teacher),
+/// Something the API stores in a collection of its own
+pub trait Model: Serialize + DeserializeOwned + Unpin + Send + Sync {
+    /// How the model is called in messages, e.g. "Student"
+    const NAME: &'static str;
+    /// Every field the model has, as stored
+    const FIELDS: &'static [&'static str];
+    /// Fields holding an `ObjectId`, so parameters given as text can be converted
+    const OBJECT_ID_FIELDS: &'static [&'static str];
+    /// What each field has to look like before it's stored, see `validation::validate`
+    const RULES: &'static [(&'static str, &'static [Rule])];
+    /// Fields never copied into the audit log nor answered
+    const SECRET_FIELDS: &'static [&'static str];
+    /// Fields a client may pick documents by when changing several at once, see `utils::params_to_filter`
+    const FILTER_FIELDS: &'static [&'static str] = &[];
+    /// Sets of fields no two stored documents may share all values of, checked on insert
+    const UNIQUE_KEYS: &'static [&'static [&'static str]] = &[];
+}
+
+const NAME_RULES: &[Rule] = &[Rule::Required, Rule::Trim, Rule::Length { min: 1, max: 100 }];
+
+impl Model for Student {
+    const NAME: &'static str = "Student";
+    const FIELDS: &'static [&'static str] = &["_id", "name", "card_id", "class_id", "token", "deleted_at", "version"];
+    const OBJECT_ID_FIELDS: &'static [&'static str] = &["_id", "class_id"];
+    const RULES: &'static [(&'static str, &'static [Rule])] = &[
+        ("name", NAME_RULES),
+        // The UIDs of the cards a reader can scan are 4, 7 or 10 bytes long
+        ("card_id", &[Rule::Trim, Rule::Uppercase, Rule::Pattern {
+            pattern: "[0-9A-F]{8}|[0-9A-F]{14}|[0-9A-F]{20}",
+            description: "has to be a card UID of 4, 7 or 10 bytes written in hexadecimal"
+        }]),
+        ("token", &[Rule::ReadOnly]),
+        ("deleted_at", &[Rule::ReadOnly]),
+        ("version", &[Rule::ReadOnly]),
+    ];
+    const SECRET_FIELDS: &'static [&'static str] = &["token"];
+    const FILTER_FIELDS: &'static [&'static str] = &["_id", "name", "card_id", "class_id"];
+}
+
+impl Model for Teacher {
+    const NAME: &'static str = "Teacher";
+    const FIELDS: &'static [&'static str] = &["_id", "name", "pass", "deleted_at", "version"];
+    const OBJECT_ID_FIELDS: &'static [&'static str] = &["_id"];
+    const RULES: &'static [(&'static str, &'static [Rule])] = &[
+        ("name", NAME_RULES),
+        ("pass", &[Rule::Required, Rule::Length { min: 8, max: 128 }]),
+        ("deleted_at", &[Rule::ReadOnly]),
+        ("version", &[Rule::ReadOnly]),
+    ];
+    const SECRET_FIELDS: &'static [&'static str] = &["pass"];
+    const FILTER_FIELDS: &'static [&'static str] = &["_id", "name"];
+}
+
+impl Model for Device {
+    const NAME: &'static str = "Device";
+    const FIELDS: &'static [&'static str] = &["_id", "name", "class_id", "token", "debounce_seconds", "display_message", "blocked_cards", "deleted_at", "version"];
+    const OBJECT_ID_FIELDS: &'static [&'static str] = &["_id", "class_id"];
+    const RULES: &'static [(&'static str, &'static [Rule])] = &[
+        ("name", NAME_RULES),
+        ("class_id", &[Rule::Required]),
+        ("token", &[Rule::ReadOnly]),
+        ("display_message", &[Rule::Trim, Rule::Length { min: 0, max: 200 }]),
+        // Compared with the card ids of students, stored the same way
+        ("blocked_cards", &[Rule::Trim, Rule::Uppercase]),
+        ("deleted_at", &[Rule::ReadOnly]),
+        ("version", &[Rule::ReadOnly]),
+    ];
+    const SECRET_FIELDS: &'static [&'static str] = &["token"];
+}
+
+impl Model for Webhook {
+    const NAME: &'static str = "Webhook";
+    const FIELDS: &'static [&'static str] = &["_id", "url", "events", "secret", "deleted_at", "version"];
+    const OBJECT_ID_FIELDS: &'static [&'static str] = &["_id"];
+    const RULES: &'static [(&'static str, &'static [Rule])] = &[
+        ("url", &[Rule::Required, Rule::Trim, Rule::Pattern {
+            pattern: r"https?://[^\s/?#]+[^\s]*",
+            description: "has to be an http or https URL"
+        }]),
+        ("events", &[Rule::Required]),
+        // Long enough not to be guessed, the receiver checks signatures with it
+        ("secret", &[Rule::Required, Rule::Length { min: 16, max: 256 }]),
+        ("deleted_at", &[Rule::ReadOnly]),
+        ("version", &[Rule::ReadOnly]),
+    ];
+    const SECRET_FIELDS: &'static [&'static str] = &["secret"];
+}
+
+impl Model for WebhookDelivery {
+    const NAME: &'static str = "Webhook delivery";
+    const FIELDS: &'static [&'static str] = &["_id", "webhook_id", "event", "payload", "status", "attempts", "response_status", "error", "created_at", "next_attempt_at", "last_attempt_at", "version"];
+    const OBJECT_ID_FIELDS: &'static [&'static str] = &["_id", "webhook_id"];
+    const RULES: &'static [(&'static str, &'static [Rule])] = &[];
+    const SECRET_FIELDS: &'static [&'static str] = &[];
+}
+
+impl Model for Attendance {
+    const NAME: &'static str = "Attendance";
+    const FIELDS: &'static [&'static str] = &["_id", "student_id", "class_id", "lesson", "method", "device_id", "tap_id", "checked_in_at", "version"];
+    const OBJECT_ID_FIELDS: &'static [&'static str] = &["_id", "student_id", "class_id", "device_id"];
+    const RULES: &'static [(&'static str, &'static [Rule])] = &[
+        ("student_id", &[Rule::Required]),
+        ("class_id", &[Rule::Required]),
+        ("lesson", &[Rule::Required, Rule::Trim, Rule::Length { min: 1, max: 100 }]),
+        ("method", &[Rule::Required]),
+        ("tap_id", &[Rule::Length { min: 1, max: 100 }]),
+        ("checked_in_at", &[Rule::Required]),
+        ("version", &[Rule::ReadOnly]),
+    ];
+    const SECRET_FIELDS: &'static [&'static str] = &[];
+    // A student checks in to a lesson once, however many scans race each other,
+    // and a tap a device sends again is recorded once
+    const UNIQUE_KEYS: &'static [&'static [&'static str]] = &[&["student_id", "class_id", "lesson"], &["device_id", "tap_id"]];
+}
+
+impl Model for AuditEntry {
+    const NAME: &'static str = "Audit entry";
+    const FIELDS: &'static [&'static str] = &["_id", "action", "entity", "entity_id", "before", "after", "actor", "actor_id", "route", "client_ip", "at"];
+    const OBJECT_ID_FIELDS: &'static [&'static str] = &["_id", "actor_id"];
+    const RULES: &'static [(&'static str, &'static [Rule])] = &[];
+    const SECRET_FIELDS: &'static [&'static str] = &[];
+}
+
+impl Model for AppliedMigration {
+    const NAME: &'static str = "Migration";
+    const FIELDS: &'static [&'static str] = &["_id", "name", "applied_at"];
+    const OBJECT_ID_FIELDS: &'static [&'static str] = &[];
+    const RULES: &'static [(&'static str, &'static [Rule])] = &[];
+    const SECRET_FIELDS: &'static [&'static str] = &[];
+}
+
+/// Stored documents taken as they are, for code reshaping them whatever model they hold
+impl Model for Document {
+    const NAME: &'static str = "Document";
+    const FIELDS: &'static [&'static str] = &[];
+    const OBJECT_ID_FIELDS: &'static [&'static str] = &[];
+    const RULES: &'static [(&'static str, &'static [Rule])] = &[];
+    const SECRET_FIELDS: &'static [&'static str] = &[];
 }
 
 #[derive(Serialize, Deserialize)]
 pub struct ErrorResponse {
     pub message: String,
     pub error_code: u16,
-    pub error_line: u32
+    pub error: String,
+    /// Every field that failed validation, only there for `validation_error`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub violations: Vec<Violation>
 }
 
 impl ErrorResponse {
-    pub fn new(message: String, error_code: u16) -> Self {
+    pub fn new(message: String, error_code: u16, error: &str) -> Self {
         Self {
             message, 
             error_code,
-            error_line:line!()
+            error: error.to_string(),
+            violations: Vec::new()
         }
     }
 }
\ No newline at end of file
@@ -1,4 +1,4 @@
-use mongodb::bson::oid::ObjectId;
+use mongodb::bson::{oid::ObjectId, DateTime};
 use serde::{Serialize, Deserialize};
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -10,16 +10,14 @@ pub struct Student {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub card_id: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub class_id: Option<ObjectId>
+    pub class_id: Option<ObjectId>,
+    /// What the student checks in with as `Authorization: Bearer <token>`, see `POST /student/<id>/token`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub token: Option<String>,
+    /// When the student was deleted, deleted students are only kept until purged
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub deleted_at: Option<DateTime>,
+    /// Moves up with every change, see `Repository`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub version: Option<i64>
 }
-
-impl Student {
-    pub fn empty() -> Self {
-        Self {
-            id: None,
-            name: None,
-            card_id: None,
-            class_id: None,
-        }
-    }
-}
\ No newline at end of file
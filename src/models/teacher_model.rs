@@ -1,5 +1,8 @@
-use mongodb::bson::oid::ObjectId;
+use argon2::{Argon2, PasswordHash, PasswordHasher, PasswordVerifier, password_hash::SaltString};
+use mongodb::bson::{oid::ObjectId, DateTime};
+use rand::RngCore;
 use serde::{Serialize, Deserialize};
+use crate::models::ApiError;
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Teacher {
@@ -7,16 +10,44 @@ pub struct Teacher {
     pub id: Option<ObjectId>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub name: Option<String>,
+    /// Stored as a salted argon2 hash, see `Teacher::hash_pass`
     #[serde(skip_serializing_if = "Option::is_none")]
     pub pass: Option<String>,
+    /// When the teacher was deleted, deleted teachers are only kept until purged
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub deleted_at: Option<DateTime>,
+    /// Moves up with every change, see `Repository`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub version: Option<i64>,
 }
 
 impl Teacher {
-    pub fn empty() -> Self {
-        Self {
-            id: None,
-            name: None,
-            pass: None
-        }
+    /// `pass` as it's stored: an argon2 hash with a salt of its own, in the PHC string format
+    pub fn hash_pass(pass: &str) -> Result<String, ApiError> {
+        let mut salt = [0u8; 16];
+        rand::thread_rng().fill_bytes(&mut salt);
+        let salt = SaltString::encode_b64(&salt)
+            .map_err(|err| ApiError::UnexpectedType(format!("The pass can't be salted: {}", err)))?;
+        Argon2::default().hash_password(pass.as_bytes(), &salt)
+            .map(|hash| hash.to_string())
+            .map_err(|err| ApiError::UnexpectedType(format!("The pass can't be hashed: {}", err)))
     }
-}
\ No newline at end of file
+
+    /// Whether a pass was hashed by `hash_pass` already, passes stored before weren't
+    pub fn is_hashed(pass: &str) -> bool {
+        PasswordHash::new(pass).is_ok()
+    }
+
+    /// The teacher with its pass hashed, as validated teachers are stored
+    pub fn with_hashed_pass(self) -> Result<Self, ApiError> {
+        let pass = self.pass.as_deref().map(Self::hash_pass).transpose()?;
+        Ok(Self { pass, ..self })
+    }
+
+    /// Whether `pass` is the one the teacher's stored hash was made of
+    pub fn verify_pass(&self, pass: &str) -> bool {
+        self.pass.as_deref()
+            .and_then(|hash| PasswordHash::new(hash).ok())
+            .is_some_and(|hash| Argon2::default().verify_password(pass.as_bytes(), &hash).is_ok())
+    }
+}
@@ -1,77 +1,84 @@
 use std::collections::HashMap;
-use mongodb::bson::oid::ObjectId;
-use crate::models::Model;
+use mongodb::bson::{oid::ObjectId, Bson, DateTime, Document};
+use crate::models::{ApiError, Model};
 
 /// For converting from user request to be understood by MongoDB
 pub fn string_to_oid(oid_text: &str) -> Option<ObjectId> {
-    match ObjectId::parse_str(oid_text) {
-        Ok(result_oid) => {
-            Some(result_oid)
-        }
-        Err(_) => {
-            None
-        }
-    }
+    ObjectId::parse_str(oid_text).ok()
 }
 
-fn insert_to_document<T: Into<mongodb::bson::Bson>>(target_document: &mut mongodb::bson::Document, key: &str, value: Option<T>) {
-    match value {
-        Some(val) => {
-            target_document.insert(key, val);
-        },
-        None => ()
-    }
+/// Like `string_to_oid`, for ids that have to be valid (e.g. in a route's path)
+pub fn parse_oid(oid_text: &str) -> Result<ObjectId, ApiError> {
+    string_to_oid(oid_text).ok_or_else(|| ApiError::BadParams(format!("{} is not a valid id!", oid_text)))
 }
 
-fn hashmap_item_to_oid(hashmap: &HashMap<String, Option<String>>, key: &str) -> Option<ObjectId> {
-    hashmap.get(key)
-    .and_then(|k| k.as_ref())
-    .and_then(|k_str| string_to_oid(k_str))
+/// Reads an RFC 3339 time given as `parameter`, e.g. `2024-01-31T07:00:00Z`
+pub fn parse_time(time: &str, parameter: &str) -> Result<DateTime, ApiError> {
+    DateTime::parse_rfc3339_str(time)
+        .map_err(|_| ApiError::BadParams(format!("{} has to be an RFC 3339 time, e.g. 2024-01-31T07:00:00Z!", parameter)))
+}
+
+/// Narrows `filter` to documents that aren't soft deleted, what every read sees by default
+pub fn not_deleted(mut filter: Document) -> Document {
+    filter.insert("deleted_at", Bson::Null);
+    filter
+}
+
+/// `data` as it's answered, without its `Model::SECRET_FIELDS`
+pub fn public_json<M: Model>(data: &M) -> Result<serde_json::Value, serde_json::Error> {
+    let mut value = serde_json::to_value(data)?;
+    if let Some(fields) = value.as_object_mut() {
+        for field in M::SECRET_FIELDS {
+            fields.remove(*field);
+        }
+    }
+    Ok(value)
 }
 
 /// For communication between Rust and MongoDB
-pub fn hashmap_to_model_document(hashmap: &HashMap<String, Option<String>>, model: Model) -> mongodb::bson::Document {
-    match model {
-        Model::Student(_) => {
-            let mut retval: mongodb::bson::Document = mongodb::bson::Document::new();
-            
-            /*
+///
+/// Parameters that are `None` are left out, `id` is stored as `_id` and the
+/// model's `ObjectId` fields are converted (and left out when they don't parse).
+pub fn hashmap_to_model_document<M: Model>(hashmap: &HashMap<String, Option<String>>) -> mongodb::bson::Document {
+    let mut retval: mongodb::bson::Document = mongodb::bson::Document::new();
+
+    for (key, value) in hashmap {
+        let key = if key == "id" { "_id" } else { key.as_str() };
+        let value = match value {
+            Some(value) => value,
+            None => continue
+        };
 
-            "_id": hashmap_item_to_oid(&hashmap, "id"),
-            "name": hashmap.get("name"),
-            "class_id": hashmap_item_to_oid(&hashmap, "class_id"),
-            "card_id": hashmap.get("card_id"),
-            
-            */
+        if M::OBJECT_ID_FIELDS.contains(&key) {
+            if let Some(oid) = string_to_oid(value) {
+                retval.insert(key, oid);
+            }
+        } else {
+            retval.insert(key, value.clone());
+        }
+    }
 
-            insert_to_document(&mut retval, "_id", hashmap_item_to_oid(&hashmap, "id"));
-            insert_to_document(&mut retval, "name", hashmap.get("name").and_then(|x| x.clone()));
-            insert_to_document(&mut retval, "class_id", hashmap_item_to_oid(&hashmap, "class_id"));
-            insert_to_document(&mut retval, "card_id", hashmap.get("card_id").and_then(|x| x.clone()));
+    retval
+}
 
-            retval
-        },
-        Model::Teacher(_) => {
-            // doc! {
-            //     "_id": hashmap_item_to_oid(&hashmap, "id"),
-            //     "name": hashmap.get("name"),
-            //     "pass": hashmap.get("pass"),
-            // };
-            let mut retval: mongodb::bson::Document = mongodb::bson::Document::new();
-            
-            /*
-            
-            "_id": hashmap_item_to_oid(&hashmap, "id"),
-            "name": hashmap.get("name"),
-            "pass": hashmap.get("pass"),
-            
-            */
+/// Like `hashmap_to_model_document`, for filters of requests that change data:
+/// a field that isn't one of `M::FILTER_FIELDS` or an invalid id is refused instead
+/// of left out, as leaving it out would widen the filter to more documents than asked for.
+pub fn params_to_filter<M: Model>(params: HashMap<String, String>) -> Result<Document, ApiError> {
+    let mut filter: Document = Document::new();
 
-            insert_to_document(&mut retval, "_id", hashmap_item_to_oid(&hashmap, "id"));
-            insert_to_document(&mut retval, "name", hashmap.get("name").and_then(|x| x.clone()));
-            insert_to_document(&mut retval, "pass", hashmap.get("pass").and_then(|x| x.clone()));
+    for (key, value) in params {
+        let key = if key == "id" { String::from("_id") } else { key };
+        if !M::FILTER_FIELDS.contains(&key.as_str()) {
+            return Err(ApiError::BadParams(format!("{} can't be filtered on {}!", M::NAME, key)));
+        }
 
-            retval
+        if M::OBJECT_ID_FIELDS.contains(&key.as_str()) {
+            filter.insert(key, parse_oid(&value)?);
+        } else {
+            filter.insert(key, value);
         }
     }
-}
\ No newline at end of file
+
+    Ok(filter)
+}
@@ -0,0 +1,125 @@
+use mongodb::bson::{Bson, Document};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use crate::models::{ApiError, Model};
+
+/// One check a field of a model goes through before it's stored, see `Model::RULES`
+#[derive(Debug)]
+pub enum Rule {
+    /// Has to be given when the model is created, and can't be cleared afterwards
+    Required,
+    /// Managed by the API itself, requests can neither set nor clear it
+    ReadOnly,
+    /// Surrounding whitespace is dropped before the rules after it look at the text
+    Trim,
+    /// Letters are made uppercase, so text written either way is stored and compared one way
+    Uppercase,
+    /// Text of at least `min` and at most `max` characters
+    Length { min: usize, max: usize },
+    /// Text matching `pattern` as a whole, `description` tells a person what that means
+    Pattern { pattern: &'static str, description: &'static str },
+}
+
+/// Something wrong with one field of a request
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Violation {
+    /// Where the field sits in the request body, e.g. `new_data.name`
+    pub field: String,
+    pub message: String,
+}
+
+impl Violation {
+    pub fn new(field: &str, message: &str) -> Self {
+        Self {
+            field: field.to_string(),
+            message: message.to_string()
+        }
+    }
+}
+
+/// Checks `data` against the rules of `M` and answers it with its text trimmed.
+/// When `creating`, a missing required field is a violation too.
+pub fn validate<M: Model>(data: M, creating: bool) -> Result<M, ApiError> {
+    let mut document = mongodb::bson::to_document(&data)
+        .map_err(|err| ApiError::UnexpectedType(format!("{} can't be validated: {}", M::NAME, err)))?;
+    validate_document::<M>(&mut document, &[], creating)?;
+    mongodb::bson::from_document(document)
+        .map_err(|err| ApiError::UnexpectedType(format!("{} can't be validated: {}", M::NAME, err)))
+}
+
+/// `validate` for a model already turned into a document, where `cleared` are
+/// the fields a patch removes. Every violation is reported at once.
+pub fn validate_document<M: Model>(document: &mut Document, cleared: &[String], creating: bool) -> Result<(), ApiError> {
+    let mut violations: Vec<Violation> = Vec::new();
+
+    for (field, rules) in M::RULES {
+        let required = rules.iter().any(|rule| matches!(rule, Rule::Required));
+        let read_only = rules.iter().any(|rule| matches!(rule, Rule::ReadOnly));
+        let is_cleared = cleared.iter().any(|cleared| cleared == field);
+        match document.get_mut(*field) {
+            None | Some(Bson::Null) => {
+                if read_only && is_cleared {
+                    violations.push(Violation::new(field, "can't be changed"));
+                } else if required && (creating || is_cleared) {
+                    violations.push(Violation::new(field, "is required"));
+                }
+            },
+            Some(_) if read_only => violations.push(Violation::new(field, "can't be changed")),
+            Some(value) => check_value(field, value, rules, &mut violations)
+        }
+    }
+
+    if violations.is_empty() {
+        Ok(())
+    } else {
+        Err(ApiError::Validation(violations))
+    }
+}
+
+/// `text` rewritten by the rules of `M`'s `field` that rewrite it, the way it's stored,
+/// e.g. a card id to look a student up by
+pub fn normalized<M: Model>(field: &str, text: &str) -> String {
+    let rules = M::RULES.iter().find(|(name, _)| *name == field).map(|(_, rules)| *rules).unwrap_or(&[]);
+    rules.iter().fold(text.to_string(), |text, rule| match rule {
+        Rule::Trim => text.trim().to_string(),
+        Rule::Uppercase => text.to_uppercase(),
+        _ => text
+    })
+}
+
+fn check_value(field: &str, value: &mut Bson, rules: &[Rule], violations: &mut Vec<Violation>) {
+    // Only text has rules to go through, the types themselves are checked when the body is read
+    match value {
+        Bson::String(text) => check_text(field, text, rules, violations),
+        Bson::Array(values) => for value in values.iter_mut() {
+            if let Bson::String(text) = value {
+                check_text(field, text, rules, violations);
+            }
+        },
+        _ => {}
+    }
+}
+
+fn check_text(field: &str, text: &mut String, rules: &[Rule], violations: &mut Vec<Violation>) {
+    for rule in rules {
+        match rule {
+            Rule::Required | Rule::ReadOnly => {},
+            Rule::Trim => *text = text.trim().to_string(),
+            Rule::Uppercase => *text = text.to_uppercase(),
+            Rule::Length { min, max } => {
+                let length = text.chars().count();
+                if length == 0 && *min > 0 {
+                    violations.push(Violation::new(field, "can't be blank"));
+                } else if length < *min || length > *max {
+                    violations.push(Violation::new(field, &format!("has to be between {} and {} characters long", min, max)));
+                }
+            },
+            Rule::Pattern { pattern, description } => {
+                let pattern = Regex::new(&format!("^(?:{})$", pattern)).expect("validation patterns are valid regexes");
+                if !pattern.is_match(text) {
+                    violations.push(Violation::new(field, description));
+                }
+            }
+        }
+    }
+}
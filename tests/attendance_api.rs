@@ -0,0 +1,90 @@
+mod common;
+
+use std::io::Read;
+
+use mongodb::bson::oid::ObjectId;
+use rocket::http::{Header, Status};
+use rocket::local::blocking::{Client, LocalResponse};
+use serde_json::json;
+use mi_presency_api::api::attendance_api::AttendanceFeed;
+use mi_presency_api::models::Attendance;
+use common::{admin, assert_error, check_in, client, create};
+
+const CLASS_A: &str = "65a0000000000000000000a1";
+const CLASS_B: &str = "65a0000000000000000000b2";
+
+fn create_student(client: &Client, name: &str, class_id: &str) -> String {
+    create(client, "/student", json!({"name": name, "class_id": {"$oid": class_id}}))
+}
+
+fn attendance(lesson: usize) -> Attendance {
+    Attendance {
+        id: Some(ObjectId::new()),
+        student_id: Some(ObjectId::new()),
+        class_id: ObjectId::parse_str(CLASS_A).ok(),
+        lesson: Some(format!("Lesson {}", lesson)),
+        method: Some(String::from("qr")),
+        device_id: None,
+        tap_id: None,
+        checked_in_at: None,
+        version: None,
+    }
+}
+
+/// Reads the stream until `until` shows up in it, it never ends by itself
+fn read_until(response: &mut LocalResponse<'_>, until: &str) -> String {
+    let mut read = String::new();
+    let mut chunk = [0u8; 1024];
+    while !read.contains(until) {
+        let length = response.read(&mut chunk).expect("the stream is read");
+        assert!(length > 0, "the stream ended before {} in {}", until, read);
+        read.push_str(&String::from_utf8_lossy(&chunk[..length]));
+    }
+    read
+}
+
+#[test]
+fn get_attendance_stream_needs_staff() {
+    let client = client();
+    assert_error(client.get("/attendance/stream").dispatch(), Status::Unauthorized, 9);
+}
+
+#[test]
+fn get_attendance_stream_replays_what_came_after_the_last_event_id() {
+    let client = client();
+    let first = create_student(&client, "Budi Santoso", CLASS_A);
+    let second = create_student(&client, "Siti Rahma", CLASS_B);
+    let third = create_student(&client, "Bayu Pratama", CLASS_A);
+    check_in(&client, &first, CLASS_A, "Math");
+    check_in(&client, &second, CLASS_B, "Math");
+    check_in(&client, &third, CLASS_A, "Math");
+
+    let mut response = client.get(format!("/attendance/stream?class_id={}", CLASS_A))
+        .header(admin())
+        .header(Header::new("Last-Event-ID", "1"))
+        .dispatch();
+    assert_eq!(response.status(), Status::Ok);
+    let read = read_until(&mut response, &third);
+    // The second is of another class
+    assert!(read.contains("id:3") && !read.contains("id:1") && !read.contains("id:2"), "unexpected events in {}", read);
+    assert!(!read.contains(&first), "unexpected check-ins in {}", read);
+}
+
+#[test]
+fn the_feed_keeps_the_latest_thousand_events() {
+    let feed = AttendanceFeed::new();
+    for lesson in 0..1005 {
+        feed.publish(attendance(lesson));
+    }
+
+    let (missed, _) = feed.subscribe(Some(0));
+    assert_eq!(missed.len(), 1000);
+    assert_eq!(missed.first().map(|event| event.id), Some(6));
+    assert_eq!(missed.last().map(|event| event.id), Some(1005));
+
+    let (missed, _) = feed.subscribe(Some(1003));
+    assert_eq!(missed.iter().map(|event| event.id).collect::<Vec<_>>(), vec![1004, 1005]);
+    // An id from before a restart gets everything kept
+    assert_eq!(feed.subscribe(Some(5000)).0.len(), 1000);
+    assert!(feed.subscribe(None).0.is_empty());
+}
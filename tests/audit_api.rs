@@ -0,0 +1,26 @@
+mod common;
+
+use rocket::http::{ContentType, Status};
+use serde_json::json;
+use common::{admin, assert_error, client, create, json, oid};
+
+#[test]
+fn get_audit_filters_by_every_audited_entity() {
+    let client = client();
+    let student_id = create(&client, "/student", json!({"name": "Budi Santoso"}));
+    let teacher_id = create(&client, "/teacher", json!({"name": "Pak Hadi", "pass": "papan-tulis"}));
+    let device_id = create(&client, "/device", json!({"name": "Door of 7A", "class_id": {"$oid": "65a0000000000000000000a1"}}));
+    let response = client.post("/webhook").header(admin()).header(ContentType::JSON)
+        .body(json!({"url": "http://localhost/hooks", "events": ["attendance.late"], "secret": "a webhook secret of some length"}).to_string())
+        .dispatch();
+    let webhook_id = oid(&json(response)["insertedId"]);
+
+    // Written either way
+    for (entity, id) in [("student", student_id), ("Teacher", teacher_id), ("device", device_id), ("WEBHOOK", webhook_id)] {
+        let entries = json(client.get(format!("/audit?entity={}", entity)).header(admin()).dispatch());
+        assert_eq!(entries["total"], 1, "unexpected entries of {}", entity);
+        assert_eq!(oid(&entries["items"][0]["entity_id"]), id);
+        assert_eq!(entries["items"][0]["actor"], "admin");
+    }
+    assert_error(client.get("/audit?entity=classroom").header(admin()).dispatch(), Status::BadRequest, 1);
+}
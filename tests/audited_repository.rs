@@ -0,0 +1,82 @@
+use mongodb::bson::{doc, Document};
+use mi_presency_api::database::{AuditContext, Database, DeleteResult, InsertResult, ListOptions, Page, Patch, Repository, UpdateResult};
+use mi_presency_api::models::{ApiError, Student};
+
+/// Students, with every replacement preceded by another client's rename of the student
+struct RenamedMeanwhile<'a> {
+    students: &'a dyn Repository<Student>,
+}
+
+#[rocket::async_trait]
+impl Repository<Student> for RenamedMeanwhile<'_> {
+    async fn get(&self, filter: Document) -> Result<Student, ApiError> {
+        self.students.get(filter).await
+    }
+
+    async fn list(&self, filter: Document) -> Result<Vec<Student>, ApiError> {
+        self.students.list(filter).await
+    }
+
+    async fn list_page(&self, filter: Document, options: ListOptions) -> Result<Page<Student>, ApiError> {
+        self.students.list_page(filter, options).await
+    }
+
+    async fn count(&self, filter: Document) -> Result<u64, ApiError> {
+        self.students.count(filter).await
+    }
+
+    async fn insert(&self, data: &Student) -> Result<InsertResult, ApiError> {
+        self.students.insert(data).await
+    }
+
+    async fn update(&self, filter: Document, data: &Student) -> Result<UpdateResult, ApiError> {
+        self.students.update(filter, data).await
+    }
+
+    async fn patch(&self, filter: Document, patch: &Patch) -> Result<UpdateResult, ApiError> {
+        self.students.patch(filter, patch).await
+    }
+
+    async fn replace(&self, filter: Document, data: &Student) -> Result<UpdateResult, ApiError> {
+        let rename = Patch { set: doc! {"name": "Budi S."}, unset: vec![] };
+        self.students.patch(doc! {"_id": filter.get("_id").cloned()}, &rename).await?;
+        self.students.replace(filter, data).await
+    }
+
+    async fn delete_many(&self, filter: Document) -> Result<DeleteResult, ApiError> {
+        self.students.delete_many(filter).await
+    }
+}
+
+fn student(name: &str) -> Student {
+    Student {
+        id: None,
+        name: Some(name.to_string()),
+        card_id: None,
+        class_id: None,
+        token: None,
+        deleted_at: None,
+        version: None,
+    }
+}
+
+#[rocket::async_test]
+async fn a_replace_doesnt_overwrite_a_change_made_after_its_snapshot() {
+    let db = Database::memory();
+    let id = db.student_database.insert(&student("Budi")).await.expect("the student is stored").inserted_id;
+    let audit = AuditContext {
+        actor: String::from("admin"),
+        actor_id: None,
+        route: String::from("PUT /student"),
+        client_ip: None,
+    };
+
+    let students = RenamedMeanwhile { students: db.student_database.as_ref() };
+    // `If-Match: *`, the version is the one snapshotted
+    let result = db.audited(&students, &audit).replace(doc! {"_id": id.clone()}, &student("Budi Santoso")).await.expect("the replace goes through");
+    assert_eq!(result.matched_count, 0);
+
+    let stored = db.student_database.get(doc! {"_id": id}).await.expect("the student is there");
+    assert_eq!(stored.name.as_deref(), Some("Budi S."));
+    assert!(db.audit_database.list(doc! {"action": "replace"}).await.expect("the log is listed").is_empty());
+}
@@ -0,0 +1,94 @@
+use std::io::Read;
+
+use flate2::{read::GzDecoder, write::GzEncoder, Compression};
+use mongodb::bson::{doc, Document};
+use mi_presency_api::database::{Collection, Database, backup::{backup, restore, Manifest}, migrations::Migrator, seeder::{seed, SeedOptions}};
+use mi_presency_api::models::ApiError;
+
+/// A database with a bit of everything, its migrations applied
+async fn filled() -> Database {
+    let db = Database::memory();
+    let options = SeedOptions { classes: 2, students_per_class: 3, teachers: 2, days: 2, ..SeedOptions::new(7) };
+    seed(&db, &options).await.expect("the database is seeded");
+    Migrator::new(&db).up(None, false).await.expect("the migrations apply");
+    db
+}
+
+async fn contents(db: &Database) -> Vec<Vec<Document>> {
+    let mut contents = Vec::new();
+    for collection in Collection::ALL {
+        contents.push(db.documents(collection).list(doc! {}).await.expect("the collection is listed"));
+    }
+    contents
+}
+
+/// `archive` with its manifest rewritten by `change`
+fn with_manifest(archive: &[u8], mut change: impl FnMut(&mut Manifest)) -> Vec<u8> {
+    let mut unpacked = tar::Archive::new(GzDecoder::new(archive));
+    let mut files: Vec<(String, Vec<u8>)> = Vec::new();
+    for entry in unpacked.entries().expect("the archive is a tar") {
+        let mut entry = entry.expect("the entry is read");
+        let path = entry.path().expect("the entry has a path").to_string_lossy().to_string();
+        let mut bytes = Vec::new();
+        entry.read_to_end(&mut bytes).expect("the entry is read");
+        if path == "manifest.json" {
+            let mut manifest: Manifest = serde_json::from_slice(&bytes).expect("the manifest is JSON");
+            change(&mut manifest);
+            bytes = serde_json::to_vec(&manifest).expect("the manifest is written");
+        }
+        files.push((path, bytes));
+    }
+
+    let mut packed = tar::Builder::new(GzEncoder::new(Vec::new(), Compression::default()));
+    for (path, bytes) in files {
+        let mut header = tar::Header::new_gnu();
+        header.set_size(bytes.len() as u64);
+        header.set_mode(0o644);
+        header.set_cksum();
+        packed.append_data(&mut header, &path, bytes.as_slice()).expect("the entry is written");
+    }
+    packed.into_inner().and_then(|encoder| encoder.finish()).expect("the archive is written")
+}
+
+#[rocket::async_test]
+async fn a_backup_restores_as_it_was() {
+    let source = filled().await;
+    let (manifest, archive) = backup(&source).await.expect("the backup is made");
+    assert!(manifest.collections.iter().any(|entry| entry.count > 0));
+
+    let target = Database::memory();
+    // Migrations recorded already are replaced by the archive's
+    Migrator::new(&target).up(None, false).await.expect("the migrations apply");
+    restore(&target, &archive, false).await.expect("the backup is restored");
+    assert_eq!(contents(&target).await, contents(&source).await);
+
+    // Only into an empty database
+    assert!(matches!(restore(&target, &archive, false).await, Err(ApiError::Conflict(_))));
+}
+
+#[rocket::async_test]
+async fn a_dry_run_restores_nothing() {
+    let (_, archive) = backup(&filled().await).await.expect("the backup is made");
+
+    let target = Database::memory();
+    restore(&target, &archive, true).await.expect("the archive is checked");
+    assert!(contents(&target).await.iter().all(Vec::is_empty));
+}
+
+#[rocket::async_test]
+async fn a_restore_failing_half_way_is_rolled_back() {
+    let (_, archive) = backup(&filled().await).await.expect("the backup is made");
+    // Loading the students twice fails on their ids, after the first load went in
+    let archive = with_manifest(&archive, |manifest| {
+        let students = manifest.collections.iter().position(|entry| entry.collection == Collection::Students.key()).expect("the students are listed");
+        let again = serde_json::from_value(serde_json::to_value(&manifest.collections[students]).expect("the entry is JSON")).expect("the entry is read");
+        manifest.collections.push(again);
+    });
+
+    let target = Database::memory();
+    Migrator::new(&target).up(None, false).await.expect("the migrations apply");
+    let before = contents(&target).await;
+
+    assert!(matches!(restore(&target, &archive, false).await, Err(ApiError::Conflict(_))));
+    assert_eq!(contents(&target).await, before);
+}
@@ -0,0 +1,146 @@
+mod common;
+
+use std::sync::{Arc, Mutex};
+
+use futures::future::join_all;
+use mongodb::bson::{oid::ObjectId, DateTime};
+use rocket::http::{ContentType, Header, Status};
+use rocket::local::blocking::{Client, LocalResponse};
+use serde_json::json;
+use mi_presency_api::database::{Database, Repository, SqliteRepository};
+use mi_presency_api::models::{ApiError, Attendance};
+use common::{admin, assert_error, check_in, client, create, etag, json, qr_payload, student};
+
+const CLASS_A: &str = "65a0000000000000000000a1";
+const CLASS_B: &str = "65a0000000000000000000b2";
+
+fn create_student(client: &Client, name: &str, class_id: &str) -> String {
+    create(client, "/student", json!({"name": name, "class_id": {"$oid": class_id}}))
+}
+
+fn post_checkin<'c>(client: &'c Client, authorization: Option<Header<'static>>, payload: &str) -> LocalResponse<'c> {
+    let request = client.post("/checkin/qr").header(ContentType::JSON).body(json!({"payload": payload}).to_string());
+    match authorization {
+        Some(authorization) => request.header(authorization).dispatch(),
+        None => request.dispatch()
+    }
+}
+
+fn attendance(student_id: ObjectId) -> Attendance {
+    Attendance {
+        id: None,
+        student_id: Some(student_id),
+        class_id: ObjectId::parse_str(CLASS_A).ok(),
+        lesson: Some(String::from("Math")),
+        method: Some(String::from("qr")),
+        device_id: None,
+        tap_id: None,
+        checked_in_at: Some(DateTime::now()),
+        version: None,
+    }
+}
+
+/// Checks that of many racing check-ins of the same student, exactly one is stored
+async fn only_one_check_in_is_stored(attendances: &dyn Repository<Attendance>) {
+    let student_id = ObjectId::new();
+    let checkins: Vec<Attendance> = (0..8).map(|_| attendance(student_id)).collect();
+    let results = join_all(checkins.iter().map(|checkin| attendances.insert(checkin))).await;
+
+    assert_eq!(results.iter().filter(|result| result.is_ok()).count(), 1);
+    assert!(results.iter().filter_map(|result| result.as_ref().err()).all(|err| matches!(err, ApiError::Conflict(_))));
+    // Another student of the same lesson isn't held back
+    assert!(attendances.insert(&attendance(ObjectId::new())).await.is_ok());
+}
+
+#[test]
+fn get_checkin_qr_needs_staff() {
+    let client = client();
+    let student_id = create_student(&client, "Budi Santoso", CLASS_A);
+
+    let path = format!("/checkin/qr?class_id={}&lesson=Math", CLASS_A);
+    assert_error(client.get(&path).dispatch(), Status::Unauthorized, 9);
+    assert_error(client.get(&path).header(student(&client, &student_id)).dispatch(), Status::Forbidden, 10);
+    assert_eq!(client.get(&path).header(admin()).dispatch().status(), Status::Ok);
+}
+
+#[test]
+fn post_checkin_qr_checks_in_the_signed_in_student() {
+    let client = client();
+    let student_id = create_student(&client, "Budi Santoso", CLASS_A);
+    let payload = qr_payload(&client, CLASS_A, "Math");
+
+    assert_error(post_checkin(&client, None, &payload), Status::Unauthorized, 9);
+    assert_error(post_checkin(&client, Some(admin()), &payload), Status::Forbidden, 10);
+
+    let response = post_checkin(&client, Some(student(&client, &student_id)), &payload);
+    assert_eq!(response.status(), Status::Ok);
+    let audit = json(client.get("/audit?entity=Attendance").header(admin()).dispatch());
+    assert_eq!(audit["items"][0]["after"]["student_id"]["$oid"], student_id.as_str());
+    assert_eq!(audit["items"][0]["actor"], "student");
+}
+
+#[test]
+fn post_checkin_qr_refuses_twice_and_other_classes() {
+    let client = client();
+    let student_id = create_student(&client, "Budi Santoso", CLASS_A);
+    check_in(&client, &student_id, CLASS_A, "Math");
+
+    let payload = qr_payload(&client, CLASS_A, "Math");
+    assert_error(post_checkin(&client, Some(student(&client, &student_id)), &payload), Status::Conflict, 6);
+    let payload = qr_payload(&client, CLASS_B, "Math");
+    assert_error(post_checkin(&client, Some(student(&client, &student_id)), &payload), Status::Forbidden, 4);
+    assert_error(post_checkin(&client, Some(student(&client, &student_id)), "not.signed"), Status::BadRequest, 3);
+}
+
+#[test]
+fn student_tokens_are_never_answered() {
+    let client = client();
+    let student_id = create_student(&client, "Budi Santoso", CLASS_A);
+    student(&client, &student_id);
+
+    let by_id = json(client.get(format!("/student/{}", student_id)).dispatch());
+    assert!(by_id.get("token").is_none());
+    let page = json(client.get("/student").dispatch());
+    assert!(page["items"][0].get("token").is_none());
+    assert_error(client.get("/student?fields=token").dispatch(), Status::BadRequest, 1);
+    assert_error(client.get("/student?sort=token").dispatch(), Status::BadRequest, 1);
+}
+
+#[test]
+fn a_new_token_replaces_the_old_one() {
+    let client = client();
+    let student_id = create_student(&client, "Budi Santoso", CLASS_A);
+    let old = student(&client, &student_id);
+    let new = student(&client, &student_id);
+
+    let payload = qr_payload(&client, CLASS_A, "Math");
+    assert_error(post_checkin(&client, Some(old), &payload), Status::Unauthorized, 9);
+
+    // Replacing the student doesn't sign it out
+    let path = format!("/student/{}", student_id);
+    let response = client.put(&path).header(admin()).header(Header::new("If-Match", etag(&client, &path))).header(ContentType::JSON)
+        .body(json!({"name": "Budi S.", "class_id": {"$oid": CLASS_A}}).to_string()).dispatch();
+    assert_eq!(response.status(), Status::Ok);
+    assert_eq!(post_checkin(&client, Some(new), &payload).status(), Status::Ok);
+}
+
+#[test]
+fn post_student_token_needs_staff() {
+    let client = client();
+    let student_id = create_student(&client, "Budi Santoso", CLASS_A);
+    assert_error(client.post(format!("/student/{}/token", student_id)).dispatch(), Status::Unauthorized, 9);
+    assert_error(client.post(format!("/student/{}/token", common::UNKNOWN_ID)).header(admin()).dispatch(), Status::NotFound, 5);
+}
+
+#[rocket::async_test]
+async fn racing_check_ins_store_one_in_memory() {
+    let db = Database::memory();
+    only_one_check_in_is_stored(db.attendance_database.as_ref()).await;
+}
+
+#[rocket::async_test]
+async fn racing_check_ins_store_one_in_sqlite() {
+    let connection = rusqlite::Connection::open_in_memory().expect("SQLite opens in memory");
+    let attendances: SqliteRepository<Attendance> = SqliteRepository::open(Arc::new(Mutex::new(connection)), "attendances").expect("the table is created");
+    only_one_check_in_is_stored(&attendances).await;
+}
@@ -0,0 +1,96 @@
+//! What every integration test needs: a server on fresh in-memory storage
+//! and a few shortcuts for the JSON it answers with.
+
+// Each test file uses its own share of these
+#![allow(dead_code)]
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use rocket::figment::Figment;
+use rocket::http::{ContentType, Header, Status};
+use rocket::local::blocking::{Client, LocalResponse};
+use serde_json::Value;
+use mi_presency_api::config::Config;
+use mi_presency_api::database::Database;
+
+/// An id no test ever stores anything under
+pub const UNKNOWN_ID: &str = "65a000000000000000000000";
+/// What admin requests are made with, as `Authorization: Bearer <token>`
+pub const ADMIN_TOKEN: &str = "test admin token";
+
+/// The whole server, with empty collections every time it's called
+pub fn client() -> Client {
+    // Built from scratch rather than from Rocket.toml, so the tests don't need MongoDB
+    let config: Config = Figment::new()
+        .merge(("storage", "memory"))
+        .merge(("database_name", "mi-presency-test"))
+        .merge(("qr_secret", "test secret"))
+        .merge(("admin_token", ADMIN_TOKEN))
+        .extract()
+        .expect("the test configuration is valid");
+
+    Client::tracked(mi_presency_api::rocket(&config, Database::memory())).expect("the server can be built")
+}
+
+pub fn json(response: LocalResponse<'_>) -> Value {
+    response.into_json::<Value>().expect("the response is JSON")
+}
+
+/// The hexadecimal text of an `ObjectId` as the API writes it, `{"$oid": "..."}`
+pub fn oid(value: &Value) -> String {
+    value["$oid"].as_str().expect("the value is an ObjectId").to_string()
+}
+
+/// Checks that `response` is the error with `error_code`, answered with `status`
+pub fn assert_error(response: LocalResponse<'_>, status: Status, error_code: u64) -> Value {
+    assert_eq!(response.status(), status);
+    let body = json(response);
+    assert_eq!(body["error_code"], error_code, "unexpected error: {}", body);
+    body
+}
+
+/// POSTs `body` to `path` as an admin, answering the `_id` it was stored under
+pub fn create(client: &Client, path: &str, body: Value) -> String {
+    let response = client.post(path).header(admin()).header(ContentType::JSON).body(body.to_string()).dispatch();
+    assert_eq!(response.status(), Status::Ok);
+    oid(&json(response)["insertedId"])
+}
+
+/// The `Authorization` header of admin requests
+pub fn admin() -> Header<'static> {
+    Header::new("Authorization", format!("Bearer {}", ADMIN_TOKEN))
+}
+
+/// The `Authorization` header of a teacher's, signing in with `name` and `pass`
+pub fn teacher(name: &str, pass: &str) -> Header<'static> {
+    Header::new("Authorization", format!("Basic {}", STANDARD.encode(format!("{}:{}", name, pass))))
+}
+
+/// The `ETag` of `path`, for the `If-Match` of a change. Read as an admin, whoever may make the change.
+pub fn etag(client: &Client, path: &str) -> String {
+    let response = client.get(path).header(admin()).dispatch();
+    assert_eq!(response.status(), Status::Ok);
+    response.headers().get_one("ETag").expect("the response has an ETag").to_string()
+}
+
+/// The `Authorization` header of a student's, after giving them a new token
+pub fn student(client: &Client, student_id: &str) -> Header<'static> {
+    let response = client.post(format!("/student/{}/token", student_id)).header(admin()).dispatch();
+    assert_eq!(response.status(), Status::Ok);
+    let token = json(response)["token"].as_str().expect("a token is answered").to_string();
+    Header::new("Authorization", format!("Bearer {}", token))
+}
+
+/// The payload of the QR code a teacher shows for `lesson` of `class_id`
+pub fn qr_payload(client: &Client, class_id: &str, lesson: &str) -> String {
+    let response = client.get(format!("/checkin/qr?class_id={}&lesson={}", class_id, lesson)).header(admin()).dispatch();
+    assert_eq!(response.status(), Status::Ok);
+    json(response)["payload"].as_str().expect("a QR payload is answered").to_string()
+}
+
+/// Checks the student in to `lesson` of `class_id` by QR code, as the student
+pub fn check_in(client: &Client, student_id: &str, class_id: &str, lesson: &str) {
+    let payload = qr_payload(client, class_id, lesson);
+    let response = client.post("/checkin/qr").header(student(client, student_id)).header(ContentType::JSON)
+        .body(serde_json::json!({"payload": payload}).to_string()).dispatch();
+    assert_eq!(response.status(), Status::Ok);
+}
@@ -0,0 +1,115 @@
+use std::time::Duration;
+
+use mongodb::bson::{doc, oid::ObjectId};
+use serde_json::{json, Value};
+use mi_presency_api::api::{attendance_api::AttendanceFeed, device_channel::Connection, webhook_delivery::Webhooks};
+use mi_presency_api::database::{AuditContext, Database};
+use mi_presency_api::models::{Device, Student, validation::validate};
+
+const CLASS_A: &str = "65a0000000000000000000a1";
+
+fn class_a() -> Option<ObjectId> {
+    ObjectId::parse_str(CLASS_A).ok()
+}
+
+/// A device of class A blocking `blocked_cards`, stored the way `POST /device` stores it,
+/// and a student of the class with the card `0A1B2C3D`
+async fn set_up(db: &Database, blocked_cards: &[&str]) -> Device {
+    let device = validate(Device {
+        id: None,
+        name: Some(String::from("Door of 7A")),
+        class_id: class_a(),
+        token: None,
+        debounce_seconds: None,
+        display_message: None,
+        blocked_cards: Some(blocked_cards.iter().map(|card| card.to_string()).collect()),
+        deleted_at: None,
+        version: None,
+    }, true).expect("the device is valid");
+    let device_id = db.device_database.insert(&device).await.expect("the device is stored").inserted_id;
+
+    let student = validate(Student {
+        id: None,
+        name: Some(String::from("Budi Santoso")),
+        card_id: Some(String::from("0a1b2c3d")),
+        class_id: class_a(),
+        token: None,
+        deleted_at: None,
+        version: None,
+    }, true).expect("the student is valid");
+    db.student_database.insert(&student).await.expect("the student is stored");
+
+    db.device_database.get(doc! {"_id": device_id}).await.expect("the device is there")
+}
+
+fn connection<'a>(db: &'a Database, feed: &'a AttendanceFeed, webhooks: &'a Webhooks, device: Device) -> Connection<'a> {
+    Connection {
+        db,
+        feed,
+        webhooks,
+        audit: AuditContext {
+            actor: String::from("device"),
+            actor_id: device.id,
+            route: String::from("GET /device/channel"),
+            client_ip: None,
+        },
+        device,
+    }
+}
+
+async fn tap(connection: &Connection<'_>, tap_id: &str, card_id: &str) -> Value {
+    let message = json!({"type": "tap", "tap_id": tap_id, "card_id": card_id, "lesson": "Math"}).to_string();
+    serde_json::to_value(connection.answer(&message).await).expect("the reply is JSON")
+}
+
+#[rocket::async_test]
+async fn a_tap_checks_the_student_in() {
+    let db = Database::memory();
+    let feed = AttendanceFeed::new();
+    let webhooks = Webhooks::new(&db, 1, Duration::from_secs(1));
+    let connection = connection(&db, &feed, &webhooks, set_up(&db, &[]).await);
+
+    // Card ids are stored uppercase, taps are looked up the same way
+    let ack = tap(&connection, "tap-1", " 0a1b2c3d ").await;
+    assert_eq!(ack, json!({"type": "tap_ack", "tap_id": "tap-1", "accepted": true, "student_name": "Budi Santoso"}));
+
+    let attendances = db.attendance_database.list(doc! {}).await.expect("the attendances are listed");
+    assert_eq!(attendances.len(), 1);
+    assert_eq!(attendances[0].method.as_deref(), Some("card"));
+    assert_eq!(attendances[0].tap_id.as_deref(), Some("tap-1"));
+}
+
+#[rocket::async_test]
+async fn a_tap_sent_again_is_answered_as_it_was_the_first_time() {
+    let db = Database::memory();
+    let feed = AttendanceFeed::new();
+    let webhooks = Webhooks::new(&db, 1, Duration::from_secs(1));
+    let connection = connection(&db, &feed, &webhooks, set_up(&db, &[]).await);
+
+    let first = tap(&connection, "tap-1", "0A1B2C3D").await;
+    assert_eq!(first["accepted"], true);
+    assert_eq!(tap(&connection, "tap-1", "0A1B2C3D").await, first);
+    assert_eq!(db.attendance_database.list(doc! {}).await.expect("the attendances are listed").len(), 1);
+
+    // Another tap of the same card is a second check-in
+    let again = tap(&connection, "tap-2", "0A1B2C3D").await;
+    assert_eq!(again["accepted"], false);
+    assert_eq!(again["error_code"], 6);
+}
+
+#[rocket::async_test]
+async fn a_blocked_card_is_refused_however_its_written() {
+    let db = Database::memory();
+    let feed = AttendanceFeed::new();
+    let webhooks = Webhooks::new(&db, 1, Duration::from_secs(1));
+    let device = set_up(&db, &[" 0a1b2c3d "]).await;
+    assert_eq!(device.blocked_cards, Some(vec![String::from("0A1B2C3D")]));
+    let connection = connection(&db, &feed, &webhooks, device);
+
+    for (tap_id, card_id) in [("tap-1", "0A1B2C3D"), ("tap-2", "0a1b2c3d")] {
+        let ack = tap(&connection, tap_id, card_id).await;
+        assert_eq!(ack["accepted"], false);
+        assert_eq!(ack["error_code"], 4);
+    }
+    assert!(db.attendance_database.list(doc! {}).await.expect("the attendances are listed").is_empty());
+}
@@ -0,0 +1,180 @@
+use mongodb::bson::{doc, Document};
+use mi_presency_api::database::{Collection, Database, Patch, migrations::{Migration, Migrator, Step}};
+use mi_presency_api::models::{ApiError, Teacher};
+
+/// Two reversible migrations of students, the second only changing what the first did
+const TAGGING: &[Migration] = &[
+    Migration {
+        id: 1,
+        name: "tag_students",
+        steps: &[Step { collection: Collection::Students, up: tag, down: Some(untag) }]
+    },
+    Migration {
+        id: 2,
+        name: "check_tagged_students",
+        steps: &[Step { collection: Collection::Students, up: check, down: Some(uncheck) }]
+    },
+];
+
+fn tag(document: &mut Document) -> bool {
+    document.insert("tagged", true).is_none()
+}
+
+fn untag(document: &mut Document) -> bool {
+    document.remove("tagged").is_some()
+}
+
+fn check(document: &mut Document) -> bool {
+    document.get_bool("tagged").unwrap_or(false) && document.insert("checked", true).is_none()
+}
+
+fn uncheck(document: &mut Document) -> bool {
+    document.remove("checked").is_some()
+}
+
+async fn with_students(names: &[&str]) -> Database {
+    let db = Database::memory();
+    for name in names {
+        db.documents(Collection::Students).insert(&doc! {"name": *name}).await.expect("the student is stored");
+    }
+    db
+}
+
+async fn students(db: &Database) -> Vec<Document> {
+    db.documents(Collection::Students).list(doc! {}).await.expect("the students are listed")
+}
+
+async fn applied(migrator: &Migrator<'_>) -> Vec<i64> {
+    migrator.status().await.expect("the status is read").into_iter()
+        .filter(|(_, applied_at)| applied_at.is_some())
+        .map(|(migration, _)| migration.id)
+        .collect()
+}
+
+#[rocket::async_test]
+async fn up_applies_the_pending_migrations_once() {
+    let db = with_students(&["Budi", "Siti"]).await;
+    let mut migrator = Migrator::with_migrations(&db, TAGGING);
+
+    let reports = migrator.up(Some(1), false).await.expect("migration 1 applies");
+    assert_eq!(reports.len(), 1);
+    assert_eq!(reports[0].affected, vec![(Collection::Students, 2)]);
+    assert_eq!(applied(&migrator).await, vec![1]);
+
+    let reports = migrator.up(None, false).await.expect("migration 2 applies");
+    assert_eq!(reports.iter().map(|report| report.id).collect::<Vec<_>>(), vec![2]);
+    assert_eq!(applied(&migrator).await, vec![1, 2]);
+    for student in students(&db).await {
+        assert_eq!(student.get_bool("checked"), Ok(true));
+        // Every change moves the version, like any other
+        assert_eq!(student.get_i64("version"), Ok(3));
+    }
+
+    assert!(migrator.up(None, false).await.expect("nothing is pending").is_empty());
+}
+
+#[rocket::async_test]
+async fn a_dry_run_of_several_migrations_writes_nothing() {
+    let db = with_students(&["Budi", "Siti"]).await;
+    let before = students(&db).await;
+    let mut migrator = Migrator::with_migrations(&db, TAGGING);
+
+    let reports = migrator.up(None, true).await.expect("the dry run goes through");
+    // The second sees what the first would have done
+    assert_eq!(reports.iter().map(|report| report.affected.clone()).collect::<Vec<_>>(), vec![
+        vec![(Collection::Students, 2)],
+        vec![(Collection::Students, 2)],
+    ]);
+    assert!(reports.iter().all(|report| report.dry_run));
+
+    assert_eq!(students(&db).await, before);
+    assert!(applied(&Migrator::with_migrations(&db, TAGGING)).await.is_empty());
+}
+
+#[rocket::async_test]
+async fn down_undoes_the_migrations_after_the_target() {
+    let db = with_students(&["Budi", "Siti"]).await;
+    Migrator::with_migrations(&db, TAGGING).up(None, false).await.expect("the migrations apply");
+
+    let mut migrator = Migrator::with_migrations(&db, TAGGING);
+    let reports = migrator.down(0, true).await.expect("the dry run goes through");
+    assert_eq!(reports.iter().map(|report| report.id).collect::<Vec<_>>(), vec![2, 1]);
+    assert!(students(&db).await.iter().all(|student| student.get_bool("checked") == Ok(true)));
+    assert_eq!(applied(&migrator).await, vec![1, 2]);
+
+    let mut migrator = Migrator::with_migrations(&db, TAGGING);
+    migrator.down(1, false).await.expect("migration 2 is undone");
+    assert_eq!(applied(&migrator).await, vec![1]);
+    for student in students(&db).await {
+        assert_eq!(student.get("checked"), None);
+        assert_eq!(student.get_bool("tagged"), Ok(true));
+    }
+}
+
+#[rocket::async_test]
+async fn down_refuses_migrations_that_cant_be_undone() {
+    let db = with_students(&[" Budi "]).await;
+    let mut migrator = Migrator::new(&db);
+    migrator.up(None, false).await.expect("the migrations apply");
+
+    let applied_before = applied(&migrator).await;
+    assert!(matches!(migrator.down(0, false).await, Err(ApiError::BadParams(_))));
+    assert_eq!(applied(&migrator).await, applied_before);
+    assert_eq!(students(&db).await[0].get_str("name"), Ok("Budi"));
+}
+
+#[rocket::async_test]
+async fn a_document_changed_meanwhile_stops_the_migration() {
+    let db = with_students(&["Budi"]).await;
+    let mut migrator = Migrator::with_migrations(&db, TAGGING);
+    migrator.up(Some(1), false).await.expect("migration 1 applies");
+
+    // Changed after the migrator read it
+    let students_repository = db.documents(Collection::Students);
+    students_repository.patch(doc! {}, &Patch { set: doc! {"name": "Budi S."}, unset: vec![] }).await.expect("the student is changed");
+
+    assert!(matches!(migrator.up(None, false).await, Err(ApiError::Conflict(_))));
+    assert_eq!(applied(&migrator).await, vec![1]);
+    let student = &students(&db).await[0];
+    assert_eq!(student.get_str("name"), Ok("Budi S."));
+    assert_eq!(student.get("checked"), None);
+
+    // Run again, it reads the document as it's now
+    Migrator::with_migrations(&db, TAGGING).up(None, false).await.expect("migration 2 applies on a new run");
+    assert_eq!(students(&db).await[0].get_bool("checked"), Ok(true));
+}
+
+#[rocket::async_test]
+async fn teacher_passes_stored_in_plain_text_are_hashed() {
+    let db = Database::memory();
+    let teachers = db.documents(Collection::Teachers);
+    teachers.insert(&doc! {"name": "Pak Hadi", "pass": "papan-tulis"}).await.expect("the teacher is stored");
+    // Hashing a hash again would lock the teacher out
+    let hash = Teacher::hash_pass("kapur-putih").expect("the pass is hashed");
+    teachers.insert(&doc! {"name": "Bu Wulan", "pass": hash}).await.expect("the teacher is stored");
+
+    let reports = Migrator::new(&db).up(None, false).await.expect("the migrations apply");
+    let hashing = reports.iter().find(|report| report.name == "hash_teacher_passes").expect("the passes are hashed");
+    assert_eq!(hashing.affected, vec![(Collection::Teachers, 1)]);
+
+    for (name, pass) in [("Pak Hadi", "papan-tulis"), ("Bu Wulan", "kapur-putih")] {
+        let teacher = db.teacher_database.get(doc! {"name": name}).await.expect("the teacher is there");
+        assert!(teacher.verify_pass(pass));
+    }
+}
+
+#[rocket::async_test]
+async fn card_ids_stored_lowercase_are_uppercased() {
+    let db = Database::memory();
+    db.documents(Collection::Students).insert(&doc! {"name": "Budi", "card_id": "0a1b2c3d"}).await.expect("the student is stored");
+    db.documents(Collection::Devices).insert(&doc! {"name": "Door of 7A", "blocked_cards": ["0a1b2c3e", "0A1B2C3F"]}).await.expect("the device is stored");
+    db.documents(Collection::Devices).insert(&doc! {"name": "Door of 7B", "blocked_cards": ["0A1B2C3E"]}).await.expect("the device is stored");
+
+    let reports = Migrator::new(&db).up(None, false).await.expect("the migrations apply");
+    let uppercasing = reports.iter().find(|report| report.name == "uppercase_card_ids").expect("the card ids are uppercased");
+    assert_eq!(uppercasing.affected, vec![(Collection::Students, 1), (Collection::Devices, 1)]);
+
+    assert_eq!(students(&db).await[0].get_str("card_id"), Ok("0A1B2C3D"));
+    let devices = db.documents(Collection::Devices).list(doc! {"name": "Door of 7A"}).await.expect("the devices are listed");
+    assert_eq!(devices[0].get_array("blocked_cards").ok(), Some(&vec!["0A1B2C3E".into(), "0A1B2C3F".into()]));
+}
@@ -0,0 +1,42 @@
+use mongodb::bson::{doc, Document};
+use mi_presency_api::database::{Collection, Database, seeder::{filled_collection, seed, SeedOptions}};
+use mi_presency_api::models::ApiError;
+
+fn options() -> SeedOptions {
+    SeedOptions { classes: 2, students_per_class: 4, teachers: 2, days: 5, ..SeedOptions::new(42) }
+}
+
+/// What `seed` stored, but the teachers' pass hashes which are salted anew every time
+async fn seeded(db: &Database) -> Vec<Vec<Document>> {
+    let mut seeded = Vec::new();
+    for collection in [Collection::Students, Collection::Teachers, Collection::Attendances] {
+        let mut documents = db.documents(collection).list(doc! {}).await.expect("the collection is listed");
+        for document in documents.iter_mut() {
+            document.remove("pass");
+        }
+        seeded.push(documents);
+    }
+    seeded
+}
+
+#[rocket::async_test]
+async fn the_same_seed_gives_the_same_data() {
+    let first = Database::memory();
+    let second = Database::memory();
+    seed(&first, &options()).await.expect("the database is seeded");
+    seed(&second, &options()).await.expect("the database is seeded");
+
+    let data = seeded(&first).await;
+    assert!(data.iter().all(|documents| !documents.is_empty()));
+    assert_eq!(data, seeded(&second).await);
+}
+
+#[rocket::async_test]
+async fn only_an_empty_database_is_seeded() {
+    let db = Database::memory();
+    assert_eq!(filled_collection(&db).await.expect("the database is counted"), None);
+    seed(&db, &options()).await.expect("the database is seeded");
+
+    assert_eq!(filled_collection(&db).await.expect("the database is counted"), Some((Collection::Students, 8)));
+    assert!(matches!(seed(&db, &options()).await, Err(ApiError::Conflict(_))));
+}
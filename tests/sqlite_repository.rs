@@ -0,0 +1,57 @@
+use std::sync::{Arc, Mutex};
+
+use mongodb::bson::{doc, oid::ObjectId};
+use mi_presency_api::database::{Repository, SqliteRepository};
+use mi_presency_api::models::{Student, utils::not_deleted};
+
+fn student(name: &str, token: &str) -> Student {
+    Student {
+        id: None,
+        name: Some(String::from(name)),
+        card_id: None,
+        class_id: ObjectId::parse_str("65a0000000000000000000a1").ok(),
+        token: Some(String::from(token)),
+        deleted_at: None,
+        version: None,
+    }
+}
+
+async fn token_holder(students: &SqliteRepository<Student>, token: &str) -> Option<String> {
+    students.list(not_deleted(doc! {"token": token})).await.expect("the students are listed")
+        .into_iter()
+        .next()
+        .and_then(|student| student.name)
+}
+
+#[rocket::async_test]
+async fn lookups_follow_the_stored_documents() {
+    let connection = Arc::new(Mutex::new(rusqlite::Connection::open_in_memory().expect("SQLite opens in memory")));
+    let students: SqliteRepository<Student> = SqliteRepository::open(connection, "students").expect("the table is created");
+    let budi = students.insert(&student("Budi Santoso", "token-budi")).await.expect("Budi is stored").inserted_id;
+    students.insert(&student("Siti Rahma", "token-siti")).await.expect("Siti is stored");
+
+    let found = students.get(doc! {"_id": budi.clone()}).await.expect("Budi is found by id");
+    assert_eq!(found.name.as_deref(), Some("Budi Santoso"));
+    assert_eq!(token_holder(&students, "token-siti").await.as_deref(), Some("Siti Rahma"));
+
+    // A changed token is looked up by its new value only
+    students.update(doc! {"_id": budi.clone()}, &Student { token: Some(String::from("token-new")), ..student("Budi Santoso", "") })
+        .await
+        .expect("Budi is updated");
+    assert_eq!(token_holder(&students, "token-budi").await, None);
+    assert_eq!(token_holder(&students, "token-new").await.as_deref(), Some("Budi Santoso"));
+
+    students.delete_many(doc! {"_id": budi}).await.expect("Budi is deleted");
+    assert_eq!(token_holder(&students, "token-new").await, None);
+}
+
+#[rocket::async_test]
+async fn a_table_stored_without_keys_gets_them_when_opened() {
+    let connection = Arc::new(Mutex::new(rusqlite::Connection::open_in_memory().expect("SQLite opens in memory")));
+    let students: SqliteRepository<Student> = SqliteRepository::open(connection.clone(), "students").expect("the table is created");
+    students.insert(&student("Budi Santoso", "token-budi")).await.expect("Budi is stored");
+    connection.lock().expect("the connection is free").execute("DROP TABLE students_keys", []).expect("the keys are dropped");
+
+    let students: SqliteRepository<Student> = SqliteRepository::open(connection, "students").expect("the table is opened again");
+    assert_eq!(token_holder(&students, "token-budi").await.as_deref(), Some("Budi Santoso"));
+}
@@ -0,0 +1,374 @@
+mod common;
+
+use rocket::http::{ContentType, Header, Status};
+use rocket::local::blocking::Client;
+use serde_json::{json, Value};
+use common::{admin, assert_error, client, create, etag, json, oid, teacher, UNKNOWN_ID};
+
+const CLASS_A: &str = "65a0000000000000000000a1";
+const CLASS_B: &str = "65a0000000000000000000b2";
+
+fn create_student(client: &Client, name: &str, card_id: &str, class_id: &str) -> String {
+    create(client, "/student", json!({"name": name, "card_id": card_id, "class_id": {"$oid": class_id}}))
+}
+
+/// Three students, two of them in `CLASS_A`
+fn with_students() -> (Client, Vec<String>) {
+    let client = client();
+    let ids = vec![
+        create_student(&client, "Budi Santoso", "0A1B2C3D", CLASS_A),
+        create_student(&client, "Siti Rahma", "0A1B2C3E", CLASS_A),
+        create_student(&client, "Bayu Pratama", "0A1B2C3F", CLASS_B),
+    ];
+    (client, ids)
+}
+
+fn names(body: &Value) -> Vec<&str> {
+    body["items"].as_array().expect("a page has items").iter().map(|item| item["name"].as_str().unwrap_or_default()).collect()
+}
+
+#[test]
+fn post_student_stores_it() {
+    let client = client();
+    let id = create_student(&client, "  Budi Santoso ", "0a1b2c3d", CLASS_A);
+
+    let response = client.get(format!("/student/{}", id)).dispatch();
+    assert_eq!(response.status(), Status::Ok);
+    let student = json(response);
+    assert_eq!(oid(&student["_id"]), id);
+    assert_eq!(student["name"], "Budi Santoso");
+    assert_eq!(oid(&student["class_id"]), CLASS_A);
+}
+
+#[test]
+fn post_student_refuses_invalid_fields() {
+    let client = client();
+    let response = client.post("/student").header(admin()).header(ContentType::JSON).body(json!({"name": "", "card_id": "XYZ"}).to_string()).dispatch();
+    let body = assert_error(response, Status::UnprocessableEntity, 8);
+    let fields: Vec<&str> = body["violations"].as_array().unwrap().iter().map(|violation| violation["field"].as_str().unwrap()).collect();
+    assert!(fields.contains(&"name") && fields.contains(&"card_id"), "{:?}", fields);
+}
+
+#[test]
+fn post_student_refuses_a_body_that_isnt_json() {
+    let client = client();
+    let response = client.post("/student").header(admin()).header(ContentType::JSON).body("{name").dispatch();
+    assert_eq!(json(response)["error_code"], 1);
+}
+
+#[test]
+fn get_all_students_pages() {
+    let (client, _) = with_students();
+
+    let first = json(client.get("/student?limit=2&sort=name").dispatch());
+    assert_eq!(first["total"], 3);
+    assert_eq!(names(&first), ["Bayu Pratama", "Budi Santoso"]);
+
+    let cursor = first["next_cursor"].as_str().expect("there's a second page");
+    let second = json(client.get(format!("/student?limit=2&sort=name&cursor={}", cursor)).dispatch());
+    assert_eq!(names(&second), ["Siti Rahma"]);
+    assert_eq!(second["next_cursor"], Value::Null);
+}
+
+#[test]
+fn get_all_students_refuses_bad_list_params() {
+    let (client, _) = with_students();
+    assert_error(client.get("/student?limit=0").dispatch(), Status::BadRequest, 1);
+    assert_error(client.get("/student?sort=age").dispatch(), Status::BadRequest, 1);
+    assert_error(client.get("/student?cursor=nonsense").dispatch(), Status::BadRequest, 1);
+}
+
+#[test]
+fn get_student_searches_by_field() {
+    let (client, ids) = with_students();
+
+    let response = client.get("/student/search?card_id=0A1B2C3E").dispatch();
+    assert_eq!(response.status(), Status::Ok);
+    assert!(response.headers().get_one("ETag").is_some());
+    assert_eq!(json(response)["name"], "Siti Rahma");
+
+    let student = json(client.get(format!("/student/search?_id={}", ids[2])).dispatch());
+    assert_eq!(student["name"], "Bayu Pratama");
+}
+
+#[test]
+fn get_student_needs_a_param() {
+    let (client, _) = with_students();
+    assert_error(client.get("/student/search").dispatch(), Status::BadRequest, 1);
+    // A malformed id is left out, which leaves nothing to search by
+    assert_error(client.get("/student/search?_id=not-an-id").dispatch(), Status::BadRequest, 1);
+}
+
+#[test]
+fn get_student_answers_not_found() {
+    let (client, _) = with_students();
+    assert_error(client.get("/student/search?card_id=FFFFFFFF").dispatch(), Status::NotFound, 5);
+}
+
+#[test]
+fn find_students_matches_names() {
+    let (client, _) = with_students();
+
+    let contains = json(client.get("/student/find?name=PRA&sort=name").dispatch());
+    assert_eq!(names(&contains), ["Bayu Pratama"]);
+
+    let prefix = json(client.get("/student/find?name=b&match=prefix&sort=name").dispatch());
+    assert_eq!(names(&prefix), ["Bayu Pratama", "Budi Santoso"]);
+
+    let in_class = json(client.get(format!("/student/find?name=b&match=prefix&class_id={}", CLASS_A)).dispatch());
+    assert_eq!(names(&in_class), ["Budi Santoso"]);
+
+    let class_only = json(client.get(format!("/student/find?class_id={}", CLASS_A)).dispatch());
+    assert_eq!(class_only["total"], 2);
+}
+
+#[test]
+fn find_students_refuses_bad_params() {
+    let (client, _) = with_students();
+    assert_error(client.get("/student/find").dispatch(), Status::BadRequest, 1);
+    assert_error(client.get("/student/find?name=%20").dispatch(), Status::BadRequest, 1);
+    assert_error(client.get("/student/find?name=b&match=suffix").dispatch(), Status::BadRequest, 1);
+    assert_error(client.get("/student/find?class_id=not-an-id").dispatch(), Status::BadRequest, 1);
+}
+
+#[test]
+fn put_student_updates_the_match() {
+    let (client, ids) = with_students();
+    let body = json!({"params": {"card_id": "0A1B2C3F"}, "new_data": {"class_id": {"$oid": CLASS_A}}});
+
+    let response = client.put("/student").header(admin()).header(Header::new("If-Match", "\"1\"")).header(ContentType::JSON).body(body.to_string()).dispatch();
+    assert_eq!(response.status(), Status::Ok);
+    let result = json(response);
+    assert_eq!(result["matchedCount"], 1);
+    assert_eq!(result["modifiedCount"], 1);
+
+    let student = json(client.get(format!("/student/{}", ids[2])).dispatch());
+    assert_eq!(oid(&student["class_id"]), CLASS_A);
+}
+
+#[test]
+fn put_student_needs_confirm_for_several() {
+    let (client, _) = with_students();
+    let body = json!({"params": {"class_id": CLASS_A}, "new_data": {"class_id": {"$oid": CLASS_B}}});
+
+    let response = client.put("/student").header(admin()).header(Header::new("If-Match", "\"1\"")).header(ContentType::JSON).body(body.to_string()).dispatch();
+    assert_error(response, Status::PreconditionRequired, 7);
+
+    let response = client.put("/student?confirm=true").header(admin()).header(Header::new("If-Match", "\"1\"")).header(ContentType::JSON).body(body.to_string()).dispatch();
+    assert_eq!(json(response)["modifiedCount"], 2);
+    assert_eq!(json(client.get(format!("/student/find?class_id={}", CLASS_B)).dispatch())["total"], 3);
+}
+
+#[test]
+fn put_student_refuses_stale_versions() {
+    let (client, ids) = with_students();
+    let body = json!({"params": {"class_id": CLASS_A}, "new_data": {"class_id": {"$oid": CLASS_B}}}).to_string();
+    let put = |if_match: Option<&str>| {
+        let mut request = client.put("/student?confirm=true").header(admin()).header(ContentType::JSON).body(body.clone());
+        if let Some(if_match) = if_match {
+            request.add_header(Header::new("If-Match", if_match.to_string()));
+        }
+        request.dispatch()
+    };
+
+    // Siti is changed after both students of class A were read
+    let path = format!("/student/{}", ids[1]);
+    let response = client.patch(path.clone()).header(admin()).header(Header::new("If-Match", "\"1\"")).header(ContentType::JSON).body(json!({"name": "Siti R."}).to_string()).dispatch();
+    assert_eq!(response.status(), Status::Ok);
+
+    assert_error(put(None), Status::PreconditionRequired, 12);
+    assert_error(put(Some("\"1\"")), Status::PreconditionFailed, 11);
+    // Neither was moved
+    assert_eq!(json(client.get(format!("/student/find?class_id={}", CLASS_A)).dispatch())["total"], 2);
+
+    assert_eq!(json(put(Some("*")))["modifiedCount"], 2);
+    assert_eq!(json(client.get(path).dispatch())["name"], "Siti R.");
+}
+
+#[test]
+fn put_student_refuses_bad_params() {
+    let (client, _) = with_students();
+    let put = |body: Value| client.put("/student?confirm=true").header(admin()).header(Header::new("If-Match", "\"1\"")).header(ContentType::JSON).body(body.to_string()).dispatch();
+
+    assert_error(put(json!({"params": {}, "new_data": {"name": "Nobody"}})), Status::BadRequest, 1);
+    assert_error(put(json!({"params": {"age": "12"}, "new_data": {"name": "Nobody"}})), Status::BadRequest, 1);
+    assert_error(put(json!({"params": {"deleted_at": "2024-01-15"}, "new_data": {"name": "Nobody"}})), Status::BadRequest, 1);
+    assert_error(put(json!({"params": {"id": "not-an-id"}, "new_data": {"name": "Nobody"}})), Status::BadRequest, 1);
+    let body = assert_error(put(json!({"params": {"card_id": "0A1B2C3D"}, "new_data": {"card_id": "XYZ"}})), Status::UnprocessableEntity, 8);
+    assert_eq!(body["violations"][0]["field"], "new_data.card_id");
+}
+
+#[test]
+fn delete_student_removes_the_match() {
+    let (client, ids) = with_students();
+    let delete = |path: &str, body: Value| client.delete(path.to_string()).header(admin()).header(ContentType::JSON).body(body.to_string()).dispatch();
+
+    assert_error(delete("/student", json!({})), Status::BadRequest, 1);
+    // Only what a student can be searched by picks them
+    assert_error(delete("/student", json!({"version": 1})), Status::BadRequest, 1);
+    assert_error(delete("/student", json!({"token": "a guess"})), Status::BadRequest, 1);
+    assert_error(delete("/student", json!({"class_id": {"$oid": CLASS_A}})), Status::PreconditionRequired, 7);
+
+    let response = delete("/student", json!({"card_id": "0A1B2C3F"}));
+    assert_eq!(json(response)["deletedCount"], 1);
+    assert_error(client.get(format!("/student/{}", ids[2])).dispatch(), Status::NotFound, 5);
+
+    let response = delete("/student?confirm=true", json!({"class_id": {"$oid": CLASS_A}}));
+    assert_eq!(json(response)["deletedCount"], 2);
+    assert_eq!(json(client.get("/student").dispatch())["total"], 0);
+}
+
+#[test]
+fn get_student_by_id_answers_with_an_etag() {
+    let (client, ids) = with_students();
+
+    let response = client.get(format!("/student/{}", ids[0])).dispatch();
+    assert_eq!(response.status(), Status::Ok);
+    assert_eq!(response.headers().get_one("ETag"), Some("\"1\""));
+    assert_eq!(json(response)["name"], "Budi Santoso");
+}
+
+#[test]
+fn get_student_by_id_refuses_unknown_and_malformed_ids() {
+    let (client, _) = with_students();
+    assert_error(client.get(format!("/student/{}", UNKNOWN_ID)).dispatch(), Status::NotFound, 5);
+    assert_error(client.get("/student/not-an-id").dispatch(), Status::BadRequest, 1);
+}
+
+#[test]
+fn put_student_by_id_replaces_it() {
+    let (client, ids) = with_students();
+    let path = format!("/student/{}", ids[0]);
+    let tag = etag(&client, &path);
+
+    let response = client.put(path.clone()).header(admin())
+        .header(ContentType::JSON)
+        .header(Header::new("If-Match", tag.clone()))
+        .body(json!({"name": "Budi S."}).to_string())
+        .dispatch();
+    assert_eq!(response.status(), Status::Ok);
+    assert_eq!(response.headers().get_one("ETag"), Some("\"2\""));
+    let student = json(response);
+    assert_eq!(student["name"], "Budi S.");
+    // Fields left out of the body are gone
+    assert_eq!(student.get("card_id"), None);
+
+    // The ETag read before the change is stale now
+    let response = client.put(path).header(admin()).header(ContentType::JSON).header(Header::new("If-Match", tag)).body(json!({"name": "Budi"}).to_string()).dispatch();
+    assert_error(response, Status::PreconditionFailed, 11);
+}
+
+#[test]
+fn put_student_by_id_refuses_bad_requests() {
+    let (client, ids) = with_students();
+    let put = |id: &str, if_match: Option<&str>, body: Value| {
+        let mut request = client.put(format!("/student/{}", id)).header(admin()).header(ContentType::JSON).body(body.to_string());
+        if let Some(if_match) = if_match {
+            request.add_header(Header::new("If-Match", if_match.to_string()));
+        }
+        request.dispatch()
+    };
+
+    assert_error(put(&ids[0], None, json!({"name": "Budi"})), Status::PreconditionRequired, 12);
+    assert_error(put(&ids[0], Some("*"), json!({"card_id": "0A1B2C3D"})), Status::UnprocessableEntity, 8);
+    assert_error(put(UNKNOWN_ID, Some("*"), json!({"name": "Budi"})), Status::NotFound, 5);
+    assert_error(put("not-an-id", Some("*"), json!({"name": "Budi"})), Status::BadRequest, 1);
+}
+
+#[test]
+fn patch_student_by_id_merges_it() {
+    let (client, ids) = with_students();
+    let path = format!("/student/{}", ids[1]);
+    let tag = etag(&client, &path);
+
+    let response = client.patch(path.clone()).header(admin())
+        .header(ContentType::JSON)
+        .header(Header::new("If-Match", tag.clone()))
+        .body(json!({"name": "Siti R.", "card_id": null}).to_string())
+        .dispatch();
+    assert_eq!(response.status(), Status::Ok);
+    let student = json(response);
+    assert_eq!(student["name"], "Siti R.");
+    assert_eq!(student.get("card_id"), None);
+    assert_eq!(oid(&student["class_id"]), CLASS_A);
+
+    let response = client.patch(path).header(admin()).header(ContentType::JSON).header(Header::new("If-Match", tag)).body(json!({"name": "Siti"}).to_string()).dispatch();
+    assert_error(response, Status::PreconditionFailed, 11);
+}
+
+#[test]
+fn patch_student_by_id_refuses_bad_requests() {
+    let (client, ids) = with_students();
+    let patch = |id: &str, if_match: Option<&str>, body: Value| {
+        let mut request = client.patch(format!("/student/{}", id)).header(admin()).header(ContentType::JSON).body(body.to_string());
+        if let Some(if_match) = if_match {
+            request.add_header(Header::new("If-Match", if_match.to_string()));
+        }
+        request.dispatch()
+    };
+
+    assert_error(patch(&ids[1], None, json!({"name": "Siti"})), Status::PreconditionRequired, 12);
+    assert_error(patch(&ids[1], Some("*"), json!({"name": null})), Status::UnprocessableEntity, 8);
+    assert_error(patch(&ids[1], Some("*"), json!({"age": 12})), Status::UnprocessableEntity, 8);
+    assert_error(patch(&ids[1], Some("soon"), json!({"name": "Siti"})), Status::BadRequest, 1);
+    assert_error(patch(UNKNOWN_ID, Some("*"), json!({"name": "Siti"})), Status::NotFound, 5);
+    assert_error(patch("not-an-id", Some("*"), json!({"name": "Siti"})), Status::BadRequest, 1);
+}
+
+#[test]
+fn delete_student_by_id_and_restore_it() {
+    let (client, ids) = with_students();
+    let path = format!("/student/{}", ids[0]);
+
+    let response = client.delete(path.clone()).header(admin()).dispatch();
+    assert_eq!(response.status(), Status::Ok);
+    assert_eq!(json(response)["deletedCount"], 1);
+    assert_error(client.get(path.clone()).dispatch(), Status::NotFound, 5);
+    assert_eq!(json(client.get("/student").dispatch())["total"], 2);
+
+    let response = client.post(format!("{}/restore", path)).header(admin()).dispatch();
+    assert_eq!(response.status(), Status::Ok);
+    assert_eq!(json(response)["name"], "Budi Santoso");
+    assert_eq!(client.get(path.clone()).dispatch().status(), Status::Ok);
+
+    // It isn't deleted any more
+    assert_error(client.post(format!("{}/restore", path)).header(admin()).dispatch(), Status::NotFound, 5);
+}
+
+#[test]
+fn student_writes_need_staff() {
+    let (client, ids) = with_students();
+    let path = format!("/student/{}", ids[0]);
+    let student = json!({"name": "Budi", "class_id": {"$oid": CLASS_A}}).to_string();
+    let any_version = Header::new("If-Match", "*");
+
+    assert_error(client.post("/student").header(ContentType::JSON).body(student.clone()).dispatch(), Status::Unauthorized, 9);
+    let body = json!({"params": {"name": "Budi Santoso"}, "new_data": {"name": "Budi"}}).to_string();
+    assert_error(client.put("/student").header(any_version.clone()).header(ContentType::JSON).body(body).dispatch(), Status::Unauthorized, 9);
+    assert_error(client.put(path.clone()).header(any_version.clone()).header(ContentType::JSON).body(student.clone()).dispatch(), Status::Unauthorized, 9);
+    assert_error(client.patch(path.clone()).header(any_version).header(ContentType::JSON).body(student.clone()).dispatch(), Status::Unauthorized, 9);
+    assert_error(client.delete(path.clone()).dispatch(), Status::Unauthorized, 9);
+    let body = json!({"card_id": "0A1B2C3D"}).to_string();
+    assert_error(client.delete("/student").header(ContentType::JSON).body(body).dispatch(), Status::Unauthorized, 9);
+    assert_eq!(client.delete(path.clone()).header(admin()).dispatch().status(), Status::Ok);
+    assert_error(client.post(format!("{}/restore", path)).dispatch(), Status::Unauthorized, 9);
+
+    // Teachers look after their students too
+    create(&client, "/teacher", json!({"name": "Pak Hadi", "pass": "papan-tulis"}));
+    let response = client.post("/student").header(teacher("Pak Hadi", "papan-tulis")).header(ContentType::JSON).body(student).dispatch();
+    assert_eq!(response.status(), Status::Ok);
+}
+
+#[test]
+fn delete_student_by_id_refuses_unknown_and_malformed_ids() {
+    let (client, _) = with_students();
+    assert_error(client.delete(format!("/student/{}", UNKNOWN_ID)).header(admin()).dispatch(), Status::NotFound, 5);
+    assert_error(client.delete("/student/not-an-id").header(admin()).dispatch(), Status::BadRequest, 1);
+}
+
+#[test]
+fn restore_student_refuses_unknown_and_malformed_ids() {
+    let (client, _) = with_students();
+    assert_error(client.post(format!("/student/{}/restore", UNKNOWN_ID)).header(admin()).dispatch(), Status::NotFound, 5);
+    assert_error(client.post("/student/not-an-id/restore").header(admin()).dispatch(), Status::BadRequest, 1);
+}
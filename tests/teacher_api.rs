@@ -0,0 +1,341 @@
+mod common;
+
+use rocket::http::{ContentType, Header, Status};
+use rocket::local::blocking::Client;
+use serde_json::{json, Value};
+use common::{admin, assert_error, client, create, etag, json, oid, teacher, UNKNOWN_ID};
+
+fn create_teacher(client: &Client, name: &str, pass: &str) -> String {
+    create(client, "/teacher", json!({"name": name, "pass": pass}))
+}
+
+/// Three teachers, two of them sharing a pass
+fn with_teachers() -> (Client, Vec<String>) {
+    let client = client();
+    let ids = vec![
+        create_teacher(&client, "Pak Hadi", "papan-tulis"),
+        create_teacher(&client, "Bu Dewi", "papan-tulis"),
+        create_teacher(&client, "Bu Wulan", "kapur-putih"),
+    ];
+    (client, ids)
+}
+
+/// Whether a teacher can sign in with `name` and `pass`
+fn signs_in(client: &Client, name: &str, pass: &str) -> bool {
+    let response = client.get("/checkin/qr?class_id=65a0000000000000000000a1&lesson=Math").header(teacher(name, pass)).dispatch();
+    response.status() == Status::Ok
+}
+
+fn names(body: &Value) -> Vec<&str> {
+    body["items"].as_array().expect("a page has items").iter().map(|item| item["name"].as_str().unwrap_or_default()).collect()
+}
+
+#[test]
+fn post_teacher_stores_it() {
+    let client = client();
+    let id = create_teacher(&client, " Pak Hadi ", "papan-tulis");
+
+    let response = client.get(format!("/teacher/{}", id)).dispatch();
+    assert_eq!(response.status(), Status::Ok);
+    let teacher = json(response);
+    assert_eq!(oid(&teacher["_id"]), id);
+    assert_eq!(teacher["name"], "Pak Hadi");
+}
+
+#[test]
+fn teacher_passes_are_never_answered_nor_searched() {
+    let (client, ids) = with_teachers();
+
+    assert!(json(client.get(format!("/teacher/{}", ids[0])).dispatch()).get("pass").is_none());
+    assert!(json(client.get("/teacher").dispatch())["items"][0].get("pass").is_none());
+    assert_error(client.get("/teacher?fields=pass").dispatch(), Status::BadRequest, 1);
+    // Left out like any parameter the route doesn't know, which leaves nothing to search by
+    assert_error(client.get("/teacher/search?pass=papan-tulis").dispatch(), Status::BadRequest, 1);
+
+    assert!(signs_in(&client, "Pak Hadi", "papan-tulis"));
+    assert!(!signs_in(&client, "Pak Hadi", "kapur-putih"));
+}
+
+#[test]
+fn post_teacher_refuses_invalid_fields() {
+    let client = client();
+    let response = client.post("/teacher").header(admin()).header(ContentType::JSON).body(json!({"name": "Pak Hadi", "pass": "short"}).to_string()).dispatch();
+    let body = assert_error(response, Status::UnprocessableEntity, 8);
+    assert_eq!(body["violations"][0]["field"], "pass");
+}
+
+#[test]
+fn get_all_teachers_pages() {
+    let (client, _) = with_teachers();
+
+    let first = json(client.get("/teacher?limit=2&sort=-name").dispatch());
+    assert_eq!(first["total"], 3);
+    assert_eq!(names(&first), ["Pak Hadi", "Bu Wulan"]);
+
+    let second = json(client.get("/teacher?limit=2&sort=-name&page=2").dispatch());
+    assert_eq!(names(&second), ["Bu Dewi"]);
+    assert_eq!(second["next_cursor"], Value::Null);
+}
+
+#[test]
+fn get_all_teachers_refuses_bad_list_params() {
+    let (client, _) = with_teachers();
+    assert_error(client.get("/teacher?limit=501").dispatch(), Status::BadRequest, 1);
+    assert_error(client.get("/teacher?page=0").dispatch(), Status::BadRequest, 1);
+    assert_error(client.get("/teacher?page=18446744073709551615").dispatch(), Status::BadRequest, 1);
+    assert_error(client.get("/teacher?page=9223372036854775809&limit=1").dispatch(), Status::BadRequest, 1);
+    assert_error(client.get("/teacher?fields=card_id").dispatch(), Status::BadRequest, 1);
+}
+
+#[test]
+fn get_teacher_searches_by_field() {
+    let (client, ids) = with_teachers();
+
+    let response = client.get("/teacher/search?name=Bu%20Dewi").dispatch();
+    assert_eq!(response.status(), Status::Ok);
+    assert!(response.headers().get_one("ETag").is_some());
+    assert_eq!(oid(&json(response)["_id"]), ids[1]);
+
+    let teacher = json(client.get(format!("/teacher/search?_id={}", ids[2])).dispatch());
+    assert_eq!(teacher["name"], "Bu Wulan");
+}
+
+#[test]
+fn get_teacher_needs_a_param() {
+    let (client, _) = with_teachers();
+    assert_error(client.get("/teacher/search").dispatch(), Status::BadRequest, 1);
+    // A malformed id is left out, which leaves nothing to search by
+    assert_error(client.get("/teacher/search?_id=not-an-id").dispatch(), Status::BadRequest, 1);
+}
+
+#[test]
+fn get_teacher_answers_not_found() {
+    let (client, _) = with_teachers();
+    assert_error(client.get("/teacher/search?name=Bu%20Ratna").dispatch(), Status::NotFound, 5);
+}
+
+#[test]
+fn put_teacher_updates_the_match() {
+    let (client, ids) = with_teachers();
+    let body = json!({"params": {"name": "Bu Wulan"}, "new_data": {"pass": "spidol-biru"}});
+
+    let response = client.put("/teacher").header(admin()).header(Header::new("If-Match", "\"1\"")).header(ContentType::JSON).body(body.to_string()).dispatch();
+    assert_eq!(response.status(), Status::Ok);
+    let result = json(response);
+    assert_eq!(result["matchedCount"], 1);
+    assert_eq!(result["modifiedCount"], 1);
+
+    assert!(signs_in(&client, "Bu Wulan", "spidol-biru"));
+    assert!(!signs_in(&client, "Bu Wulan", "kapur-putih"));
+    assert_eq!(oid(&json(client.get("/teacher/search?name=Bu%20Wulan").dispatch())["_id"]), ids[2]);
+}
+
+#[test]
+fn put_teacher_needs_confirm_for_several() {
+    let (client, _) = with_teachers();
+    create_teacher(&client, "Pak Hadi", "penghapus");
+    let body = json!({"params": {"name": "Pak Hadi"}, "new_data": {"pass": "spidol-biru"}});
+
+    let response = client.put("/teacher").header(admin()).header(Header::new("If-Match", "\"1\"")).header(ContentType::JSON).body(body.to_string()).dispatch();
+    assert_error(response, Status::PreconditionRequired, 7);
+
+    let response = client.put("/teacher?confirm=true").header(admin()).header(Header::new("If-Match", "\"1\"")).header(ContentType::JSON).body(body.to_string()).dispatch();
+    let result = json(response);
+    assert_eq!(result["matchedCount"], 2);
+    assert_eq!(result["modifiedCount"], 2);
+}
+
+#[test]
+fn put_teacher_refuses_bad_params() {
+    let (client, _) = with_teachers();
+    let put = |body: Value| client.put("/teacher?confirm=true").header(admin()).header(Header::new("If-Match", "\"1\"")).header(ContentType::JSON).body(body.to_string()).dispatch();
+
+    assert_error(put(json!({"params": {}, "new_data": {"name": "Nobody"}})), Status::BadRequest, 1);
+    assert_error(put(json!({"params": {"class_id": "65a0000000000000000000a1"}, "new_data": {"name": "Nobody"}})), Status::BadRequest, 1);
+    assert_error(put(json!({"params": {"pass": "papan-tulis"}, "new_data": {"name": "Nobody"}})), Status::BadRequest, 1);
+    assert_error(put(json!({"params": {"version": "1"}, "new_data": {"name": "Nobody"}})), Status::BadRequest, 1);
+    assert_error(put(json!({"params": {"id": "not-an-id"}, "new_data": {"name": "Nobody"}})), Status::BadRequest, 1);
+    let body = assert_error(put(json!({"params": {"name": "Pak Hadi"}, "new_data": {"pass": "short"}})), Status::UnprocessableEntity, 8);
+    assert_eq!(body["violations"][0]["field"], "new_data.pass");
+}
+
+#[test]
+fn delete_teacher_removes_the_match() {
+    let (client, ids) = with_teachers();
+    let delete = |path: &str, body: Value| client.delete(path.to_string()).header(admin()).header(ContentType::JSON).body(body.to_string()).dispatch();
+
+    assert_error(delete("/teacher", json!({})), Status::BadRequest, 1);
+    assert_error(delete("/teacher", json!({"pass": "papan-tulis"})), Status::BadRequest, 1);
+    create_teacher(&client, "Pak Hadi", "penghapus");
+    assert_error(delete("/teacher", json!({"name": "Pak Hadi"})), Status::PreconditionRequired, 7);
+
+    let response = delete("/teacher", json!({"name": "Bu Wulan"}));
+    assert_eq!(json(response)["deletedCount"], 1);
+    assert_error(client.get(format!("/teacher/{}", ids[2])).dispatch(), Status::NotFound, 5);
+
+    let response = delete("/teacher?confirm=true", json!({"name": "Pak Hadi"}));
+    assert_eq!(json(response)["deletedCount"], 2);
+    assert_eq!(json(client.get("/teacher").dispatch())["total"], 1);
+}
+
+#[test]
+fn get_teacher_by_id_answers_with_an_etag() {
+    let (client, ids) = with_teachers();
+
+    let response = client.get(format!("/teacher/{}", ids[0])).dispatch();
+    assert_eq!(response.status(), Status::Ok);
+    assert_eq!(response.headers().get_one("ETag"), Some("\"1\""));
+    assert_eq!(json(response)["name"], "Pak Hadi");
+}
+
+#[test]
+fn get_teacher_by_id_refuses_unknown_and_malformed_ids() {
+    let (client, _) = with_teachers();
+    assert_error(client.get(format!("/teacher/{}", UNKNOWN_ID)).dispatch(), Status::NotFound, 5);
+    assert_error(client.get("/teacher/not-an-id").dispatch(), Status::BadRequest, 1);
+}
+
+#[test]
+fn put_teacher_by_id_replaces_it() {
+    let (client, ids) = with_teachers();
+    let path = format!("/teacher/{}", ids[0]);
+    let tag = etag(&client, &path);
+
+    let response = client.put(path.clone()).header(admin())
+        .header(ContentType::JSON)
+        .header(Header::new("If-Match", tag.clone()))
+        .body(json!({"name": "Pak Hadi Susanto", "pass": "spidol-biru"}).to_string())
+        .dispatch();
+    assert_eq!(response.status(), Status::Ok);
+    assert_eq!(response.headers().get_one("ETag"), Some("\"2\""));
+    assert_eq!(json(response)["name"], "Pak Hadi Susanto");
+
+    // The ETag read before the change is stale now
+    let response = client.put(path).header(admin())
+        .header(ContentType::JSON)
+        .header(Header::new("If-Match", tag))
+        .body(json!({"name": "Pak Hadi", "pass": "papan-tulis"}).to_string())
+        .dispatch();
+    assert_error(response, Status::PreconditionFailed, 11);
+}
+
+#[test]
+fn put_teacher_by_id_refuses_bad_requests() {
+    let (client, ids) = with_teachers();
+    let put = |id: &str, if_match: Option<&str>, body: Value| {
+        let mut request = client.put(format!("/teacher/{}", id)).header(admin()).header(ContentType::JSON).body(body.to_string());
+        if let Some(if_match) = if_match {
+            request.add_header(Header::new("If-Match", if_match.to_string()));
+        }
+        request.dispatch()
+    };
+    let teacher = json!({"name": "Pak Hadi", "pass": "papan-tulis"});
+
+    assert_error(put(&ids[0], None, teacher.clone()), Status::PreconditionRequired, 12);
+    assert_error(put(&ids[0], Some("*"), json!({"name": "Pak Hadi"})), Status::UnprocessableEntity, 8);
+    assert_error(put(UNKNOWN_ID, Some("*"), teacher.clone()), Status::NotFound, 5);
+    assert_error(put("not-an-id", Some("*"), teacher), Status::BadRequest, 1);
+}
+
+#[test]
+fn patch_teacher_by_id_merges_it() {
+    let (client, ids) = with_teachers();
+    let path = format!("/teacher/{}", ids[1]);
+    let tag = etag(&client, &path);
+
+    let response = client.patch(path.clone()).header(admin())
+        .header(ContentType::JSON)
+        .header(Header::new("If-Match", tag.clone()))
+        .body(json!({"pass": "spidol-biru"}).to_string())
+        .dispatch();
+    assert_eq!(response.status(), Status::Ok);
+    assert_eq!(json(response)["name"], "Bu Dewi");
+    assert!(signs_in(&client, "Bu Dewi", "spidol-biru"));
+    assert!(!signs_in(&client, "Bu Dewi", "papan-tulis"));
+
+    let response = client.patch(path).header(admin()).header(ContentType::JSON).header(Header::new("If-Match", tag)).body(json!({"name": "Bu Dewi S."}).to_string()).dispatch();
+    assert_error(response, Status::PreconditionFailed, 11);
+}
+
+#[test]
+fn patch_teacher_by_id_refuses_bad_requests() {
+    let (client, ids) = with_teachers();
+    let patch = |id: &str, if_match: Option<&str>, body: Value| {
+        let mut request = client.patch(format!("/teacher/{}", id)).header(admin()).header(ContentType::JSON).body(body.to_string());
+        if let Some(if_match) = if_match {
+            request.add_header(Header::new("If-Match", if_match.to_string()));
+        }
+        request.dispatch()
+    };
+
+    assert_error(patch(&ids[1], None, json!({"name": "Bu Dewi"})), Status::PreconditionRequired, 12);
+    assert_error(patch(&ids[1], Some("*"), json!({"pass": null})), Status::UnprocessableEntity, 8);
+    assert_error(patch(&ids[1], Some("*"), json!({"deleted_at": null})), Status::UnprocessableEntity, 8);
+    assert_error(patch(&ids[1], Some("soon"), json!({"name": "Bu Dewi"})), Status::BadRequest, 1);
+    assert_error(patch(UNKNOWN_ID, Some("*"), json!({"name": "Bu Dewi"})), Status::NotFound, 5);
+    assert_error(patch("not-an-id", Some("*"), json!({"name": "Bu Dewi"})), Status::BadRequest, 1);
+}
+
+#[test]
+fn delete_teacher_by_id_and_restore_it() {
+    let (client, ids) = with_teachers();
+    let path = format!("/teacher/{}", ids[0]);
+
+    let response = client.delete(path.clone()).header(admin()).dispatch();
+    assert_eq!(response.status(), Status::Ok);
+    assert_eq!(json(response)["deletedCount"], 1);
+    assert_error(client.get(path.clone()).dispatch(), Status::NotFound, 5);
+    assert_error(client.get("/teacher/search?name=Pak%20Hadi").dispatch(), Status::NotFound, 5);
+
+    let response = client.post(format!("{}/restore", path)).header(admin()).dispatch();
+    assert_eq!(response.status(), Status::Ok);
+    assert_eq!(json(response)["name"], "Pak Hadi");
+    assert_eq!(client.get(path.clone()).dispatch().status(), Status::Ok);
+
+    // It isn't deleted any more
+    assert_error(client.post(format!("{}/restore", path)).header(admin()).dispatch(), Status::NotFound, 5);
+}
+
+#[test]
+fn teacher_writes_need_an_admin() {
+    let (client, ids) = with_teachers();
+    let path = format!("/teacher/{}", ids[0]);
+    let body = json!({"name": "Pak Hadi", "pass": "spidol-biru"}).to_string();
+    let any_version = Header::new("If-Match", "*");
+    let dewi = teacher("Bu Dewi", "papan-tulis");
+
+    assert_error(client.post("/teacher").header(ContentType::JSON).body(body.clone()).dispatch(), Status::Unauthorized, 9);
+    assert_error(client.post("/teacher").header(dewi.clone()).header(ContentType::JSON).body(body.clone()).dispatch(), Status::Forbidden, 10);
+    let bulk = json!({"params": {"name": "Pak Hadi"}, "new_data": {"pass": "spidol-biru"}}).to_string();
+    assert_error(client.put("/teacher").header(any_version.clone()).header(ContentType::JSON).body(bulk.clone()).dispatch(), Status::Unauthorized, 9);
+    assert_error(client.put("/teacher").header(dewi.clone()).header(any_version.clone()).header(ContentType::JSON).body(bulk).dispatch(), Status::Forbidden, 10);
+    assert_error(client.put(path.clone()).header(any_version.clone()).header(ContentType::JSON).body(body.clone()).dispatch(), Status::Unauthorized, 9);
+    assert_error(client.patch(path.clone()).header(any_version.clone()).header(ContentType::JSON).body(body.clone()).dispatch(), Status::Unauthorized, 9);
+    assert_error(client.delete(path.clone()).dispatch(), Status::Unauthorized, 9);
+    assert_error(client.delete(path.clone()).header(dewi.clone()).dispatch(), Status::Forbidden, 10);
+    assert_error(client.post(format!("{}/restore", path)).dispatch(), Status::Unauthorized, 9);
+    assert_error(client.post("/admin/purge").header(dewi.clone()).dispatch(), Status::Forbidden, 10);
+    assert!(signs_in(&client, "Pak Hadi", "papan-tulis"));
+
+    // A teacher changes only their own pass
+    assert_error(client.patch(path).header(dewi.clone()).header(any_version.clone()).header(ContentType::JSON).body(body.clone()).dispatch(), Status::Forbidden, 10);
+    assert!(signs_in(&client, "Pak Hadi", "papan-tulis"));
+    let own = json!({"pass": "spidol-merah"}).to_string();
+    let response = client.patch(format!("/teacher/{}", ids[1])).header(dewi).header(any_version).header(ContentType::JSON).body(own).dispatch();
+    assert_eq!(response.status(), Status::Ok);
+    assert!(signs_in(&client, "Bu Dewi", "spidol-merah"));
+}
+
+#[test]
+fn delete_teacher_by_id_refuses_unknown_and_malformed_ids() {
+    let (client, _) = with_teachers();
+    assert_error(client.delete(format!("/teacher/{}", UNKNOWN_ID)).header(admin()).dispatch(), Status::NotFound, 5);
+    assert_error(client.delete("/teacher/not-an-id").header(admin()).dispatch(), Status::BadRequest, 1);
+}
+
+#[test]
+fn restore_teacher_refuses_unknown_and_malformed_ids() {
+    let (client, _) = with_teachers();
+    assert_error(client.post(format!("/teacher/{}/restore", UNKNOWN_ID)).header(admin()).dispatch(), Status::NotFound, 5);
+    assert_error(client.post("/teacher/not-an-id/restore").header(admin()).dispatch(), Status::BadRequest, 1);
+}
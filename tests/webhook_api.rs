@@ -0,0 +1,344 @@
+mod common;
+
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::TcpListener;
+use std::sync::mpsc::{self, Receiver};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use hmac::{Hmac, Mac};
+use rocket::http::{ContentType, Header, Status};
+use rocket::local::blocking::Client;
+use serde_json::{json, Value};
+use sha2::Sha256;
+use common::{admin, assert_error, check_in, client, create, etag, json, oid, UNKNOWN_ID};
+
+const CLASS_A: &str = "65a0000000000000000000a1";
+const SECRET: &str = "a webhook secret of some length";
+
+/// A request the mock receiver got, with its header names lowercased
+struct Received {
+    headers: HashMap<String, String>,
+    body: String,
+}
+
+/// A local HTTP server answering its requests with `statuses`, one each and in order,
+/// then stopping. Answers its URL and what it receives.
+fn mock_receiver(statuses: Vec<u16>) -> (String, Receiver<Received>) {
+    let listener = TcpListener::bind("127.0.0.1:0").expect("a local port is free");
+    let url = format!("http://{}/hooks", listener.local_addr().expect("the port is bound"));
+    let (sender, receiver) = mpsc::channel();
+
+    thread::spawn(move || {
+        for status in statuses {
+            let (mut stream, _) = match listener.accept() {
+                Ok(connection) => connection,
+                Err(_) => return
+            };
+            let mut reader = BufReader::new(stream.try_clone().expect("the stream can be cloned"));
+            let mut headers = HashMap::new();
+            let mut line = String::new();
+            // The request line, then headers until the empty line
+            reader.read_line(&mut line).ok();
+            loop {
+                line.clear();
+                reader.read_line(&mut line).ok();
+                match line.trim_end().split_once(':') {
+                    Some((name, value)) => headers.insert(name.trim().to_lowercase(), value.trim().to_string()),
+                    None => break
+                };
+            }
+            let length = headers.get("content-length").and_then(|length| length.parse::<usize>().ok()).unwrap_or(0);
+            let mut body = vec![0; length];
+            reader.read_exact(&mut body).ok();
+
+            let answer = format!("HTTP/1.1 {} Test\r\nContent-Length: 0\r\nConnection: close\r\n\r\n", status);
+            stream.write_all(answer.as_bytes()).ok();
+            sender.send(Received { headers, body: String::from_utf8_lossy(&body).to_string() }).ok();
+        }
+    });
+    (url, receiver)
+}
+
+/// A local HTTP server answering every request with `status` after `delay`, many at once.
+/// Answers its URL and a message for each connection it takes.
+fn slow_receiver(delay: Duration, status: u16) -> (String, Receiver<()>) {
+    let listener = TcpListener::bind("127.0.0.1:0").expect("a local port is free");
+    let url = format!("http://{}/hooks", listener.local_addr().expect("the port is bound"));
+    let (sender, receiver) = mpsc::channel();
+
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let Ok(mut stream) = stream else { return };
+            if sender.send(()).is_err() {
+                return;
+            }
+            thread::spawn(move || {
+                thread::sleep(delay);
+                let answer = format!("HTTP/1.1 {} Test\r\nContent-Length: 0\r\nConnection: close\r\n\r\n", status);
+                stream.write_all(answer.as_bytes()).ok();
+            });
+        }
+    });
+    (url, receiver)
+}
+
+fn create_webhook(client: &Client, url: &str, events: &[&str]) -> String {
+    let response = client.post("/webhook").header(admin()).header(ContentType::JSON)
+        .body(json!({"url": url, "events": events, "secret": SECRET}).to_string()).dispatch();
+    assert_eq!(response.status(), Status::Ok);
+    oid(&json(response)["insertedId"])
+}
+
+fn deliveries(client: &Client, webhook_id: &str) -> Vec<Value> {
+    let response = client.get(format!("/webhook/{}/deliveries", webhook_id)).header(admin()).dispatch();
+    assert_eq!(response.status(), Status::Ok);
+    json(response)["items"].as_array().expect("a page has items").clone()
+}
+
+/// The deliveries of `webhook_id` once `done` holds for them, the worker makes them in the background
+fn deliveries_when(client: &Client, webhook_id: &str, done: impl Fn(&[Value]) -> bool) -> Vec<Value> {
+    let deadline = Instant::now() + Duration::from_secs(10);
+    loop {
+        let deliveries = deliveries(client, webhook_id);
+        if done(&deliveries) || Instant::now() > deadline {
+            return deliveries;
+        }
+        thread::sleep(Duration::from_millis(50));
+    }
+}
+
+fn verify_signature(received: &Received) {
+    let signature = received.headers.get("x-presency-signature").expect("deliveries are signed");
+    let (timestamp, signature) = signature.strip_prefix("t=").and_then(|rest| rest.split_once(",v1=")).expect("the signature has a timestamp");
+
+    let mut mac = Hmac::<Sha256>::new_from_slice(SECRET.as_bytes()).expect("HMAC accepts keys of any length");
+    mac.update(format!("{}.{}", timestamp, received.body).as_bytes());
+    let expected: String = mac.finalize().into_bytes().iter().map(|byte| format!("{:02x}", byte)).collect();
+    assert_eq!(signature, expected);
+}
+
+#[test]
+fn webhooks_are_admin_only() {
+    let client = client();
+    let body = json!({"url": "http://localhost/hooks", "events": ["attendance.late"], "secret": SECRET}).to_string();
+    assert_error(client.post("/webhook").header(ContentType::JSON).body(body).dispatch(), Status::Unauthorized, 9);
+    assert_error(client.get("/webhook").dispatch(), Status::Unauthorized, 9);
+    assert_error(client.post(format!("/webhook/{}/test", UNKNOWN_ID)).dispatch(), Status::Unauthorized, 9);
+}
+
+#[test]
+fn post_webhook_refuses_invalid_fields() {
+    let client = client();
+    for body in [
+        json!({"url": "ftp://localhost/hooks", "events": ["attendance.late"], "secret": SECRET}),
+        json!({"url": "http://localhost/hooks", "events": ["attendance.late"], "secret": "short"}),
+        json!({"url": "http://localhost/hooks", "events": ["attendance.unknown"], "secret": SECRET}),
+        json!({"url": "http://localhost/hooks", "events": [], "secret": SECRET}),
+        json!({"url": "http://localhost/hooks", "secret": SECRET}),
+    ] {
+        let response = client.post("/webhook").header(admin()).header(ContentType::JSON).body(body.to_string()).dispatch();
+        assert_error(response, Status::UnprocessableEntity, 8);
+    }
+}
+
+#[test]
+fn webhooks_never_answer_their_secret() {
+    let client = client();
+    let id = create_webhook(&client, "http://localhost/hooks", &["attendance.late"]);
+
+    let webhook = json(client.get(format!("/webhook/{}", id)).header(admin()).dispatch());
+    assert_eq!(webhook["url"], "http://localhost/hooks");
+    assert!(webhook.get("secret").is_none());
+    let page = json(client.get("/webhook").header(admin()).dispatch());
+    assert!(page["items"][0].get("secret").is_none());
+}
+
+#[test]
+fn patch_webhook_checks_events() {
+    let client = client();
+    let id = create_webhook(&client, "http://localhost/hooks", &["attendance.late"]);
+    let path = format!("/webhook/{}", id);
+
+    let response = client.patch(&path).header(admin()).header(Header::new("If-Match", etag(&client, &path)))
+        .header(ContentType::JSON).body(json!({"events": ["nope"]}).to_string()).dispatch();
+    assert_error(response, Status::UnprocessableEntity, 8);
+
+    let response = client.patch(&path).header(admin()).header(Header::new("If-Match", etag(&client, &path)))
+        .header(ContentType::JSON).body(json!({"events": ["attendance.late", "attendance.absent"]}).to_string()).dispatch();
+    assert_eq!(response.status(), Status::Ok);
+    assert_eq!(json(response)["events"], json!(["attendance.late", "attendance.absent"]));
+}
+
+#[test]
+fn test_fire_sends_a_signed_ping() {
+    let client = client();
+    let (url, received) = mock_receiver(vec![200]);
+    let id = create_webhook(&client, &url, &["attendance.late"]);
+
+    let response = client.post(format!("/webhook/{}/test", id)).header(admin()).dispatch();
+    assert_eq!(response.status(), Status::Ok);
+    let delivery = json(response);
+    assert_eq!(delivery["status"], "delivered");
+    assert_eq!(delivery["attempts"], 1);
+    assert_eq!(delivery["response_status"], 200);
+
+    let request = received.recv_timeout(Duration::from_secs(5)).expect("the ping arrived");
+    verify_signature(&request);
+    assert_eq!(request.headers["x-presency-event"], "ping");
+    assert_eq!(request.headers["x-presency-delivery"], oid(&delivery["_id"]));
+    let body: Value = serde_json::from_str(&request.body).expect("the body is JSON");
+    assert_eq!(body["event"], "ping");
+    assert_eq!(oid(&body["data"]["webhook_id"]), id);
+
+    let logged = deliveries(&client, &id);
+    assert_eq!(logged.len(), 1);
+    assert_eq!(logged[0]["status"], "delivered");
+}
+
+#[test]
+fn failed_test_fire_is_logged() {
+    let client = client();
+    let (url, _received) = mock_receiver(vec![500]);
+    let id = create_webhook(&client, &url, &["attendance.late"]);
+
+    let delivery = json(client.post(format!("/webhook/{}/test", id)).header(admin()).dispatch());
+    assert_eq!(delivery["status"], "failed");
+    assert_eq!(delivery["attempts"], 1);
+    assert_eq!(delivery["response_status"], 500);
+    assert!(delivery["error"].is_string());
+}
+
+#[test]
+fn a_slow_test_fire_is_only_sent_once() {
+    let client = client();
+    // Longer than the worker waits between looking for due deliveries
+    let (url, received) = slow_receiver(Duration::from_secs(6), 500);
+    let id = create_webhook(&client, &url, &["attendance.late"]);
+
+    let delivery = json(client.post(format!("/webhook/{}/test", id)).header(admin()).dispatch());
+    assert_eq!(delivery["status"], "failed");
+    assert_eq!(received.try_iter().count(), 1);
+
+    let logged = deliveries(&client, &id);
+    assert_eq!(logged.len(), 1);
+    assert_eq!(logged[0]["status"], "failed");
+    assert_eq!(logged[0]["attempts"], 1);
+}
+
+#[test]
+fn test_fire_refuses_unknown_and_deleted_webhooks() {
+    let client = client();
+    assert_error(client.post(format!("/webhook/{}/test", UNKNOWN_ID)).header(admin()).dispatch(), Status::NotFound, 5);
+    assert_error(client.post("/webhook/nope/test").header(admin()).dispatch(), Status::BadRequest, 1);
+
+    let id = create_webhook(&client, "http://localhost/hooks", &["attendance.late"]);
+    assert_eq!(client.delete(format!("/webhook/{}", id)).header(admin()).dispatch().status(), Status::Ok);
+    assert_error(client.post(format!("/webhook/{}/test", id)).header(admin()).dispatch(), Status::NotFound, 5);
+}
+
+#[test]
+fn failed_deliveries_are_retried_later() {
+    let client = client();
+    let (url, received) = mock_receiver(vec![503]);
+    let id = create_webhook(&client, &url, &["attendance.checked_in"]);
+    let student_id = create(&client, "/student", json!({"name": "Budi Santoso", "class_id": {"$oid": CLASS_A}}));
+
+    check_in(&client, &student_id, CLASS_A, "Math");
+    received.recv_timeout(Duration::from_secs(10)).expect("the check-in was sent");
+
+    let logged = deliveries_when(&client, &id, |deliveries| deliveries.first().is_some_and(|delivery| delivery["attempts"] == 1));
+    assert_eq!(logged.len(), 1);
+    assert_eq!(logged[0]["event"], "attendance.checked_in");
+    assert_eq!(logged[0]["status"], "pending");
+    assert_eq!(logged[0]["response_status"], 503);
+    let last_attempt = logged[0]["last_attempt_at"]["$date"]["$numberLong"].as_str().and_then(|at| at.parse::<i64>().ok()).expect("the attempt time is logged");
+    let next_attempt = logged[0]["next_attempt_at"]["$date"]["$numberLong"].as_str().and_then(|at| at.parse::<i64>().ok()).expect("the retry is scheduled");
+    assert_eq!(next_attempt - last_attempt, 30_000);
+}
+
+#[test]
+fn check_ins_are_logged_for_the_webhooks_as_theyre_stored() {
+    let client = client();
+    let (url, _received) = mock_receiver(vec![200, 200]);
+    let id = create_webhook(&client, &url, &["attendance.checked_in"]);
+    let first = create(&client, "/student", json!({"name": "Budi Santoso", "class_id": {"$oid": CLASS_A}}));
+    let second = create(&client, "/student", json!({"name": "Siti Rahma", "class_id": {"$oid": CLASS_A}}));
+
+    check_in(&client, &first, CLASS_A, "Math");
+    check_in(&client, &second, CLASS_A, "Math");
+    // Before the worker had a chance to see them
+    let logged = deliveries(&client, &id);
+    assert_eq!(logged.len(), 2);
+    assert!(logged.iter().all(|delivery| delivery["event"] == "attendance.checked_in"));
+}
+
+#[test]
+fn a_webhook_not_answering_doesnt_hold_back_the_others() {
+    let client = client();
+    // Takes the connection but never answers, so every attempt runs into the timeout
+    let silent = TcpListener::bind("127.0.0.1:0").expect("a local port is free");
+    create_webhook(&client, &format!("http://{}/hooks", silent.local_addr().expect("the port is bound")), &["attendance.checked_in"]);
+    let (url, received) = mock_receiver(vec![200]);
+    create_webhook(&client, &url, &["attendance.checked_in"]);
+    let student_id = create(&client, "/student", json!({"name": "Budi Santoso", "class_id": {"$oid": CLASS_A}}));
+
+    check_in(&client, &student_id, CLASS_A, "Math");
+    received.recv_timeout(Duration::from_secs(5)).expect("the check-in was sent without waiting for the silent webhook");
+}
+
+#[test]
+fn closing_a_lesson_sends_late_and_absent_events() {
+    let client = client();
+    let (url, received) = mock_receiver(vec![200, 200, 200]);
+    let id = create_webhook(&client, &url, &["attendance.checked_in", "attendance.late", "attendance.absent"]);
+    let late = create(&client, "/student", json!({"name": "Budi Santoso", "class_id": {"$oid": CLASS_A}}));
+    let absent = create(&client, "/student", json!({"name": "Siti Rahma", "class_id": {"$oid": CLASS_A}}));
+
+    check_in(&client, &late, CLASS_A, "Math");
+    // The lesson started an hour ago, so checking in now is late
+    let started_at = hour_ago();
+    let response = client.post("/lesson/close").header(admin()).header(ContentType::JSON)
+        .body(json!({"class_id": CLASS_A, "lesson": "Math", "started_at": started_at}).to_string()).dispatch();
+    assert_eq!(response.status(), Status::Ok);
+    assert_eq!(json(response), json!({"present": 0, "late": 1, "absent": 1, "deliveries": 2}));
+
+    let mut events: HashMap<String, Value> = HashMap::new();
+    for _ in 0..3 {
+        let request = received.recv_timeout(Duration::from_secs(10)).expect("every event was sent");
+        verify_signature(&request);
+        let body: Value = serde_json::from_str(&request.body).expect("the body is JSON");
+        events.insert(body["event"].as_str().unwrap_or_default().to_string(), body);
+    }
+    assert_eq!(oid(&events["attendance.checked_in"]["data"]["student_id"]), late);
+    assert_eq!(oid(&events["attendance.late"]["data"]["student_id"]), late);
+    assert!(events["attendance.late"]["data"]["minutes_late"].as_i64().is_some_and(|minutes| minutes >= 59));
+    assert_eq!(oid(&events["attendance.absent"]["data"]["student_id"]), absent);
+    assert_eq!(events["attendance.absent"]["data"]["student_name"], "Siti Rahma");
+
+    let logged = deliveries_when(&client, &id, |deliveries| deliveries.iter().all(|delivery| delivery["status"] == "delivered"));
+    assert_eq!(logged.len(), 3);
+}
+
+#[test]
+fn closing_a_lesson_needs_staff_and_valid_params() {
+    let client = client();
+    let body = json!({"class_id": CLASS_A, "lesson": "Math", "started_at": "2024-01-15T08:00:00Z"}).to_string();
+    assert_error(client.post("/lesson/close").header(ContentType::JSON).body(body).dispatch(), Status::Unauthorized, 9);
+
+    for body in [
+        json!({"class_id": "nope", "lesson": "Math", "started_at": "2024-01-15T08:00:00Z"}),
+        json!({"class_id": CLASS_A, "lesson": " ", "started_at": "2024-01-15T08:00:00Z"}),
+        json!({"class_id": CLASS_A, "lesson": "Math", "started_at": "yesterday"}),
+    ] {
+        let response = client.post("/lesson/close").header(admin()).header(ContentType::JSON).body(body.to_string()).dispatch();
+        assert_error(response, Status::BadRequest, 1);
+    }
+}
+
+/// An hour ago, in RFC 3339
+fn hour_ago() -> String {
+    let now = mongodb::bson::DateTime::now().timestamp_millis();
+    mongodb::bson::DateTime::from_millis(now - 60 * 60 * 1000).try_to_rfc3339_string().expect("the time can be written")
+}